@@ -1,21 +1,69 @@
 #![no_std]
 extern crate byteorder;
+extern crate ringbuf;
+#[cfg(feature = "loopback")]
+#[macro_use]
+extern crate std;
 
-use byteorder::{ByteOrder, NativeEndian};
+#[cfg(feature = "loopback")]
+mod loopback;
+mod transport;
+
+use byteorder::{ByteOrder, LittleEndian};
+use core::cmp::min;
 use core::mem::size_of;
 use core::ops::Deref;
-use core::slice;
+use core::time::Duration;
+use core::{str, u32};
+
+#[cfg(feature = "loopback")]
+pub use loopback::{loopback, Loopback};
+pub use transport::Transport;
 
 pub const BUFFER_SIZE: usize = 4 * 1024 * 1024;
 pub type CallId = u64;
+/// Id the ivshmem server gives a peer.
+pub type PeerId = u16;
+
+/// Id of ivshrpcd, the first peer to connect to the ivshmem server.
+pub const HOST_ID: PeerId = 0;
+/// Guests sharing one region, each with a slot of its own for its rings. The guest with id `n`
+/// takes slot `n - 1`.
+pub const MAX_GUESTS: usize = 4;
+pub const SLOT_SIZE: usize = BUFFER_SIZE / MAX_GUESTS;
+
+/// The slot of a guest, if there is room for it.
+pub fn guest_slot(id: PeerId) -> Option<usize> {
+    if id > HOST_ID && (id as usize) <= MAX_GUESTS {
+        Some(id as usize - 1)
+    } else {
+        None
+    }
+}
+
+/// Offset of a ring in the region. The host writes to the first half of a slot, the guest to the
+/// second.
+pub fn ring_offset(slot: usize, half: usize) -> usize {
+    slot * SLOT_SIZE + half * SLOT_SIZE / 2
+}
 
 pub const IVSHRPC_HEADER_SIZE: usize = size_of::<MsgHeader>();
 
+/// Version of the protocol, bumped with every change the other end would trip over.
+pub const PROTOCOL_VERSION: u16 = 1;
+/// A peer that acts on `MsgType::Cancel`, a bit of `Hello::features`.
+pub const FEATURE_CANCEL: u32 = 1 << 0;
+/// Size of a `Hello` without its name.
+pub const HELLO_SIZE: usize = 12;
+
 #[repr(packed)]
 pub struct MsgHeader {
     pub msgtype: u8,
     pub length: u32,
     pub callid: CallId,
+    /// Milliseconds the caller of a fuse waits for its result, 0 if it waits for as long as it
+    /// takes. Past that the caller has failed the call and cancels it.
+    pub timeout_ms: u32,
 }
 
 impl MsgHeader {
@@ -24,6 +72,31 @@ impl MsgHeader {
             msgtype: msgtype as u8,
             length: 0,
             callid,
+            timeout_ms: 0,
+        }
+    }
+    /// Sets the timeout, rounded up to a millisecond.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout_ms = match timeout {
+            Some(timeout) => {
+                let ms = timeout.as_secs() * 1000
+                    + (timeout.subsec_nanos() as u64 + 999_999) / 1_000_000;
+                if ms > u32::MAX as u64 {
+                    u32::MAX
+                } else if ms == 0 {
+                    1
+                } else {
+                    ms as u32
+                }
+            }
+            None => 0,
+        };
+        self
+    }
+    pub fn timeout(&self) -> Option<Duration> {
+        match self.timeout_ms {
+            0 => None,
+            ms => Some(Duration::from_millis(ms as u64)),
         }
     }
     #[inline]
@@ -31,17 +104,19 @@ impl MsgHeader {
         assert!(h.len() == size_of::<MsgHeader>());
         MsgHeader {
             msgtype: h[0],
-            length: NativeEndian::read_u32(&h[1..5]),
-            callid: NativeEndian::read_u64(&h[5..13]),
+            length: LittleEndian::read_u32(&h[1..5]),
+            callid: LittleEndian::read_u64(&h[5..13]),
+            timeout_ms: LittleEndian::read_u32(&h[13..17]),
         }
     }
-    pub fn to_slice(&self) -> &[u8] {
-        unsafe {
-            slice::from_raw_parts(
-                self as *const MsgHeader as *const u8,
-                size_of::<MsgHeader>(),
-            )
-        }
+    /// Wire representation of the header, integers are always little endian.
+    pub fn to_bytes(&self) -> [u8; IVSHRPC_HEADER_SIZE] {
+        let mut buf = [0; IVSHRPC_HEADER_SIZE];
+        buf[0] = self.msgtype;
+        LittleEndian::write_u32(&mut buf[1..5], self.length);
+        LittleEndian::write_u64(&mut buf[5..13], self.callid);
+        LittleEndian::write_u32(&mut buf[13..17], self.timeout_ms);
+        buf
     }
 }
 
@@ -51,6 +126,12 @@ pub enum MsgType {
     Fuse,
     Return,
     Error,
+    /// The caller of a fuse gave up on it, so the callee can drop it. Nothing is sent back.
+    Cancel,
+    /// Sent by a guest once its rings are set up, before anything else. Holds a `Hello`.
+    Hello,
+    /// Answers a `Hello` with the host's own.
+    HelloAck,
 }
 
 impl MsgType {
@@ -60,7 +141,80 @@ impl MsgType {
             1 => Some(MsgType::Fuse),
             2 => Some(MsgType::Return),
             3 => Some(MsgType::Error),
+            4 => Some(MsgType::Cancel),
+            5 => Some(MsgType::Hello),
+            6 => Some(MsgType::HelloAck),
             _ => None,
         }
     }
 }
+
+/// What a peer tells the other about itself in the handshake. Laid out by hand rather than in
+/// SOS, so that peers find out whether they agree on SOS before using it.
+#[derive(Debug, PartialEq)]
+pub struct Hello<'a> {
+    pub version: u16,
+    /// The `SOS_VERSION` the peer encodes payloads with.
+    pub sos_version: u8,
+    /// Largest frame the peer sends or takes, header included.
+    pub max_frame: u32,
+    pub features: u32,
+    pub name: &'a str,
+}
+
+/// What two peers settled on in the handshake.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Agreement {
+    /// Largest frame either of them takes.
+    pub max_frame: u32,
+    /// Features both of them have.
+    pub features: u32,
+}
+
+/// Why two peers can't talk, holding what the other peer has.
+#[derive(Debug, PartialEq)]
+pub enum Mismatch {
+    Version(u16),
+    SosVersion(u8),
+}
+
+impl<'a> Hello<'a> {
+    pub fn encoded_len(&self) -> usize {
+        HELLO_SIZE + self.name.len()
+    }
+    /// Writes the hello to `buf`, which is `encoded_len` bytes long.
+    pub fn encode(&self, buf: &mut [u8]) {
+        LittleEndian::write_u16(&mut buf[0..2], self.version);
+        buf[2] = self.sos_version;
+        buf[3] = 0;
+        LittleEndian::write_u32(&mut buf[4..8], self.max_frame);
+        LittleEndian::write_u32(&mut buf[8..12], self.features);
+        buf[HELLO_SIZE..].copy_from_slice(self.name.as_bytes());
+    }
+    /// `None` if `buf` is too short for a hello or the name is not UTF-8.
+    pub fn decode(buf: &'a [u8]) -> Option<Self> {
+        if buf.len() < HELLO_SIZE {
+            return None;
+        }
+        Some(Hello {
+            version: LittleEndian::read_u16(&buf[0..2]),
+            sos_version: buf[2],
+            max_frame: LittleEndian::read_u32(&buf[4..8]),
+            features: LittleEndian::read_u32(&buf[8..12]),
+            name: str::from_utf8(&buf[HELLO_SIZE..]).ok()?,
+        })
+    }
+    /// What we can settle on with a peer that sent `theirs`.
+    pub fn agree(&self, theirs: &Hello) -> Result<Agreement, Mismatch> {
+        if theirs.version != self.version {
+            return Err(Mismatch::Version(theirs.version));
+        }
+        if theirs.sos_version != self.sos_version {
+            return Err(Mismatch::SosVersion(theirs.sos_version));
+        }
+        Ok(Agreement {
+            max_frame: min(self.max_frame, theirs.max_frame),
+            features: self.features & theirs.features,
+        })
+    }
+}
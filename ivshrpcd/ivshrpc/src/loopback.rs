@@ -0,0 +1,122 @@
+//! Two ends of a channel in one process, connected by rings on the heap. Stands in for the guest
+//! and the host when testing.
+use super::Transport;
+use ringbuf::{mpsc, Blocking, Header, Memory, Notifier, RecvError, SpinThenYield, TimedOut};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Polls of a ring before blocking on the doorbell, or yielding for room.
+const SPINS: usize = 100;
+/// Yields a sender waits through for the other end to make room.
+const ROOM_YIELDS: usize = 100_000;
+
+/// Rung by the sending end, waited on by the receiving one.
+struct Doorbell {
+    rung: Mutex<bool>,
+    cond: Condvar,
+    timeout: Duration,
+}
+
+impl Doorbell {
+    fn ring(&self) {
+        *self.rung.lock().unwrap() = true;
+        self.cond.notify_one();
+    }
+}
+
+impl Notifier for Doorbell {
+    fn wait(&self) -> Result<(), TimedOut> {
+        let mut rung = self.rung.lock().unwrap();
+        while !*rung {
+            let (guard, res) = self.cond.wait_timeout(rung, self.timeout).unwrap();
+            rung = guard;
+            if res.timed_out() && !*rung {
+                return Err(TimedOut);
+            }
+        }
+        *rung = false;
+        Ok(())
+    }
+}
+
+pub struct Loopback {
+    producer: mpsc::Producer<'static>,
+    consumer: Mutex<mpsc::Consumer<'static>>,
+    /// Rung by the other end.
+    doorbell: Arc<Doorbell>,
+    theirs: Arc<Doorbell>,
+    // Last, so that the rings go before their memory
+    _memory: [Arc<Memory>; 2],
+}
+
+/// Sets up a ring of `capacity` bytes.
+fn ring(
+    capacity: usize,
+) -> (
+    Arc<Memory>,
+    mpsc::Producer<'static>,
+    mpsc::Consumer<'static>,
+) {
+    let memory = Memory::new(capacity, 0);
+    unsafe {
+        let buff = memory.buff();
+        Header::new_inline_at(buff);
+        let producer = mpsc::Producer::from_slice(&mut *(buff as *mut [u8])).unwrap();
+        let consumer = mpsc::Consumer::from_slice(buff).unwrap();
+        (Arc::new(memory), producer, consumer)
+    }
+}
+
+/// Two connected ends, each sending on a ring of `capacity` bytes. Receiving gives up after
+/// `timeout` without a frame.
+pub fn loopback(capacity: usize, timeout: Duration) -> (Loopback, Loopback) {
+    let (a_memory, a_producer, a_consumer) = ring(capacity);
+    let (b_memory, b_producer, b_consumer) = ring(capacity);
+    let doorbell = || {
+        Arc::new(Doorbell {
+            rung: Mutex::new(false),
+            cond: Condvar::new(),
+            timeout,
+        })
+    };
+    let (a_doorbell, b_doorbell) = (doorbell(), doorbell());
+    (
+        Loopback {
+            producer: a_producer,
+            consumer: Mutex::new(b_consumer),
+            doorbell: a_doorbell.clone(),
+            theirs: b_doorbell.clone(),
+            _memory: [a_memory.clone(), b_memory.clone()],
+        },
+        Loopback {
+            producer: b_producer,
+            consumer: Mutex::new(a_consumer),
+            doorbell: b_doorbell,
+            theirs: a_doorbell,
+            _memory: [a_memory, b_memory],
+        },
+    )
+}
+
+impl Transport for Loopback {
+    fn max_frame(&self) -> usize {
+        self.producer.max_frame()
+    }
+
+    fn send<F: FnOnce(&mut [u8])>(&self, len: usize, fill: F) -> Result<(), TimedOut> {
+        let wait = SpinThenYield::new(SPINS, Some(ROOM_YIELDS), thread::yield_now);
+        {
+            let mut frame = self.producer.reserve_frame(len, &wait)?;
+            fill(&mut frame);
+        }
+        self.theirs.ring();
+        Ok(())
+    }
+
+    fn recv<R, F: FnOnce(&[u8]) -> R>(&self, handle: F) -> Result<R, RecvError> {
+        let mut consumer = self.consumer.lock().unwrap();
+        let frame = consumer.recv_frame(&Blocking::new(SPINS, &*self.doorbell))?;
+        Ok(handle(&frame))
+    }
+}
@@ -0,0 +1,17 @@
+use ringbuf::{RecvError, TimedOut};
+
+/// Carries frames between the two ends of an ivshrpc channel. Sending a frame rings the doorbell
+/// of the other end, which waits on it to receive. Frames arrive whole and in the order they
+/// were sent.
+pub trait Transport {
+    /// The largest frame `send` takes.
+    fn max_frame(&self) -> usize;
+
+    /// Sends a frame of `len` bytes, which `fill` writes in place.
+    fn send<F: FnOnce(&mut [u8])>(&self, len: usize, fill: F) -> Result<(), TimedOut>;
+
+    /// Hands the next frame to `handle`, waiting for it until the transport gives up. Only one
+    /// thread at a time receives. Once the frames are corrupted, nothing more is received until
+    /// the transport is set up again.
+    fn recv<R, F: FnOnce(&[u8]) -> R>(&self, handle: F) -> Result<R, RecvError>;
+}
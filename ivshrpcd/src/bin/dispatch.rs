@@ -1,15 +1,17 @@
-use super::{ivshrpc_cast, ivshrpc_fuse};
+use super::{ivshrpc_cast, ivshrpc_fuse, MSG_LIMITS};
 use either::Either;
 use fnv::FnvHashMap;
+use ivshrpc::PeerId;
 use spin::RwLock;
-use std::convert::TryInto;
+use std::time::Duration;
 
 use sos::{
-    DecodeIter, EncodedValues, Function, JustError, OwnedEncodedValues, OwnedFunction, Value,
+    DecodeIter, EncodedValues, ErrorCode, Function, JustError, OwnedEncodedValues, OwnedFunction,
 };
 
-type FuseFunc = fn(args: DecodeIter) -> OwnedEncodedValues;
-type CastFunc = fn(args: DecodeIter);
+/// Host functions are told which guest called them.
+type FuseFunc = fn(guest: PeerId, args: DecodeIter) -> OwnedEncodedValues;
+type CastFunc = fn(guest: PeerId, args: DecodeIter);
 
 lazy_static! {
     static ref FUNC_TABLE: RwLock<FnvHashMap<OwnedFunction, Either<CastFunc, FuseFunc>>> = {
@@ -30,53 +32,57 @@ lazy_static! {
     };
 }
 
-fn hello(args: DecodeIter) {
-    println!("Hello from host {:?}", args.collect::<Vec<Value>>())
+fn hello(guest: PeerId, args: DecodeIter) {
+    println!("Hello from host to guest {} {}", guest, args)
 }
 
-fn hello_fuse(args: DecodeIter) -> OwnedEncodedValues {
-    let msg = format!("Hello from host {:?}", args.collect::<Vec<Value>>());
+fn hello_fuse(guest: PeerId, args: DecodeIter) -> OwnedEncodedValues {
+    let msg = format!("Hello from host to guest {} {}", guest, args);
     EncodedValues::from(sos![msg.as_str()]).into_owned()
 }
 
-fn cast_test(mut args: DecodeIter) {
-    let ret = ivshrpc_fuse(sos![("call", "print"), args.next().unwrap()]);
+fn cast_test(guest: PeerId, mut args: DecodeIter) {
+    let ret = ivshrpc_fuse(
+        guest,
+        sos![("call", "print"), args.next().unwrap()],
+        Some(Duration::from_secs(5)),
+    );
     println!(
-        "Kernel returned {:?}",
+        "Guest {} returned {}",
+        guest,
         EncodedValues::from(ret.unwrap())
-            .decode()
-            .unwrap()
-            .collect::<Vec<_>>()
     );
 }
 
 pub fn dispatch<'a, 'b>(
+    guest: PeerId,
     args: OwnedEncodedValues,
     fuse: bool,
 ) -> Result<OwnedEncodedValues, JustError<'static>> {
     let args = EncodedValues::from(args);
-    let mut iter = args
-        .decode()
-        .ok_or(JustError::new("Could not decode argumenta"))?;
-    let function: Function = iter
-        .next()
-        .ok_or(JustError::new("Not enough arguments"))?
-        .try_into()
-        .map_err(|e| JustError::new(e))?;
+    let mut iter = args.validate_limited(MSG_LIMITS)?.into_inner();
+    let function: Function = iter.next_as()?;
+    println!("Guest {} calls {:?}", guest, function);
 
     let lock = FUNC_TABLE.read();
 
     let func = lock
         .get(&OwnedFunction::from(function)) // TODO avoid this stupid copying operation
-        .ok_or(JustError::new("No such function"))?;
+        .ok_or(JustError::with_code(
+            ErrorCode::NO_SUCH_FUNCTION,
+            "No such function",
+        ))?;
 
     if fuse {
-        Ok(func.right().ok_or(JustError::new(
+        Ok(func.right().ok_or(JustError::with_code(
+            ErrorCode::WRONG_CALL_KIND,
             "Attempt to fuse to a cast only function",
-        ))?(iter))
+        ))?(guest, iter))
     } else {
-        func.left()
-            .ok_or(JustError::new("Attempt to cast to a fuse only function"))?(iter);
+        func.left().ok_or(JustError::with_code(
+            ErrorCode::WRONG_CALL_KIND,
+            "Attempt to cast to a fuse only function",
+        ))?(guest, iter);
         Ok(EncodedValues::from(sos!()).into_owned())
     }
 }
@@ -9,58 +9,86 @@ extern crate ringbuf;
 #[macro_use]
 extern crate sos;
 extern crate ivshrpc;
+extern crate ivshrpcd;
 extern crate nix;
 extern crate spin;
 extern crate spmc;
-extern crate threadpool;
 
 mod dispatch;
+mod server;
 
 use byteorder::{ByteOrder, NativeEndian};
 use dispatch::dispatch;
+use server::Server;
 
 use fnv::FnvHashMap;
 use ivshrpc::*;
+use ivshrpcd::Endpoint;
 use memmap::MmapMut;
 use nix::fcntl;
+use nix::poll::{poll, EventFlags, PollFd};
 use nix::sys::socket::{recvmsg, CmsgSpace, ControlMessage, MsgFlags, RecvMsg};
 use nix::sys::uio::IoVec;
 use nix::unistd;
-use ringbuf::{Consumer, Header, Producer};
-use sos::{EncodedValues, OwnedEncodedValues, SOS};
+use ringbuf::{
+    mpsc, Blocking, End, Header, Monitor, Notifier, Peer, RecvError, SpinThenYield, TimedOut,
+};
+use sos::{DecodeLimits, OwnedEncodedValues, SOS};
 
 use std::fs::{remove_file, File};
 use std::io::Read;
-use std::ops::Deref;
-use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 use std::os::unix::net::UnixStream;
-use std::process::Command;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{Arc, Condvar, Mutex};
-use std::{str, thread, time};
-use threadpool::ThreadPool;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{slice, str, thread};
 
 const IVSH_PATH: &str = "/dev/shm/ivshmem";
-const IVSH_SERVER: &str = "ivshmem-server";
 const IVSH_SERVER_SOCKET: &str = "/tmp/ivshmem_socket";
+/// Interrupts per peer, as the guests' ivshmem devices are configured. Each slot has one of ours
+/// for its guest to ring, the guests are only ever sent their first.
+const VECTORS: usize = MAX_GUESTS;
+/// Workers per guest.
 const NUM_WORKERS: usize = 8;
+/// Polls of the rings before yielding or blocking.
+const RING_SPINS: usize = 1000;
+/// Yields a worker waits through for the guest to make room, about a second.
+const ROOM_YIELDS: usize = 1_000_000;
+/// Milliseconds between checks of the guest's heartbeat, if it sends nothing meanwhile.
+const HEARTBEAT_MS: i32 = 100;
+/// Checks in a row without a heartbeat before the calls waiting on the guest are failed.
+const SILENT_CHECKS: usize = 10;
+/// Limits on the SOS payloads the guest sends us.
+const MSG_LIMITS: DecodeLimits = DecodeLimits {
+    max_depth: 16,
+    max_values: 4096,
+    max_bytes: 1024 * 1024,
+};
 
 lazy_static! {
-    static ref NOTIFY_FD: spin::Mutex<RawFd> = spin::Mutex::new(-1);
-    static ref PRODUCER: spin::Mutex<Option<Producer<'static>>> = spin::Mutex::new(None);
-    static ref CALL_QUEUE: spin::Mutex<FnvHashMap<CallId, CallResult>> =
-        spin::Mutex::new(FnvHashMap::default());
-    static ref THREAD_POOL: spin::Mutex<ThreadPool> =
-        spin::Mutex::new(ThreadPool::new(NUM_WORKERS));
+    static ref LINKS: Vec<Link> = (0..MAX_GUESTS).map(|_| Link::new()).collect();
 }
 
-type CallResult = Arc<(
-    Mutex<Option<Result<OwnedEncodedValues, OwnedEncodedValues>>>,
-    Condvar,
-)>;
+/// One per slot, set up before any call is made.
+static mut ENDPOINTS: Option<Vec<Arc<Endpoint<Ivshmem>>>> = None;
 
-static CALL_ID: AtomicUsize = AtomicUsize::new(0);
-static mut CONSUMER: Option<Consumer> = None;
+/// What we hear about the guest in a slot from the ivshmem server.
+struct Link {
+    /// Interrupts the guest, -1 while the slot is empty.
+    notify: spin::Mutex<RawFd>,
+    /// Set when the guest disconnects, its dispatch thread then sets its rings up again.
+    reset: AtomicBool,
+}
+
+impl Link {
+    fn new() -> Self {
+        Link {
+            notify: spin::Mutex::new(-1),
+            reset: AtomicBool::new(false),
+        }
+    }
+}
 
 fn get_fd(msg: &RecvMsg) -> RawFd {
     for cmsg in msg.cmsgs() {
@@ -82,151 +110,214 @@ fn send_interrupt(fd: RawFd) {
     }
 }
 
-#[inline]
-fn write_msg<T: SOS>(args: T, mut header: MsgHeader) {
-    header.length = args.encoded_len() as u32;
-    let mut lock = PRODUCER.lock();
-    let mut buffer = lock
-        .as_mut()
-        .unwrap()
-        .write(IVSHRPC_HEADER_SIZE + header.length as usize);
-    buffer[..IVSHRPC_HEADER_SIZE].copy_from_slice(header.to_slice());
-    args.encode(&mut buffer[IVSHRPC_HEADER_SIZE..]);
-
-    // TODO, check if listening
-    let fd = NOTIFY_FD.lock();
-    assert!(*fd != -1);
-    send_interrupt(*fd);
+/// The interrupts a guest sends us, counted by the eventfd of its slot's vector. Times out every
+/// `HEARTBEAT_MS` without one, or right away when the rings are to be set up again.
+struct Interrupts {
+    fd: File,
+    link: &'static Link,
 }
 
-fn listen_for_clients(fd: RawFd, myid: u16) -> Result<(), nix::Error> {
+impl Notifier for Interrupts {
+    fn wait(&self) -> Result<(), TimedOut> {
+        if self.link.reset.load(Ordering::Relaxed) {
+            return Err(TimedOut);
+        }
+        let mut fds = [PollFd::new(self.fd.as_raw_fd(), EventFlags::POLLIN)];
+        if poll(&mut fds, HEARTBEAT_MS).expect("Failed to poll my own fd") == 0 {
+            return Err(TimedOut);
+        }
+        let mut buf: [u8; 8] = [0; 8];
+        (&self.fd)
+            .read_exact(&mut buf[..])
+            .expect("Failed to read on my own fd");
+        Ok(())
+    }
+}
+
+/// The rings in a guest's slot, with its interrupts as their doorbell.
+struct Ivshmem {
+    producer: mpsc::Producer<'static>,
+    consumer: Mutex<mpsc::Consumer<'static>>,
+    interrupts: Blocking<Interrupts>,
+    link: &'static Link,
+}
+
+impl Transport for Ivshmem {
+    fn max_frame(&self) -> usize {
+        self.producer.max_frame()
+    }
+
+    fn send<F: FnOnce(&mut [u8])>(&self, len: usize, fill: F) -> Result<(), TimedOut> {
+        let wait = SpinThenYield::new(RING_SPINS, Some(ROOM_YIELDS), thread::yield_now);
+        {
+            let mut frame = self.producer.reserve_frame(len, &wait)?;
+            fill(&mut frame);
+        }
+
+        // TODO, check if listening
+        let fd = self.link.notify.lock();
+        // Without a guest the message waits for the rings to be set up again
+        if *fd != -1 {
+            send_interrupt(*fd);
+        }
+        Ok(())
+    }
+
+    fn recv<R, F: FnOnce(&[u8]) -> R>(&self, handle: F) -> Result<R, RecvError> {
+        let mut consumer = self.consumer.lock().unwrap();
+        let frame = consumer.recv_frame(&self.interrupts)?;
+        Ok(handle(&frame))
+    }
+}
+
+fn listen_for_clients(
+    fd: RawFd,
+    myid: PeerId,
+    mut vectors: FnvHashMap<PeerId, usize>,
+) -> Result<(), nix::Error> {
     let mut buf: [u8; 8] = [0; 8];
     let iov = [IoVec::from_mut_slice(&mut buf[..])];
     let mut cmsg: CmsgSpace<RawFd> = CmsgSpace::new();
 
     loop {
         let msg = recvmsg(fd, &iov, Some(&mut cmsg), MsgFlags::empty())?;
-        let rcvid = NativeEndian::read_i64(iov[0].as_slice()) as u16;
+        let rcvid = NativeEndian::read_i64(iov[0].as_slice()) as PeerId;
         assert!(rcvid != myid); // This means that the server was configured for more vectors
         let fd = get_fd(&msg);
-
-        // When fd is not present it means that a client was disconnected, otherwise connected
-        if fd == -1 {
-            println!("Client id {} disconnected", rcvid)
-        } else {
-            println!("Client id {} connected", rcvid);
-            *NOTIFY_FD.lock() = fd;
-        }
+        update_guest(&mut vectors, rcvid, fd);
     }
 }
 
-fn dispatch_thread(myfd: RawFd) {
-    let flags = fcntl::fcntl(myfd, fcntl::FcntlArg::F_GETFL).unwrap();
-    let mut oflags = fcntl::OFlag::from_bits(flags).unwrap();
-    oflags.remove(fcntl::OFlag::O_NONBLOCK);
-    let mut stream = unsafe { File::from_raw_fd(myfd) };
+/// Takes in what the server says about a guest. Without an fd it disconnected, otherwise it sent
+/// one of its vectors, in order, of which we keep the first. `vectors` counts the ones received.
+fn update_guest(vectors: &mut FnvHashMap<PeerId, usize>, id: PeerId, fd: RawFd) {
+    let link = match guest_slot(id) {
+        Some(slot) => &LINKS[slot],
+        None => {
+            println!("Guest id {} has no slot, there are {}", id, MAX_GUESTS);
+            if fd != -1 {
+                let _ = unistd::close(fd);
+            }
+            return;
+        }
+    };
 
-    loop {
-        fcntl::fcntl(myfd, fcntl::FcntlArg::F_SETFL(oflags)).expect("Failed to make myfd blocking");
-        let mut buf: [u8; 8] = [0; 8];
-        stream
-            .read_exact(&mut buf[..])
-            .expect("Failed to read on my own fd");
-        println!("Received an interrupt!");
-        listener()
+    if fd == -1 {
+        println!("Guest id {} disconnected", id);
+        vectors.remove(&id);
+        let mut notify = link.notify.lock();
+        if *notify != -1 {
+            let _ = unistd::close(*notify);
+            *notify = -1;
+        }
+        link.reset.store(true, Ordering::Relaxed);
+        return;
     }
+
+    let received = vectors.entry(id).or_insert(0);
+    if *received == 0 {
+        println!("Guest id {} connected", id);
+        *link.notify.lock() = fd;
+    } else {
+        let _ = unistd::close(fd);
+    }
+    *received += 1;
 }
 
-pub fn ivshrpc_cast<T: SOS>(args: T) {
-    let callid = CALL_ID.fetch_add(1, Ordering::Relaxed);
-    write_msg(args, MsgHeader::new(MsgType::Cast, callid as u64));
+/// Half of a slot in the shared memory, which outlives every user of it.
+unsafe fn ring(base: *mut u8, slot: usize, half: usize) -> &'static mut [u8] {
+    slice::from_raw_parts_mut(base.offset(ring_offset(slot, half) as isize), SLOT_SIZE / 2)
 }
 
-pub fn ivshrpc_fuse<'a, T: SOS>(args: T) -> Result<OwnedEncodedValues, OwnedEncodedValues> {
-    //-> EncodedValues<'a> {}
-    let callid = CALL_ID.fetch_add(1, Ordering::Relaxed);
-    write_msg(args, MsgHeader::new(MsgType::Fuse, callid as u64));
-
-    let entry = CALL_QUEUE
-        .lock()
-        .entry(callid as u64)
-        .or_insert(Arc::new((Mutex::new(None), Condvar::new())))
-        .clone();
-
-    let (lock, var) = entry.deref();
-    let mut res = lock.lock().unwrap();
-    while res.is_none() {
-        res = var.wait(res).unwrap();
+/// Sets up both rings in the shared memory afresh, returning the new epoch.
+fn setup_rings(to_guest: &mut [u8], from_guest: &mut [u8]) -> usize {
+    unsafe {
+        let to_guest = Header::new_inline_at(to_guest);
+        let from_guest = Header::new_inline_at(from_guest);
+        let epoch = to_guest.generation();
+        to_guest.announce(End::Producer, epoch);
+        from_guest.announce(End::Consumer, epoch);
+        epoch
     }
-
-    return res.take().unwrap();
 }
 
-fn listener() {
-    let consumer = unsafe { CONSUMER.as_mut().unwrap() };
+/// Handles messages from the guest in a slot and keeps an eye on it in between. When it starts
+/// over, disconnects or corrupts the rings, the calls waiting on it fail and the rings are set up
+/// again for it.
+fn dispatch_thread(endpoint: &Arc<Endpoint<Ivshmem>>, to_guest: &mut [u8], from_guest: &mut [u8]) {
+    let link = endpoint.transport().link;
+    let mut guest = unsafe { Header::attach(from_guest) }
+        .ok()
+        .map(|header| Monitor::new(header, End::Producer));
+
     loop {
-        let header = {
-            let mut header = consumer
-                .try_read(IVSHRPC_HEADER_SIZE, 1000)
-                .map(MsgHeader::from_slice);
-            if header.is_none() {
-                // TODO set not listening
-                // Checking one last time to avoid race condition
-                header = consumer
-                    .try_read(IVSHRPC_HEADER_SIZE, 1)
-                    .map(MsgHeader::from_slice);
-                if header.is_none() {
-                    return;
+        let stopped = Endpoint::listen(endpoint);
+
+        // The guest writes to the headers too, they only make sense for as long as it lets them
+        let headers = unsafe { (Header::attach(to_guest), Header::attach(from_guest)) };
+        let reason = match headers {
+            (Ok(to_guest), Ok(from_guest)) => {
+                to_guest.beat(End::Producer);
+                let guest = guest.get_or_insert_with(|| Monitor::new(from_guest, End::Producer));
+                match guest.check(from_guest) {
+                    Peer::Restarted(epoch) => {
+                        println!(
+                            "Guest id {} started over at epoch {}",
+                            endpoint.peer(),
+                            epoch
+                        );
+                        Some("The guest started over")
+                    }
+                    Peer::Silent(checks) if checks >= SILENT_CHECKS => {
+                        endpoint.fail_calls("The guest stopped responding");
+                        None
+                    }
+                    _ => None,
                 }
             }
-            header.unwrap()
+            _ => Some("The guest corrupted the ring headers"),
         };
-
-        unsafe {
-            println!("Len: {}, {:?}", header.length, header.to_slice());
-        }
-
-        let buff = consumer.read(header.length as usize);
-        //println!("Bytes: {:?}", &buff[..]);
-        let values = EncodedValues::from(&buff[..]);
-        let callid = header.callid;
-        let msgtype = MsgType::from_u8(header.msgtype).expect("Unexpected msgtype");
-
-        match msgtype {
-            MsgType::Fuse | MsgType::Cast => {
-                let pool = THREAD_POOL.lock();
-                let owned_values = values.into_owned();
-                pool.execute(move || {
-                    let result = dispatch(owned_values, msgtype == MsgType::Fuse);
-                    match result {
-                        Ok(val) => if msgtype == MsgType::Fuse {
-                            write_msg(
-                                EncodedValues::from(val),
-                                MsgHeader::new(MsgType::Error, callid),
-                            );
-                        },
-                        Err(err) => write_msg(err, MsgHeader::new(MsgType::Error, callid)),
-                    }
-                });
-            }
-            MsgType::Error | MsgType::Return => {
-                let entry = CALL_QUEUE
-                    .lock()
-                    .remove(&callid)
-                    .expect("Received return for unqueued call");
-                let (lock, var) = entry.deref();
-                *lock.lock().unwrap() = Some(if msgtype == MsgType::Error {
-                    Err(values.into_owned())
-                } else {
-                    Ok(values.into_owned())
-                });
-                var.notify_all();
-            }
+        let reason = if link.reset.swap(false, Ordering::Relaxed) {
+            Some("The guest disconnected")
+        } else if stopped == RecvError::Corrupted {
+            Some("The guest corrupted its ring")
+        } else {
+            reason
         };
+        if let Some(reason) = reason {
+            endpoint.start_over(reason, || setup_rings(to_guest, from_guest));
+            // The guest waits for the rings to be set up before using them
+            let fd = link.notify.lock();
+            if *fd != -1 {
+                send_interrupt(*fd);
+            }
+        }
     }
 }
 
-fn ivsh_server_init(fd: RawFd) -> Result<(u16, RawFd, RawFd), nix::Error> {
+fn endpoint(guest: PeerId) -> &'static Arc<Endpoint<Ivshmem>> {
+    let slot = guest_slot(guest).expect("No slot for the guest");
+    unsafe { &ENDPOINTS.as_ref().unwrap()[slot] }
+}
+
+pub fn ivshrpc_cast<T: SOS>(guest: PeerId, args: T) -> Result<(), OwnedEncodedValues> {
+    endpoint(guest).cast(args)
+}
+
+pub fn ivshrpc_fuse<T: SOS>(
+    guest: PeerId,
+    args: T,
+    timeout: Option<Duration>,
+) -> Result<OwnedEncodedValues, OwnedEncodedValues> {
+    endpoint(guest).fuse(args, timeout)
+}
+
+/// Walks through the server's greeting, returning our id, the shared memory and our vectors.
+/// The guests connected already are taken in along the way.
+fn ivsh_server_init(
+    fd: RawFd,
+    vectors: &mut FnvHashMap<PeerId, usize>,
+) -> Result<(PeerId, RawFd, Vec<RawFd>), nix::Error> {
     let mut buf: [u8; 8] = [0; 8];
     let iov = [IoVec::from_mut_slice(&mut buf[..])];
     let mut cmsg: CmsgSpace<RawFd> = CmsgSpace::new();
@@ -238,7 +329,7 @@ fn ivsh_server_init(fd: RawFd) -> Result<(u16, RawFd, RawFd), nix::Error> {
     }
     // My id
     recvmsg::<()>(fd, &iov, None, MsgFlags::empty())?;
-    let id: u16 = NativeEndian::read_i64(iov[0].as_slice()) as u16;
+    let id: PeerId = NativeEndian::read_i64(iov[0].as_slice()) as PeerId;
 
     // Fd that points to memory
     let memfd = {
@@ -251,69 +342,104 @@ fn ivsh_server_init(fd: RawFd) -> Result<(u16, RawFd, RawFd), nix::Error> {
         fd
     };
 
+    let mut own = Vec::with_capacity(VECTORS);
     loop {
         let msg = recvmsg(fd, &iov, Some(&mut cmsg), MsgFlags::empty())?;
-        let rcvid = NativeEndian::read_i64(iov[0].as_slice()) as u16;
+        let rcvid = NativeEndian::read_i64(iov[0].as_slice()) as PeerId;
         // This is connection setup
         let fd = get_fd(&msg);
         assert!(fd != -1);
-        if rcvid == id {
-            return Ok((id, memfd, fd));
+        if rcvid != id {
+            update_guest(vectors, rcvid, fd);
+            continue;
+        }
+        // Ours come last
+        own.push(fd);
+        if own.len() == VECTORS {
+            return Ok((id, memfd, own));
         }
-        *NOTIFY_FD.lock() = fd;
     }
 }
 
 fn main() {
     let _ = remove_file(IVSH_SERVER_SOCKET);
 
-    let _cmd = Command::new(IVSH_SERVER)
-        .args(&[
-            "-F",
-            "-m",
-            "/dev/shm",
-            "-M",
-            "ivshmem",
-            "-l",
-            &BUFFER_SIZE.to_string(),
-            "-n",
-            "1",
-            "-S",
-            IVSH_SERVER_SOCKET,
-        ]).spawn()
-        .expect("Failed to start ivshmem-server");
-
-    let timeout = time::SystemTime::now() + time::Duration::from_secs(10);
-
-    let mut connfd = 0;
-
-    while time::SystemTime::now() < timeout {
-        let conn = UnixStream::connect(IVSH_SERVER_SOCKET);
-        if conn.is_err() {
-            continue;
-        }
-        connfd = conn.unwrap().into_raw_fd();
-        break;
-    }
-    assert!(connfd != 0);
+    let server = Server::bind(IVSH_SERVER_SOCKET, BUFFER_SIZE, VECTORS)
+        .expect("Failed to start the ivshmem server");
+    thread::spawn(move || Server::serve(Arc::new(server)));
+
+    // We are a peer like the guests, the first one to connect
+    let connfd = UnixStream::connect(IVSH_SERVER_SOCKET)
+        .expect("Failed to connect to the ivshmem server")
+        .into_raw_fd();
 
-    let (myid, memfd, myfd) =
-        ivsh_server_init(connfd).expect("Failed to connect to ivshmem-server");
+    let mut vectors = FnvHashMap::default();
+    let (myid, memfd, myfds) =
+        ivsh_server_init(connfd, &mut vectors).expect("Failed to connect to the ivshmem server");
+    assert_eq!(
+        myid, HOST_ID,
+        "Guests connected to the ivshmem server before us"
+    );
 
     let file = unsafe { File::from_raw_fd(memfd) };
     let mut mapping = unsafe { MmapMut::map_mut(&file).expect("Failed to map ivshmem") };
-    let (viho, vohi) = mapping.split_at_mut(BUFFER_SIZE / 2);
+    let base = mapping.as_mut_ptr();
 
-    // It is host's responsibility to initliase the headers, anything that was there previously will be wiped.
+    let mut slots = Vec::with_capacity(MAX_GUESTS);
+    for (slot, myfd) in myfds.into_iter().enumerate() {
+        // This is used to escape mapping lifetime, the threads using it are joined below.
+        let (to_guest, from_guest) = unsafe { (ring(base, slot, 0), ring(base, slot, 1)) };
+
+        // It is host's responsibility to initliase the headers, anything that was there previously will be wiped.
+        let epoch = setup_rings(to_guest, from_guest);
+
+        let flags = fcntl::fcntl(myfd, fcntl::FcntlArg::F_GETFL).unwrap();
+        let mut oflags = fcntl::OFlag::from_bits(flags).unwrap();
+        oflags.remove(fcntl::OFlag::O_NONBLOCK);
+        fcntl::fcntl(myfd, fcntl::FcntlArg::F_SETFL(oflags)).expect("Failed to make myfd blocking");
+        let interrupts = Interrupts {
+            fd: unsafe { File::from_raw_fd(myfd) },
+            link: &LINKS[slot],
+        };
+
+        let ivshmem = unsafe {
+            Ivshmem {
+                producer: mpsc::Producer::from_slice(&mut *(to_guest as *mut [u8]))
+                    .expect("Failed to attach to the ring we set up"),
+                consumer: Mutex::new(
+                    mpsc::Consumer::from_slice(&mut *(from_guest as *mut [u8]))
+                        .expect("Failed to attach to the ring we set up"),
+                ),
+                interrupts: Blocking::new(RING_SPINS, interrupts),
+                link: &LINKS[slot],
+            }
+        };
+        // The guest in slot n has id n + 1
+        let guest = slot as PeerId + 1;
+        let endpoint = Arc::new(Endpoint::new(
+            guest,
+            "ivshrpcd",
+            ivshmem,
+            epoch,
+            dispatch,
+            MSG_LIMITS,
+            NUM_WORKERS,
+        ));
+        slots.push((endpoint, to_guest, from_guest));
+    }
     unsafe {
-        Header::new_inline_at(viho);
-        Header::new_inline_at(vohi);
-        // This is used to escape mapping lifetime.
-        *PRODUCER.lock() = Some(Producer::from_slice(&mut *(viho as *mut [u8])));
-        CONSUMER = Some(Consumer::from_slice(&mut *(vohi as *mut [u8])));
+        ENDPOINTS = Some(slots.iter().map(|slot| slot.0.clone()).collect());
     }
 
-    thread::spawn(move || listen_for_clients(connfd, myid));
+    thread::spawn(move || listen_for_clients(connfd, myid, vectors));
 
-    dispatch_thread(myfd);
+    let threads: Vec<_> = slots
+        .into_iter()
+        .map(|(endpoint, to_guest, from_guest)| {
+            thread::spawn(move || dispatch_thread(&endpoint, to_guest, from_guest))
+        })
+        .collect();
+    for thread in threads {
+        let _ = thread.join();
+    }
 }
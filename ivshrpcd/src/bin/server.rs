@@ -0,0 +1,190 @@
+//! The server side of the protocol QEMU's ivshmem doorbell device speaks over a Unix socket, in
+//! place of QEMU's `ivshmem-server`. Every peer that connects is given an id, the shared memory
+//! and an eventfd per vector of every peer, its own included. Peers are told when others come
+//! and go, and can interrupt one another through those eventfds.
+use byteorder::{ByteOrder, NativeEndian};
+use nix;
+use nix::errno::Errno;
+use nix::sys::eventfd::{eventfd, EfdFlags};
+use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+use nix::sys::socket::{sendmsg, ControlMessage, MsgFlags};
+use nix::sys::uio::IoVec;
+use nix::unistd::{close, ftruncate};
+use std::ffi::CString;
+use std::io;
+use std::io::Read;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Sent to a peer before anything else.
+const PROTOCOL_VERSION: i64 = 0;
+
+struct Peer {
+    id: u16,
+    sock: UnixStream,
+    /// Interrupt the peer when written to, one per vector.
+    vectors: Vec<RawFd>,
+}
+
+struct Peers {
+    list: Vec<Peer>,
+}
+
+pub struct Server {
+    listener: UnixListener,
+    memfd: RawFd,
+    vectors: usize,
+    peers: Mutex<Peers>,
+}
+
+/// Sends `value` to a peer, along with `fd` unless it is -1.
+fn send(sock: &UnixStream, value: i64, fd: RawFd) -> Result<(), nix::Error> {
+    let mut buf = [0; 8];
+    NativeEndian::write_i64(&mut buf, value);
+    let iov = [IoVec::from_slice(&buf[..])];
+    let fds = [fd];
+    let cmsgs = [ControlMessage::ScmRights(&fds)];
+    let cmsgs: &[ControlMessage] = if fd == -1 { &[] } else { &cmsgs };
+    sendmsg(sock.as_raw_fd(), &iov, cmsgs, MsgFlags::empty(), None)?;
+    Ok(())
+}
+
+fn io_error(e: nix::Error) -> io::Error {
+    match e {
+        nix::Error::Sys(errno) => errno.into(),
+        e => io::Error::new(io::ErrorKind::Other, e),
+    }
+}
+
+impl Server {
+    /// Sets up `size` bytes of shared memory and listens for peers on the socket at `path`, each
+    /// of them with `vectors` interrupts.
+    pub fn bind(path: &str, size: usize, vectors: usize) -> io::Result<Self> {
+        let memfd = memfd_create(
+            &CString::new("ivshmem").unwrap(),
+            MemFdCreateFlag::MFD_CLOEXEC,
+        )
+        .map_err(io_error)?;
+        ftruncate(memfd, size as i64).map_err(io_error)?;
+
+        Ok(Server {
+            listener: UnixListener::bind(path)?,
+            memfd,
+            vectors,
+            peers: Mutex::new(Peers { list: Vec::new() }),
+        })
+    }
+
+    /// Accepts peers until the socket fails.
+    pub fn serve(server: Arc<Server>) -> io::Result<()> {
+        loop {
+            let (sock, _) = server.listener.accept()?;
+            // Kept to hear from the peer, the one in the list is written to
+            let mut hangup = sock.try_clone()?;
+            let id = match server.add_peer(sock) {
+                Ok(id) => id,
+                Err(e) => {
+                    println!("Failed to set up a new peer: {:?}", e);
+                    continue;
+                }
+            };
+            println!("Peer id {} connected", id);
+
+            let server = server.clone();
+            thread::spawn(move || {
+                // Peers never send anything, reading only tells when they hang up
+                let mut buf = [0; 1];
+                while let Ok(n) = hangup.read(&mut buf) {
+                    if n == 0 {
+                        break;
+                    }
+                }
+                server.remove_peer(id);
+                println!("Peer id {} disconnected", id);
+            });
+        }
+    }
+
+    fn add_peer(&self, sock: UnixStream) -> Result<u16, nix::Error> {
+        let mut peers = self.peers.lock().unwrap();
+        let id = match peers.free_id() {
+            Some(id) => id,
+            None => {
+                // Every id is taken
+                return Err(nix::Error::Sys(Errno::EUSERS));
+            }
+        };
+        let mut peer = Peer {
+            id,
+            sock,
+            vectors: Vec::with_capacity(self.vectors),
+        };
+        let res = self.greet(&mut peer, &peers.list);
+        if let Err(e) = res {
+            peer.close();
+            return Err(e);
+        }
+
+        // The others hear about the new peer, failing that they are dropped when they hang up
+        for other in &peers.list {
+            for fd in &peer.vectors {
+                let _ = send(&other.sock, id as i64, *fd);
+            }
+        }
+        peers.list.push(peer);
+        Ok(id)
+    }
+
+    /// Walks a new peer through the handshake: the version, its id, the shared memory, the
+    /// vectors of the other peers and then its own.
+    fn greet(&self, peer: &mut Peer, others: &[Peer]) -> Result<(), nix::Error> {
+        for _ in 0..self.vectors {
+            peer.vectors
+                .push(eventfd(0, EfdFlags::EFD_CLOEXEC | EfdFlags::EFD_NONBLOCK)?);
+        }
+        send(&peer.sock, PROTOCOL_VERSION, -1)?;
+        send(&peer.sock, peer.id as i64, -1)?;
+        send(&peer.sock, -1, self.memfd)?;
+        for other in others {
+            for fd in &other.vectors {
+                send(&peer.sock, other.id as i64, *fd)?;
+            }
+        }
+        for fd in &peer.vectors {
+            send(&peer.sock, peer.id as i64, *fd)?;
+        }
+        Ok(())
+    }
+
+    fn remove_peer(&self, id: u16) {
+        let mut peers = self.peers.lock().unwrap();
+        let index = match peers.list.iter().position(|peer| peer.id == id) {
+            Some(index) => index,
+            None => return,
+        };
+        let peer = peers.list.remove(index);
+        peer.close();
+        // An id without an fd tells the others that the peer is gone
+        for other in &peers.list {
+            let _ = send(&other.sock, id as i64, -1);
+        }
+    }
+}
+
+impl Peers {
+    /// The lowest id not taken, so that a peer that comes back takes the place of the one before.
+    fn free_id(&self) -> Option<u16> {
+        (0..=u16::max_value()).find(|id| self.list.iter().all(|peer| peer.id != *id))
+    }
+}
+
+impl Peer {
+    /// Closes the eventfds, the socket closes when the peer is dropped.
+    fn close(&self) {
+        for fd in &self.vectors {
+            let _ = close(*fd);
+        }
+    }
+}
@@ -0,0 +1,567 @@
+use fnv::FnvHashMap;
+use ivshrpc::*;
+use ringbuf::{RecvError, TimedOut};
+use sos::{
+    DecodeLimits, EncodedValues, ErrorCode, JustError, OwnedEncodedValues, ReferencedValues, SOS,
+    SOS_VERSION,
+};
+use spin;
+use std::cmp::min;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use threadpool::ThreadPool;
+
+/// Runs a call from the other end, a fuse if the flag is set. The result of a cast is dropped.
+pub type Dispatcher =
+    fn(PeerId, OwnedEncodedValues, bool) -> Result<OwnedEncodedValues, JustError<'static>>;
+
+type CallResult = Arc<(
+    Mutex<Option<Result<OwnedEncodedValues, OwnedEncodedValues>>>,
+    Condvar,
+)>;
+
+/// A fuse from the other end, from when it comes in until its result is sent back.
+struct Incoming {
+    cancelled: AtomicBool,
+    deadline: Option<Instant>,
+}
+
+impl Incoming {
+    /// Whether the caller stopped waiting for the result.
+    fn given_up(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || self
+                .deadline
+                .map_or(false, |deadline| Instant::now() >= deadline)
+    }
+}
+
+/// How far the handshake with the other end got. Calls go either way only once it is agreed.
+enum Handshake {
+    Pending,
+    Agreed(Agreement),
+    /// The other end speaks a protocol we don't, until it starts over.
+    Refused,
+}
+
+/// One end of an ivshrpc channel. Calls made through it wait here for their results, calls from
+/// the other end are handed to the dispatcher on a pool of workers.
+pub struct Endpoint<T> {
+    /// The other end, as the dispatcher and the logs know it.
+    peer: PeerId,
+    /// Who we are to the other end, sent in the handshake.
+    name: &'static str,
+    transport: T,
+    dispatch: Dispatcher,
+    /// Limits on the SOS payloads the other end sends us.
+    limits: DecodeLimits,
+    /// Our epoch on the transport, held to send and exclusively to start over.
+    epoch: RwLock<usize>,
+    handshake: Mutex<Handshake>,
+    handshaken: Condvar,
+    calls: spin::Mutex<FnvHashMap<CallId, CallResult>>,
+    incoming: spin::Mutex<FnvHashMap<CallId, Arc<Incoming>>>,
+    call_id: AtomicUsize,
+    pool: spin::Mutex<ThreadPool>,
+}
+
+impl<T: Transport + Send + Sync + 'static> Endpoint<T> {
+    /// An endpoint named `name` at `epoch` on `transport` to `peer`, running calls on `workers`
+    /// threads.
+    pub fn new(
+        peer: PeerId,
+        name: &'static str,
+        transport: T,
+        epoch: usize,
+        dispatch: Dispatcher,
+        limits: DecodeLimits,
+        workers: usize,
+    ) -> Self {
+        Endpoint {
+            peer,
+            name,
+            transport,
+            dispatch,
+            limits,
+            epoch: RwLock::new(epoch),
+            handshake: Mutex::new(Handshake::Pending),
+            handshaken: Condvar::new(),
+            calls: spin::Mutex::new(FnvHashMap::default()),
+            incoming: spin::Mutex::new(FnvHashMap::default()),
+            call_id: AtomicUsize::new(0),
+            pool: spin::Mutex::new(ThreadPool::new(workers)),
+        }
+    }
+
+    pub fn peer(&self) -> PeerId {
+        self.peer
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// What we tell the other end about ourselves.
+    fn hello(&self) -> Hello<'static> {
+        Hello {
+            version: PROTOCOL_VERSION,
+            sos_version: SOS_VERSION,
+            max_frame: min(
+                self.transport.max_frame(),
+                IVSHRPC_HEADER_SIZE + self.limits.max_bytes,
+            ) as u32,
+            features: FEATURE_CANCEL,
+            name: self.name,
+        }
+    }
+
+    /// What the handshake settled on, if it is done.
+    pub fn agreement(&self) -> Option<Agreement> {
+        match *self.handshake.lock().unwrap() {
+            Handshake::Agreed(agreement) => Some(agreement),
+            _ => None,
+        }
+    }
+
+    /// Greets the other end and waits up to `timeout` for it to answer, failing with
+    /// `ErrorCode::TRANSPORT` if it refuses. Only one end greets, the other answers.
+    pub fn handshake(&self, timeout: Duration) -> Result<Agreement, OwnedEncodedValues> {
+        if self.send_hello(MsgType::Hello, None).is_err() {
+            return Err(fault(ErrorCode::TRANSPORT, "Timed out sending the hello"));
+        }
+        let deadline = Instant::now() + timeout;
+        let mut state = self.handshake.lock().unwrap();
+        loop {
+            state = match *state {
+                Handshake::Agreed(agreement) => return Ok(agreement),
+                Handshake::Refused => {
+                    return Err(fault(
+                        ErrorCode::TRANSPORT,
+                        "The other end speaks another protocol",
+                    ))
+                }
+                Handshake::Pending => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(fault(
+                            ErrorCode::TIMEOUT,
+                            "Timed out waiting for the other end to answer the hello",
+                        ));
+                    }
+                    self.handshaken
+                        .wait_timeout(state, deadline - now)
+                        .unwrap()
+                        .0
+                }
+            };
+        }
+    }
+
+    /// Whether a message with `len` bytes of payload can go to the other end, as far as the
+    /// handshake goes.
+    fn admit(&self, len: usize) -> Result<(), OwnedEncodedValues> {
+        match self.agreement() {
+            None => Err(fault(
+                ErrorCode::TRANSPORT,
+                "The handshake with the other end has not finished",
+            )),
+            Some(agreement) if IVSHRPC_HEADER_SIZE + len > agreement.max_frame as usize => Err(
+                fault(ErrorCode::TOO_LARGE, "The message does not fit in a frame"),
+            ),
+            Some(_) => Ok(()),
+        }
+    }
+
+    pub fn cast<A: SOS>(&self, args: A) -> Result<(), OwnedEncodedValues> {
+        self.admit(args.encoded_len())?;
+        let callid = self.call_id.fetch_add(1, Ordering::Relaxed);
+        self.send(args, MsgHeader::new(MsgType::Cast, callid as u64), None)
+            .map_err(|_| fault(ErrorCode::TRANSPORT, "Timed out sending the call"))
+    }
+
+    /// Calls a function on the other end and waits for its result, failing the call with
+    /// `ErrorCode::TIMEOUT` if it takes longer than `timeout`.
+    pub fn fuse<A: SOS>(
+        &self,
+        args: A,
+        timeout: Option<Duration>,
+    ) -> Result<OwnedEncodedValues, OwnedEncodedValues> {
+        self.admit(args.encoded_len())?;
+        let callid = self.call_id.fetch_add(1, Ordering::Relaxed);
+        // Queued first, so that the call fails along with the others if the other end goes away
+        let entry = self
+            .calls
+            .lock()
+            .entry(callid as u64)
+            .or_insert(Arc::new((Mutex::new(None), Condvar::new())))
+            .clone();
+        if self
+            .send(
+                args,
+                MsgHeader::new(MsgType::Fuse, callid as u64).with_timeout(timeout),
+                None,
+            )
+            .is_err()
+        {
+            self.calls.lock().remove(&(callid as u64));
+            return Err(fault(ErrorCode::TRANSPORT, "Timed out sending the call"));
+        }
+
+        let (lock, var) = entry.deref();
+        let mut deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut res = lock.lock().unwrap();
+        while res.is_none() {
+            res = match deadline {
+                None => var.wait(res).unwrap(),
+                Some(at) => {
+                    let now = Instant::now();
+                    if now < at {
+                        var.wait_timeout(res, at - now).unwrap().0
+                    } else if self.calls.lock().remove(&(callid as u64)).is_some() {
+                        drop(res);
+                        return Err(self.time_out(callid as u64));
+                    } else {
+                        // The result is on its way already
+                        deadline = None;
+                        res
+                    }
+                }
+            };
+        }
+        res.take().unwrap()
+    }
+
+    /// Cancels a fuse that took too long on the other end, returning the error it fails with.
+    fn time_out(&self, callid: CallId) -> OwnedEncodedValues {
+        println!("Peer {}: Call {} timed out", self.peer, callid);
+        let cancels = self
+            .agreement()
+            .map_or(false, |agreement| agreement.features & FEATURE_CANCEL != 0);
+        if cancels
+            && self
+                .send(sos!(), MsgHeader::new(MsgType::Cancel, callid), None)
+                .is_err()
+        {
+            println!(
+                "Peer {}: Failed to cancel call {}, the other end is not reading",
+                self.peer, callid
+            );
+        }
+        fault(ErrorCode::TIMEOUT, "Timed out waiting for the result")
+    }
+
+    /// Sends a message to the other end. A reply passes the epoch its call came in on, it is
+    /// dropped if we started over since as whoever made the call is gone.
+    fn send<A: SOS>(
+        &self,
+        args: A,
+        header: MsgHeader,
+        epoch: Option<usize>,
+    ) -> Result<(), TimedOut> {
+        self.send_with(
+            header,
+            args.encoded_len(),
+            |payload| {
+                args.encode(payload);
+            },
+            epoch,
+        )
+    }
+
+    /// Sends a message with `len` bytes of payload, which `fill` writes in place.
+    fn send_with<F: FnOnce(&mut [u8])>(
+        &self,
+        mut header: MsgHeader,
+        len: usize,
+        fill: F,
+        epoch: Option<usize>,
+    ) -> Result<(), TimedOut> {
+        let current = self.epoch.read().unwrap();
+        if epoch.map_or(false, |epoch| epoch != *current) {
+            let callid = header.callid;
+            println!(
+                "Peer {}: Dropped reply to call {}, the other end started over",
+                self.peer, callid
+            );
+            return Ok(());
+        }
+        header.length = len as u32;
+        self.transport.send(IVSHRPC_HEADER_SIZE + len, |frame| {
+            frame[..IVSHRPC_HEADER_SIZE].copy_from_slice(&header.to_bytes());
+            fill(&mut frame[IVSHRPC_HEADER_SIZE..]);
+        })
+    }
+
+    fn send_hello(&self, msgtype: MsgType, epoch: Option<usize>) -> Result<(), TimedOut> {
+        let hello = self.hello();
+        self.send_with(
+            MsgHeader::new(msgtype, 0),
+            hello.encoded_len(),
+            |payload| hello.encode(payload),
+            epoch,
+        )
+    }
+
+    /// Sends a message nobody waits on the outcome of, so a failure can only be logged.
+    fn reply<A: SOS>(&self, args: A, header: MsgHeader, epoch: usize) {
+        let callid = header.callid;
+        let sent = match self.admit(args.encoded_len()) {
+            // A result too large to send back fails the call instead
+            Err(err) if header.msgtype == MsgType::Return as u8 => self.send(
+                EncodedValues::from(err),
+                MsgHeader::new(MsgType::Error, callid),
+                Some(epoch),
+            ),
+            _ => self.send(args, header, Some(epoch)),
+        };
+        if sent.is_err() {
+            println!(
+                "Peer {}: Dropped reply to call {}, the other end is not reading",
+                self.peer, callid
+            );
+        }
+    }
+
+    /// Handles messages from the other end until the transport gives up waiting for the next one,
+    /// returning why it did. Corrupted frames call for starting over.
+    pub fn listen(endpoint: &Arc<Self>) -> RecvError {
+        // Starting over happens between listens, never during one
+        let epoch = *endpoint.epoch.read().unwrap();
+        loop {
+            if let Err(e) = endpoint
+                .transport
+                .recv(|frame| Self::handle(endpoint, frame, epoch))
+            {
+                return e;
+            }
+        }
+    }
+
+    fn handle(endpoint: &Arc<Self>, frame: &[u8], epoch: usize) {
+        if frame.len() < IVSHRPC_HEADER_SIZE {
+            println!(
+                "Peer {}: Dropped a frame too short for a header",
+                endpoint.peer
+            );
+            return;
+        }
+        let header = MsgHeader::from_slice(&frame[..IVSHRPC_HEADER_SIZE]);
+        let payload = &frame[IVSHRPC_HEADER_SIZE..];
+
+        let callid = header.callid;
+        let msgtype = match MsgType::from_u8(header.msgtype) {
+            Some(msgtype) => msgtype,
+            None => {
+                return println!(
+                    "Peer {}: Dropped call {} of unknown type {}",
+                    endpoint.peer, callid, header.msgtype
+                )
+            }
+        };
+        if payload.len() > endpoint.limits.max_bytes {
+            endpoint.reject(
+                msgtype,
+                callid,
+                JustError::with_code(ErrorCode::TOO_LARGE, "Message exceeds decode limits"),
+                epoch,
+            );
+            return;
+        }
+
+        let values = EncodedValues::from(payload);
+
+        match msgtype {
+            MsgType::Fuse | MsgType::Cast if endpoint.agreement().is_none() => endpoint.reject(
+                msgtype,
+                callid,
+                JustError::with_code(ErrorCode::TRANSPORT, "Call before the handshake"),
+                epoch,
+            ),
+            MsgType::Fuse | MsgType::Cast => {
+                let call = if msgtype == MsgType::Fuse {
+                    Some(endpoint.track(callid, header.timeout()))
+                } else {
+                    None
+                };
+                let pool = endpoint.pool.lock();
+                let owned_values = values.into_owned();
+                let endpoint = endpoint.clone();
+                pool.execute(move || {
+                    if call.as_ref().map_or(false, |call| call.given_up()) {
+                        endpoint.untrack(callid, call.as_ref().unwrap());
+                        return println!(
+                            "Peer {}: Dropped call {}, its caller gave up on it",
+                            endpoint.peer, callid
+                        );
+                    }
+                    let result =
+                        (endpoint.dispatch)(endpoint.peer, owned_values, msgtype == MsgType::Fuse);
+                    if let Some(call) = call {
+                        endpoint.untrack(callid, &call);
+                        if call.given_up() {
+                            return println!(
+                                "Peer {}: Dropped the result of call {}, its caller gave up",
+                                endpoint.peer, callid
+                            );
+                        }
+                    }
+                    match result {
+                        Ok(val) => {
+                            if msgtype == MsgType::Fuse {
+                                endpoint.reply(
+                                    EncodedValues::from(val),
+                                    MsgHeader::new(MsgType::Return, callid),
+                                    epoch,
+                                );
+                            }
+                        }
+                        Err(err) => {
+                            endpoint.reply(err, MsgHeader::new(MsgType::Error, callid), epoch)
+                        }
+                    }
+                });
+            }
+            MsgType::Error | MsgType::Return => {
+                // Fuse and cast arguments are validated by the dispatcher, results only here
+                if let Err(e) = values.validate_limited(endpoint.limits) {
+                    endpoint.reject(msgtype, callid, e.into(), epoch);
+                } else if msgtype == MsgType::Error {
+                    endpoint.deliver(callid, Err(values.into_owned()));
+                } else {
+                    endpoint.deliver(callid, Ok(values.into_owned()));
+                }
+            }
+            MsgType::Cancel => endpoint.cancel(callid),
+            MsgType::Hello | MsgType::HelloAck => endpoint.greet(msgtype, payload, epoch),
+        };
+    }
+
+    /// Takes in the other end's side of the handshake, answering a hello with ours.
+    fn greet(&self, msgtype: MsgType, payload: &[u8], epoch: usize) {
+        let ours = self.hello();
+        let agreement = match Hello::decode(payload) {
+            Some(theirs) => {
+                println!("Peer {}: Hello from {}", self.peer, theirs.name);
+                ours.agree(&theirs)
+                    .map_err(|e| println!("Peer {}: Refused the handshake: {:?}", self.peer, e))
+                    .ok()
+            }
+            None => {
+                println!("Peer {}: Refused a malformed hello", self.peer);
+                None
+            }
+        };
+        *self.handshake.lock().unwrap() = agreement.map_or(Handshake::Refused, Handshake::Agreed);
+        self.handshaken.notify_all();
+
+        // Answered either way, so that the other end finds out it was refused
+        if msgtype == MsgType::Hello && self.send_hello(MsgType::HelloAck, Some(epoch)).is_err() {
+            println!(
+                "Peer {}: Failed to answer the hello, the other end is not reading",
+                self.peer
+            );
+        }
+    }
+
+    /// Keeps track of a fuse from the other end until its result is sent back.
+    fn track(&self, callid: CallId, timeout: Option<Duration>) -> Arc<Incoming> {
+        let call = Arc::new(Incoming {
+            cancelled: AtomicBool::new(false),
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+        });
+        self.incoming.lock().insert(callid, call.clone());
+        call
+    }
+
+    fn untrack(&self, callid: CallId, call: &Arc<Incoming>) {
+        let mut incoming = self.incoming.lock();
+        // Once we start over the id may belong to a call from after
+        if incoming
+            .get(&callid)
+            .map_or(false, |other| Arc::ptr_eq(other, call))
+        {
+            incoming.remove(&callid);
+        }
+    }
+
+    /// Flags a fuse from the other end as given up on, so that it is dropped rather than run or
+    /// answered.
+    fn cancel(&self, callid: CallId) {
+        match self.incoming.lock().get(&callid) {
+            Some(call) => call.cancelled.store(true, Ordering::Relaxed),
+            None => println!(
+                "Peer {}: Nothing to cancel for call {}, it is done with",
+                self.peer, callid
+            ),
+        }
+    }
+
+    /// Wakes up the caller waiting in `fuse`.
+    fn deliver(&self, callid: CallId, result: Result<OwnedEncodedValues, OwnedEncodedValues>) {
+        // A call that was failed already when the other end stopped responding
+        let entry = match self.calls.lock().remove(&callid) {
+            Some(entry) => entry,
+            None => {
+                return println!(
+                    "Peer {}: Dropped the result of call {}, nobody waits for it",
+                    self.peer, callid
+                )
+            }
+        };
+        complete(&entry, result);
+    }
+
+    /// Fails every call waiting on the other end.
+    pub fn fail_calls(&self, reason: &str) {
+        let err = fault(ErrorCode::TRANSPORT, reason);
+        let calls: Vec<_> = self.calls.lock().drain().collect();
+        for (callid, entry) in calls {
+            println!("Peer {}: Failed call {}: {}", self.peer, callid, reason);
+            complete(&entry, Err(err.clone()));
+        }
+    }
+
+    /// Moves to the epoch `setup` returns once it has set the transport up afresh, failing the
+    /// calls made before. Replies to calls from before are dropped from then on.
+    pub fn start_over<F: FnOnce() -> usize>(&self, reason: &str, setup: F) {
+        {
+            let mut epoch = self.epoch.write().unwrap();
+            *epoch = setup();
+            // The other end greets us again once it sees the new epoch
+            *self.handshake.lock().unwrap() = Handshake::Pending;
+        }
+        // Nobody waits for the calls from before anymore
+        for (_, call) in self.incoming.lock().drain() {
+            call.cancelled.store(true, Ordering::Relaxed);
+        }
+        self.fail_calls(reason);
+    }
+
+    /// Answers a message whose payload was refused in place of whoever expected its result.
+    fn reject(&self, msgtype: MsgType, callid: CallId, err: JustError, epoch: usize) {
+        match msgtype {
+            MsgType::Error | MsgType::Return => self.deliver(
+                callid,
+                Err(EncodedValues::from(ReferencedValues(&err.values())).into_owned()),
+            ),
+            MsgType::Fuse | MsgType::Cast => {
+                self.reply(err, MsgHeader::new(MsgType::Error, callid), epoch)
+            }
+            // Nobody waits on a cancel or a handshake
+            MsgType::Cancel | MsgType::Hello | MsgType::HelloAck => (),
+        }
+    }
+}
+
+fn fault(code: ErrorCode, message: &str) -> OwnedEncodedValues {
+    let err = JustError::with_code(code, message);
+    EncodedValues::from(ReferencedValues(&err.values())).into_owned()
+}
+
+fn complete(entry: &CallResult, result: Result<OwnedEncodedValues, OwnedEncodedValues>) {
+    let (lock, var) = entry.deref();
+    *lock.lock().unwrap() = Some(result);
+    var.notify_all();
+}
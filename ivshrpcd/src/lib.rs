@@ -0,0 +1,13 @@
+//! The call logic of ivshrpcd, kept apart from the ivshmem plumbing so that it runs over any
+//! `Transport`.
+extern crate fnv;
+extern crate ivshrpc;
+extern crate ringbuf;
+#[macro_use]
+extern crate sos;
+extern crate spin;
+extern crate threadpool;
+
+mod endpoint;
+
+pub use endpoint::{Dispatcher, Endpoint};
@@ -0,0 +1,60 @@
+extern crate ivshrpc;
+
+use ivshrpc::*;
+
+fn hello(name: &'static str) -> Hello<'static> {
+    Hello {
+        version: PROTOCOL_VERSION,
+        sos_version: 1,
+        max_frame: 4096,
+        features: FEATURE_CANCEL,
+        name,
+    }
+}
+
+#[test]
+fn round_trip() {
+    let ours = hello("ivshrpcd");
+    let mut buf = vec![0; ours.encoded_len()];
+    ours.encode(&mut buf);
+    assert_eq!(Hello::decode(&buf), Some(ours));
+}
+
+#[test]
+fn too_short() {
+    assert_eq!(Hello::decode(&[0; HELLO_SIZE - 1]), None);
+}
+
+#[test]
+fn agree() {
+    let ours = hello("host");
+    let theirs = Hello {
+        max_frame: 1024,
+        features: 0,
+        ..hello("guest")
+    };
+    let agreement = Agreement {
+        max_frame: 1024,
+        features: 0,
+    };
+    assert_eq!(ours.agree(&theirs), Ok(agreement));
+    assert_eq!(theirs.agree(&ours), Ok(agreement));
+}
+
+#[test]
+fn mismatch() {
+    let ours = hello("host");
+    let theirs = Hello {
+        version: PROTOCOL_VERSION + 1,
+        ..hello("guest")
+    };
+    assert_eq!(
+        ours.agree(&theirs),
+        Err(Mismatch::Version(PROTOCOL_VERSION + 1))
+    );
+    let theirs = Hello {
+        sos_version: 2,
+        ..hello("guest")
+    };
+    assert_eq!(ours.agree(&theirs), Err(Mismatch::SosVersion(2)));
+}
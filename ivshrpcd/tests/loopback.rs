@@ -0,0 +1,269 @@
+extern crate ivshrpc;
+extern crate ivshrpcd;
+#[macro_use]
+extern crate sos;
+
+use ivshrpc::{
+    loopback, Loopback, MsgHeader, MsgType, PeerId, Transport, FEATURE_CANCEL, IVSHRPC_HEADER_SIZE,
+};
+use ivshrpcd::{Dispatcher, Endpoint};
+use sos::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const LIMITS: DecodeLimits = DecodeLimits {
+    max_depth: 16,
+    max_values: 4096,
+    max_bytes: 1024 * 1024,
+};
+
+type Pair = (Arc<Endpoint<Loopback>>, Arc<Endpoint<Loopback>>);
+
+/// Two endpoints talking over a loopback with `workers` each, each listening on a thread of its
+/// own. They have yet to shake hands.
+fn strangers(a: Dispatcher, b: Dispatcher, workers: usize) -> Pair {
+    let (a_end, b_end) = loopback(4096, Duration::from_millis(10));
+    let a = Arc::new(Endpoint::new(2, "a", a_end, 1, a, LIMITS, workers));
+    let b = Arc::new(Endpoint::new(1, "b", b_end, 1, b, LIMITS, workers));
+    for endpoint in &[a.clone(), b.clone()] {
+        let endpoint = endpoint.clone();
+        thread::spawn(move || loop {
+            Endpoint::listen(&endpoint);
+        });
+    }
+    (a, b)
+}
+
+/// Two endpoints done with the handshake, `a` having greeted `b`.
+fn pair_with(a: Dispatcher, b: Dispatcher, workers: usize) -> Pair {
+    let (a, b) = strangers(a, b, workers);
+    a.handshake(Duration::from_secs(5)).unwrap();
+    (a, b)
+}
+
+fn pair(a: Dispatcher, b: Dispatcher) -> Pair {
+    pair_with(a, b, 2)
+}
+
+fn unreachable(
+    _: PeerId,
+    _: OwnedEncodedValues,
+    _: bool,
+) -> Result<OwnedEncodedValues, JustError<'static>> {
+    panic!("Nothing should be called on this end")
+}
+
+fn echo(
+    _: PeerId,
+    args: OwnedEncodedValues,
+    fuse: bool,
+) -> Result<OwnedEncodedValues, JustError<'static>> {
+    assert!(fuse);
+    Ok(args)
+}
+
+fn refuse(
+    _: PeerId,
+    _: OwnedEncodedValues,
+    _: bool,
+) -> Result<OwnedEncodedValues, JustError<'static>> {
+    Err(JustError::with_code(
+        ErrorCode::NO_SUCH_FUNCTION,
+        "No such function",
+    ))
+}
+
+/// The code of the fault a call failed with.
+fn fault_code(err: &[u8]) -> u32 {
+    match EncodedValues::from(err).decode().unwrap().next() {
+        Some(Value::Fault(fault)) => fault.code.0,
+        _ => panic!("The call did not fail with a fault"),
+    }
+}
+
+static CASTS: AtomicUsize = AtomicUsize::new(0);
+
+fn count(
+    _: PeerId,
+    args: OwnedEncodedValues,
+    fuse: bool,
+) -> Result<OwnedEncodedValues, JustError<'static>> {
+    assert!(!fuse);
+    let args = EncodedValues::from(args);
+    let mut iter = args.decode().unwrap();
+    let n: u64 = iter.next_as().unwrap();
+    CASTS.fetch_add(n as usize, Ordering::SeqCst);
+    Ok(EncodedValues::from(sos!()).into_owned())
+}
+
+#[test]
+fn cast() {
+    let (a, _b) = pair(unreachable, count);
+    for _ in 0..10 {
+        a.cast(sos![3u64]).unwrap();
+    }
+    for _ in 0..1000 {
+        if CASTS.load(Ordering::SeqCst) == 30 {
+            return;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    panic!("Casts did not all arrive");
+}
+
+#[test]
+fn fuse_returns() {
+    let (a, _b) = pair(unreachable, echo);
+    let ret = a.fuse(sos!["ping", 7u64], None).unwrap();
+    let ret = EncodedValues::from(ret);
+    let mut iter = ret.decode().unwrap();
+    assert_eq!(iter.next_as::<&str>().unwrap(), "ping");
+    assert_eq!(iter.next_as::<u64>().unwrap(), 7);
+}
+
+#[test]
+fn fuse_both_ways() {
+    let (a, b) = pair(echo, echo);
+    assert!(a.fuse(sos![1u64], None).is_ok());
+    assert!(b.fuse(sos![2u64], None).is_ok());
+}
+
+#[test]
+fn fuse_error() {
+    let (a, _b) = pair(unreachable, refuse);
+    let err = a.fuse(sos!["anything"], None).unwrap_err();
+    assert_eq!(fault_code(&err), ErrorCode::NO_SUCH_FUNCTION.0);
+}
+
+#[test]
+fn cast_error_is_dropped() {
+    let (a, _b) = pair(unreachable, refuse);
+    a.cast(sos!["anything"]).unwrap();
+    // The error reply has no caller waiting on it, the next call still works
+    let err = a.fuse(sos!["anything"], None).unwrap_err();
+    assert_eq!(fault_code(&err), ErrorCode::NO_SUCH_FUNCTION.0);
+}
+
+fn stall(
+    _: PeerId,
+    args: OwnedEncodedValues,
+    _: bool,
+) -> Result<OwnedEncodedValues, JustError<'static>> {
+    thread::sleep(Duration::from_millis(500));
+    Ok(args)
+}
+
+#[test]
+fn start_over_fails_calls() {
+    // The other end takes its time, so the call waits until we start over
+    let (a, _b) = pair(unreachable, stall);
+    let caller = {
+        let a = a.clone();
+        thread::spawn(move || a.fuse(sos!["stuck"], None))
+    };
+    thread::sleep(Duration::from_millis(50));
+    a.start_over("The other end started over", || 2);
+    let err = caller.join().unwrap().unwrap_err();
+    assert_eq!(fault_code(&err), ErrorCode::TRANSPORT.0);
+    // The other end has to greet us again
+    assert_eq!(a.agreement(), None);
+}
+
+static SLOW_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+fn slow(
+    _: PeerId,
+    args: OwnedEncodedValues,
+    fuse: bool,
+) -> Result<OwnedEncodedValues, JustError<'static>> {
+    assert!(fuse);
+    SLOW_RUNS.fetch_add(1, Ordering::SeqCst);
+    thread::sleep(Duration::from_millis(100));
+    Ok(args)
+}
+
+#[test]
+fn fuse_times_out() {
+    // A single worker on the other end, busy with the first call while the second one waits
+    let (a, _b) = pair_with(unreachable, slow, 1);
+
+    let first = {
+        let a = a.clone();
+        thread::spawn(move || a.fuse(sos![1u64], Some(Duration::from_millis(20))))
+    };
+    thread::sleep(Duration::from_millis(5));
+    let second = a.fuse(sos![2u64], Some(Duration::from_millis(20)));
+    for res in vec![first.join().unwrap(), second] {
+        assert_eq!(fault_code(&res.unwrap_err()), ErrorCode::TIMEOUT.0);
+    }
+
+    // The cancelled call is dropped before it runs, the late result of the first one too
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(SLOW_RUNS.load(Ordering::SeqCst), 1);
+    let ret = a.fuse(sos![3u64], Some(Duration::from_secs(5))).unwrap();
+    let ret = EncodedValues::from(ret);
+    assert_eq!(ret.decode().unwrap().next_as::<u64>().unwrap(), 3);
+}
+
+fn whoami(
+    peer: PeerId,
+    _: OwnedEncodedValues,
+    _: bool,
+) -> Result<OwnedEncodedValues, JustError<'static>> {
+    Ok(EncodedValues::from(sos![peer as u64]).into_owned())
+}
+
+#[test]
+fn dispatcher_knows_the_caller() {
+    let (a, b) = pair(whoami, whoami);
+    for (endpoint, caller) in vec![(a, 1), (b, 2)] {
+        let ret = EncodedValues::from(endpoint.fuse(sos![], None).unwrap());
+        assert_eq!(ret.decode().unwrap().next_as::<u64>().unwrap(), caller);
+    }
+}
+
+#[test]
+fn handshake_agrees() {
+    let (a, b) = pair(unreachable, unreachable);
+    let agreement = a.agreement().unwrap();
+    assert_eq!(b.agreement(), Some(agreement));
+    assert_eq!(agreement.max_frame as usize, a.transport().max_frame());
+    assert_eq!(agreement.features, FEATURE_CANCEL);
+}
+
+#[test]
+fn calls_before_the_handshake_fail() {
+    let (a, b) = strangers(unreachable, unreachable, 1);
+    assert_eq!(
+        fault_code(&a.cast(sos![1u64]).unwrap_err()),
+        ErrorCode::TRANSPORT.0
+    );
+    let err = b.fuse(sos![2u64], None).unwrap_err();
+    assert_eq!(fault_code(&err), ErrorCode::TRANSPORT.0);
+}
+
+#[test]
+fn call_too_large_for_a_frame() {
+    let (a, _b) = pair(unreachable, unreachable);
+    let big = "x".repeat(a.transport().max_frame());
+    let err = a.fuse(sos![&big[..]], None).unwrap_err();
+    assert_eq!(fault_code(&err), ErrorCode::TOO_LARGE.0);
+}
+
+#[test]
+fn unknown_msgtype_is_dropped() {
+    let (a, _b) = pair(unreachable, echo);
+    let mut header = MsgHeader::new(MsgType::Cast, 7);
+    header.msgtype = 0xff;
+    a.transport()
+        .send(IVSHRPC_HEADER_SIZE, |frame| {
+            frame.copy_from_slice(&header.to_bytes())
+        })
+        .unwrap();
+    // The other end still listens
+    let ret = a.fuse(sos![5u64], Some(Duration::from_secs(5))).unwrap();
+    let ret = EncodedValues::from(ret);
+    assert_eq!(ret.decode().unwrap().next_as::<u64>().unwrap(), 5);
+}
@@ -52,6 +52,8 @@ interrupt!(pit, {
 
     pic::MASTER.ack();
 
+    devices::ivshmem::heartbeat();
+
     if PIT_TICKS.fetch_add(1, Ordering::SeqCst) >= 10 {
         let _ = context::switch();
     }
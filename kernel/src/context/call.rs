@@ -1,5 +1,6 @@
 use super::memory::ContextMemory;
-use super::{FuncPtr, Module, ModuleFuncPtr, SharedModule};
+use super::memo::{self, MemoKey};
+use super::{FuncPtr, FunctionKind, Module, ModuleFuncPtr, SharedModule};
 use alloc::boxed::Box;
 use alloc::string::String;
 use alloc::vec::Vec;
@@ -12,7 +13,8 @@ use error::*;
 use memory::{allocate_frames, EntryFlags, PAGE_SIZE};
 use paging::temporary_page::TemporaryPage;
 use paging::{ActivePageTable, InactivePageTable, Page, VirtualAddress};
-use sos::{EncodedValues, SOS};
+use core::result;
+use sos::{EncodedValues, ErrorCode, JustError, SOS};
 
 pub fn spawn_kernel() -> Result<'static, Context> {
     let mut context = Context::new(context::KERNEL_MODULE.clone());
@@ -198,20 +200,45 @@ pub fn spawn(module: SharedModule) -> Result<'static, Context> {
     Ok(context)
 }
 
+/// Failures to set up a call are out of resources, the callee was found.
+fn no_resources(e: &'static str) -> JustError<'static> {
+    JustError::with_code(ErrorCode::RESOURCES, e)
+}
+
 pub fn fuse_name<'a, S: SOS>(
     module: SharedModule,
     func: &str,
     args: &S,
-) -> Result<'static, EncodedValues<'a>> {
-    let f = module.function(func).ok_or("Function not found")?;
-    let mut context = spawn(module)?;
+) -> result::Result<EncodedValues<'a>, JustError<'static>> {
+    let f = module.function(func).ok_or(JustError::with_code(
+        ErrorCode::NO_SUCH_FUNCTION,
+        "Function not found",
+    ))?;
+    let key = match module.function_kind(func) {
+        Some(FunctionKind::Pure) => {
+            let key = MemoKey::new(module.name(), func, args);
+            if let Some(result) = memo::lookup(&key) {
+                return Ok(EncodedValues::from(result));
+            }
+            Some(key)
+        }
+        _ => None,
+    };
+    let mut context = spawn(module).map_err(no_resources)?;
     context.name = Some(String::from(func));
-    fuse_inner(context, f, args)
+    let result = fuse_inner(context, f, args).map_err(no_resources)?;
+    if let Some(key) = key {
+        memo::insert(key, &result);
+    }
+    Ok(result)
 }
 
-pub fn fuse_ptr<'a, S: SOS>(func: FuncPtr, args: &S) -> Result<'static, EncodedValues<'a>> {
-    let context = spawn(func.0)?;
-    fuse_inner(context, func.1, args)
+pub fn fuse_ptr<'a, S: SOS>(
+    func: FuncPtr,
+    args: &S,
+) -> result::Result<EncodedValues<'a>, JustError<'static>> {
+    let context = spawn(func.0).map_err(no_resources)?;
+    fuse_inner(context, func.1, args).map_err(no_resources)
 }
 
 fn fuse_inner<'a, S: SOS>(
@@ -253,16 +280,22 @@ pub fn cast_name<S: SOS>(
     module: SharedModule,
     func: &str,
     args: &S,
-) -> Result<'static, SharedContext> {
-    let f = module.function(func).ok_or("Function not found")?;
-    let mut context = spawn(module)?;
+) -> result::Result<SharedContext, JustError<'static>> {
+    let f = module.function(func).ok_or(JustError::with_code(
+        ErrorCode::NO_SUCH_FUNCTION,
+        "Function not found",
+    ))?;
+    let mut context = spawn(module).map_err(no_resources)?;
     context.name = Some(String::from(func));
-    cast_inner(context, f, args)
+    cast_inner(context, f, args).map_err(no_resources)
 }
 
-pub fn cast_ptr<S: SOS>(func: FuncPtr, args: &S) -> Result<'static, SharedContext> {
-    let context = spawn(func.0)?;
-    cast_inner(context, func.1, args)
+pub fn cast_ptr<S: SOS>(
+    func: FuncPtr,
+    args: &S,
+) -> result::Result<SharedContext, JustError<'static>> {
+    let context = spawn(func.0).map_err(no_resources)?;
+    cast_inner(context, func.1, args).map_err(no_resources)
 }
 
 fn cast_inner<S: SOS>(
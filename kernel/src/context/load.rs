@@ -1,3 +1,4 @@
+use super::memo;
 use super::memory::ContextMemory;
 use alloc::string::String;
 use alloc::sync::Arc;
@@ -33,11 +34,25 @@ pub type ModuleFuncPtr = usize;
 pub type FuncPtr = (SharedModule, ModuleFuncPtr);
 pub type SharedModule = Arc<Module>;
 
+/// Whether calls to a function can be answered from earlier results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionKind {
+    /// Returns the same values for the same arguments and has no other effects.
+    Pure,
+    Procedural,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Symbol {
+    offset: ModuleFuncPtr,
+    kind: FunctionKind,
+}
+
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct Module {
     name: String,
-    func_table: FnvHashMap<String, ModuleFuncPtr>,
+    func_table: FnvHashMap<String, Symbol>,
     pub image: Vec<ContextMemory>,
     actions: FnvHashMap<usize, ModuleFuncPtr>,
     env: FnvHashMap<String, Vec<u8>>,
@@ -54,10 +69,17 @@ impl Module {
     }
 
     pub fn function(&self, name: &str) -> Option<ModuleFuncPtr> {
-        Some(*self.func_table.get(name)?)
+        Some(self.func_table.get(name)?.offset)
+    }
+
+    pub fn function_kind(&self, name: &str) -> Option<FunctionKind> {
+        Some(self.func_table.get(name)?.kind)
     }
 }
 
+/// `Kind` of a pure function in the manifest, anything else is procedural.
+const MANIFEST_KIND_PURE: usize = 1;
+
 #[derive(Deserialize, Debug)]
 struct SymbolTableEntry<'a> {
     #[serde(rename = "Name")]
@@ -68,6 +90,9 @@ struct SymbolTableEntry<'a> {
     visibility: usize,
     #[serde(rename = "ABI")]
     abi: usize,
+    /// Missing from manifests written before functions had a kind.
+    #[serde(rename = "Kind", default)]
+    kind: usize,
 }
 
 #[derive(Deserialize, Debug)]
@@ -109,7 +134,15 @@ pub fn load(data: &[u8]) -> Result<'static, Module> {
     let mut func_table = FnvHashMap::new();
 
     for func in manifest.symbol_table {
-        func_table.insert(String::from(func.name), func.offset);
+        let kind = match func.kind {
+            MANIFEST_KIND_PURE => FunctionKind::Pure,
+            _ => FunctionKind::Procedural,
+        };
+        let symbol = Symbol {
+            offset: func.offset,
+            kind,
+        };
+        func_table.insert(String::from(func.name), symbol);
     }
 
     let mut image = Vec::new();
@@ -187,6 +220,8 @@ pub fn initfs_module(name: &str) -> Result<'static, SharedModule> {
 
 pub fn load_and_cache(data: &[u8]) -> Result<'static, SharedModule> {
     let module = load(data)?.to_shared();
+    // Results of the module this one replaces may no longer hold
+    memo::forget_module(&module.name);
     MODULE_CACHE
         .write()
         .insert(module.name.clone(), module.clone());
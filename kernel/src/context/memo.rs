@@ -0,0 +1,391 @@
+//! # Memoization
+//! Results of fuse calls to pure functions, keyed by the module, the function and the encoded
+//! arguments. A repeated call is answered from here without spawning a context. Faults are not
+//! kept, the call may well succeed next time.
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::str;
+use hashmap_core::fnv::FnvHashMap;
+use sos::{decode_sos, OwnedEncodedValues, Value, SOS};
+use spin::Mutex;
+
+/// Which entry makes room for a new one once the cache is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// The entry that was used longest ago.
+    LeastRecentlyUsed,
+    /// The entry that was inserted first, hits do not keep an entry alive.
+    FirstIn,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MemoConfig {
+    /// Entries kept at most, 0 turns memoization off.
+    pub max_entries: usize,
+    /// Bytes of keys and results kept at most, larger results are not kept at all.
+    pub max_bytes: usize,
+    pub policy: EvictionPolicy,
+}
+
+impl MemoConfig {
+    /// `DEFAULT_CONFIG` with the `MEMO_ENTRIES`, `MEMO_BYTES` and `MEMO_POLICY` (`lru` or
+    /// `fifo`) lines of the boot environment applied. Values that don't parse are ignored.
+    pub fn from_env(env: &[u8]) -> Self {
+        let mut config = DEFAULT_CONFIG;
+        for line in env
+            .split(|&b| b == b'\n')
+            .filter_map(|line| str::from_utf8(line).ok())
+        {
+            let mut parts = line.trim().splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some("MEMO_ENTRIES"), Some(n)) => {
+                    config.max_entries = n.parse().unwrap_or(config.max_entries)
+                }
+                (Some("MEMO_BYTES"), Some(n)) => {
+                    config.max_bytes = n.parse().unwrap_or(config.max_bytes)
+                }
+                (Some("MEMO_POLICY"), Some("lru")) => {
+                    config.policy = EvictionPolicy::LeastRecentlyUsed
+                }
+                (Some("MEMO_POLICY"), Some("fifo")) => config.policy = EvictionPolicy::FirstIn,
+                _ => (),
+            }
+        }
+        config
+    }
+}
+
+pub const DEFAULT_CONFIG: MemoConfig = MemoConfig {
+    max_entries: 256,
+    max_bytes: 1024 * 1024,
+    policy: EvictionPolicy::LeastRecentlyUsed,
+};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemoStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+/// The module and function names, each NUL terminated, followed by the encoded arguments.
+pub struct MemoKey(Vec<u8>);
+
+impl MemoKey {
+    pub fn new<S: SOS>(module: &str, function: &str, args: &S) -> Self {
+        let names = module.len() + function.len() + 2;
+        let mut key = vec![0; names + args.encoded_len()];
+        key[..module.len()].copy_from_slice(module.as_bytes());
+        key[module.len() + 1..names - 1].copy_from_slice(function.as_bytes());
+        args.encode(&mut key[names..]);
+        MemoKey(key)
+    }
+}
+
+/// Whether `result` holds a fault or an error, which a timeout, a lack of memory or the module
+/// being replaced may have caused as much as the arguments did.
+fn is_fault(result: &[u8]) -> bool {
+    decode_sos(result, true).map_or(false, |mut values| {
+        values.any(|value| match value {
+            Value::Fault(_) | Value::Error(_) => true,
+            _ => false,
+        })
+    })
+}
+
+struct Entry {
+    result: OwnedEncodedValues,
+    /// Where the entry is in `Cache::order`.
+    stamp: u64,
+}
+
+struct Cache {
+    config: MemoConfig,
+    entries: FnvHashMap<Vec<u8>, Entry>,
+    /// Keys by when they were inserted, or last used under `EvictionPolicy::LeastRecentlyUsed`.
+    /// The first one is evicted next.
+    order: BTreeMap<u64, Vec<u8>>,
+    clock: u64,
+    stats: MemoStats,
+}
+
+impl Cache {
+    fn new(config: MemoConfig) -> Self {
+        Cache {
+            config,
+            entries: FnvHashMap::new(),
+            order: BTreeMap::new(),
+            clock: 0,
+            stats: MemoStats::default(),
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.stamp);
+            self.stats.bytes -= key.len() + entry.result.len();
+            self.stats.entries -= 1;
+        }
+    }
+
+    /// Evicts entries until `entries` more and `bytes` more fit.
+    fn make_room(&mut self, entries: usize, bytes: usize) {
+        while self.stats.entries + entries > self.config.max_entries
+            || self.stats.bytes + bytes > self.config.max_bytes
+        {
+            let oldest = match self.order.values().next() {
+                Some(key) => key.clone(),
+                None => break,
+            };
+            self.remove(&oldest);
+            self.stats.evictions += 1;
+        }
+    }
+
+    fn configure(&mut self, config: MemoConfig) {
+        self.config = config;
+        self.make_room(0, 0);
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.stats.entries = 0;
+        self.stats.bytes = 0;
+    }
+
+    fn forget_module(&mut self, module: &str) {
+        let stale: Vec<Vec<u8>> = self
+            .entries
+            .keys()
+            .filter(|key| key.starts_with(module.as_bytes()) && key.get(module.len()) == Some(&0))
+            .cloned()
+            .collect();
+        for key in stale {
+            self.remove(&key);
+        }
+    }
+
+    fn lookup(&mut self, key: &[u8]) -> Option<OwnedEncodedValues> {
+        let stamp = self.tick();
+        let result = match self.entries.get_mut(key) {
+            Some(entry) => {
+                if self.config.policy == EvictionPolicy::LeastRecentlyUsed {
+                    let key = self
+                        .order
+                        .remove(&entry.stamp)
+                        .expect("Entry is not in order");
+                    self.order.insert(stamp, key);
+                    entry.stamp = stamp;
+                }
+                Some(entry.result.clone())
+            }
+            None => None,
+        };
+        match result {
+            Some(_) => self.stats.hits += 1,
+            None => self.stats.misses += 1,
+        }
+        result
+    }
+
+    fn insert(&mut self, key: Vec<u8>, result: &[u8]) {
+        let bytes = key.len() + result.len();
+        if self.config.max_entries == 0 || bytes > self.config.max_bytes || is_fault(result) {
+            return;
+        }
+        self.remove(&key);
+        self.make_room(1, bytes);
+        let stamp = self.tick();
+        self.order.insert(stamp, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                result: result.to_vec(),
+                stamp,
+            },
+        );
+        self.stats.entries += 1;
+        self.stats.bytes += bytes;
+    }
+}
+
+lazy_static! {
+    static ref CACHE: Mutex<Cache> = Mutex::new(Cache::new(DEFAULT_CONFIG));
+}
+
+/// Replaces the configuration, evicting entries that no longer fit.
+pub fn configure(config: MemoConfig) {
+    CACHE.lock().configure(config);
+}
+
+pub fn stats() -> MemoStats {
+    CACHE.lock().stats
+}
+
+/// Drops every entry, the counters are kept.
+pub fn clear() {
+    CACHE.lock().clear();
+}
+
+/// Drops the entries of `module`, for when it is replaced.
+pub fn forget_module(module: &str) {
+    CACHE.lock().forget_module(module);
+}
+
+pub fn lookup(key: &MemoKey) -> Option<OwnedEncodedValues> {
+    CACHE.lock().lookup(&key.0)
+}
+
+pub fn insert(key: MemoKey, result: &[u8]) {
+    CACHE.lock().insert(key.0, result);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Cache, EvictionPolicy, MemoConfig, MemoKey, DEFAULT_CONFIG};
+    use sos::{ErrorCode, JustError, ReferencedValues, SOS};
+
+    fn key(module: &str, function: &str, arg: u32) -> Vec<u8> {
+        MemoKey::new(module, function, &sos!(arg)).0
+    }
+
+    fn cache(max_entries: usize, policy: EvictionPolicy) -> Cache {
+        Cache::new(MemoConfig {
+            max_entries,
+            policy,
+            ..DEFAULT_CONFIG
+        })
+    }
+
+    #[test]
+    fn insert_and_lookup() {
+        let mut cache = cache(4, EvictionPolicy::LeastRecentlyUsed);
+        assert_eq!(cache.lookup(&key("m", "f", 1)), None);
+        cache.insert(key("m", "f", 1), b"one");
+        assert_eq!(cache.lookup(&key("m", "f", 1)), Some(b"one".to_vec()));
+        assert_eq!(cache.lookup(&key("m", "f", 2)), None);
+        assert_eq!(cache.lookup(&key("m", "g", 1)), None);
+
+        // Inserting again replaces the result
+        cache.insert(key("m", "f", 1), b"uno");
+        assert_eq!(cache.lookup(&key("m", "f", 1)), Some(b"uno".to_vec()));
+        let stats = cache.stats;
+        assert_eq!((stats.hits, stats.misses, stats.entries), (2, 3, 1));
+        assert_eq!(stats.bytes, key("m", "f", 1).len() + 3);
+    }
+
+    #[test]
+    fn faults_are_not_kept() {
+        let mut cache = cache(4, EvictionPolicy::LeastRecentlyUsed);
+        let fault = JustError::with_code(ErrorCode::RESOURCES, "Out of memory");
+        let values = fault.values();
+        let mut result = vec![0; ReferencedValues(&values).encoded_len()];
+        ReferencedValues(&values).encode(&mut result);
+        cache.insert(key("m", "f", 1), &result);
+        assert_eq!(cache.lookup(&key("m", "f", 1)), None);
+        assert_eq!(cache.stats.entries, 0);
+
+        // A fault does not replace an earlier result either
+        let mut ok = vec![0; sos!(1u32).encoded_len()];
+        sos!(1u32).encode(&mut ok);
+        cache.insert(key("m", "f", 1), &ok);
+        cache.insert(key("m", "f", 1), &result);
+        assert_eq!(cache.lookup(&key("m", "f", 1)), Some(ok));
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = cache(2, EvictionPolicy::LeastRecentlyUsed);
+        cache.insert(key("m", "f", 1), b"one");
+        cache.insert(key("m", "f", 2), b"two");
+        assert!(cache.lookup(&key("m", "f", 1)).is_some());
+        cache.insert(key("m", "f", 3), b"three");
+        assert!(cache.lookup(&key("m", "f", 1)).is_some());
+        assert_eq!(cache.lookup(&key("m", "f", 2)), None);
+        assert!(cache.lookup(&key("m", "f", 3)).is_some());
+        assert_eq!((cache.stats.evictions, cache.stats.entries), (1, 2));
+    }
+
+    #[test]
+    fn evicts_first_in() {
+        let mut cache = cache(2, EvictionPolicy::FirstIn);
+        cache.insert(key("m", "f", 1), b"one");
+        cache.insert(key("m", "f", 2), b"two");
+        assert!(cache.lookup(&key("m", "f", 1)).is_some());
+        cache.insert(key("m", "f", 3), b"three");
+        assert_eq!(cache.lookup(&key("m", "f", 1)), None);
+        assert!(cache.lookup(&key("m", "f", 2)).is_some());
+    }
+
+    #[test]
+    fn evicts_for_bytes() {
+        let len = key("m", "f", 1).len() + 10;
+        let mut cache = Cache::new(MemoConfig {
+            max_bytes: 2 * len,
+            ..DEFAULT_CONFIG
+        });
+        cache.insert(key("m", "f", 1), &[1; 10]);
+        cache.insert(key("m", "f", 2), &[2; 10]);
+        cache.insert(key("m", "f", 3), &[3; 10]);
+        assert_eq!(cache.lookup(&key("m", "f", 1)), None);
+        assert_eq!(cache.stats.bytes, 2 * len);
+
+        // Too large to keep at all, nothing is evicted for it
+        cache.insert(key("m", "f", 4), &[4; 100]);
+        assert_eq!(cache.lookup(&key("m", "f", 4)), None);
+        assert_eq!(cache.stats.entries, 2);
+    }
+
+    #[test]
+    fn configure_evicts() {
+        let mut cache = cache(4, EvictionPolicy::LeastRecentlyUsed);
+        for arg in 0..4 {
+            cache.insert(key("m", "f", arg), b"result");
+        }
+        cache.configure(MemoConfig {
+            max_entries: 1,
+            ..DEFAULT_CONFIG
+        });
+        assert_eq!(cache.stats.entries, 1);
+        assert!(cache.lookup(&key("m", "f", 3)).is_some());
+
+        cache.configure(MemoConfig {
+            max_entries: 0,
+            ..DEFAULT_CONFIG
+        });
+        cache.insert(key("m", "f", 0), b"result");
+        assert_eq!(cache.stats.entries, 0);
+    }
+
+    #[test]
+    fn forget_module() {
+        let mut cache = cache(4, EvictionPolicy::LeastRecentlyUsed);
+        cache.insert(key("db", "get", 1), b"one");
+        cache.insert(key("db", "get", 2), b"two");
+        cache.insert(key("dbx", "get", 1), b"other");
+        cache.forget_module("db");
+        assert_eq!(cache.lookup(&key("db", "get", 1)), None);
+        assert_eq!(cache.lookup(&key("db", "get", 2)), None);
+        assert!(cache.lookup(&key("dbx", "get", 1)).is_some());
+        assert_eq!((cache.stats.entries, cache.stats.evictions), (1, 0));
+        assert_eq!(cache.order.len(), 1);
+    }
+
+    #[test]
+    fn config_from_env() {
+        let config =
+            MemoConfig::from_env(b"FOO=1\nMEMO_ENTRIES=8\nMEMO_POLICY=fifo\nMEMO_BYTES=x\n");
+        assert_eq!(config.max_entries, 8);
+        assert_eq!(config.max_bytes, DEFAULT_CONFIG.max_bytes);
+        assert_eq!(config.policy, EvictionPolicy::FirstIn);
+        assert_eq!(MemoConfig::from_env(b"").policy, DEFAULT_CONFIG.policy);
+    }
+}
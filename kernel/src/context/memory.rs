@@ -562,6 +562,8 @@ impl ContextValues {
             .expect("No argument memory mapped!")
             .len_bytes();
         let need = values.encoded_len();
+        // Aligned values can only be borrowed in place from an 8 byte aligned buffer
+        self.offset = align_up!(self.offset, 8);
         //println!("Need {}, length {}, offset {}", need, length, self.offset);
         if length - self.offset < need {
             self.memory = Some(
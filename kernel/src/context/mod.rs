@@ -13,8 +13,8 @@ pub use self::call::{cast_name, cast_ptr, fuse_name, fuse_ptr};
 pub use self::context::{Context, ContextId, SharedContext, Status, WaitpidKey};
 pub use self::list::ContextList;
 pub use self::load::{
-    cached_module, initfs_module, load_and_cache, FuncPtr, Module, ModuleFuncPtr, SharedModule,
-    INVALID_FUNCTION, KERNEL_MODULE,
+    cached_module, initfs_module, load_and_cache, FuncPtr, FunctionKind, Module, ModuleFuncPtr,
+    SharedModule, INVALID_FUNCTION, KERNEL_MODULE,
 };
 pub use self::memory::ContextMemory;
 pub use self::switch::{fuse_return, fuse_switch, switch};
@@ -37,6 +37,9 @@ mod call;
 // Implements loading modules.
 mod load;
 
+/// Results of pure functions
+pub mod memo;
+
 /// Memory struct - contains a set of pages for a context
 pub mod memory;
 
@@ -1,24 +1,50 @@
+use alloc::vec::Vec;
 use context;
 use context::{current_context, SharedContext, Status};
+use core::cmp::min;
 use core::ptr::read_volatile;
 use core::slice;
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
 use devices::pci::{pci_intx, PciBar, PciDevice};
 use hashmap_core::FnvHashMap;
 use interrupt;
 use ivshrpc::*;
-use ringbuf::{Consumer, Producer};
-use sos::{EncodedValues, EncodedValuesPtr, SOS};
+use ringbuf::{mpsc, End, Header, RecvError, Spin, SpinThenYield, WaitStrategy};
+use sos::{
+    validate_sos_limited, EncodedValues, EncodedValuesPtr, ErrorCode, JustError,
+    OwnedEncodedValues, ReferencedValues, Value, SOS, SOS_VERSION,
+};
 use spin::Mutex;
 use syscall::flag::MAP_WRITE;
-use syscall::{exit, physmap, sys_cast, sys_fuse};
+use syscall::{exit, physmap, sys_cast, sys_fuse, ARG_LIMITS};
+use time;
 
 const VID: u16 = 0x1af4;
 const DID: u16 = 0x1110;
 
 const MMIO_SIZE: usize = 256;
 
+/// Polls of the rings before a context yields. The interrupt handler gives up after as many, it
+/// can't let anything else run meanwhile.
+const RING_SPINS: usize = 1000;
+/// Times a context lets others run while waiting for the host to make room.
+const ROOM_YIELDS: usize = 1000;
+
 static CALL_ID: AtomicUsize = AtomicUsize::new(0);
+/// Our slot in the shared memory, taken from our peer id.
+static SLOT: AtomicUsize = AtomicUsize::new(0);
+/// Set once the host has set the rings up for this boot.
+static STARTED: AtomicBool = AtomicBool::new(false);
+/// Set once the host answered our hello, calls wait for it.
+static READY: AtomicBool = AtomicBool::new(false);
+/// Set when the host refused the handshake or corrupted its ring. Calls fail from then on, until
+/// the next boot.
+static DOWN: AtomicBool = AtomicBool::new(false);
+/// The features we agreed on with the host.
+static FEATURES: AtomicUsize = AtomicUsize::new(0);
+/// The largest frame we agreed on with the host.
+static MAX_FRAME: AtomicUsize = AtomicUsize::new(0);
 
 lazy_static! {
     static ref DEVICE: PciDevice = PciDevice::find_by_id(VID, DID)
@@ -46,53 +72,297 @@ lazy_static! {
             panic!("0th bar of ivshmem is not memory mapped");
         }
     };
-    static ref CONSUMER: Mutex<Consumer<'static>> = unsafe {
-        let buffer = slice::from_raw_parts_mut(*BUFFER_PTR as *mut u8, BUFFER_SIZE / 2);
+    static ref CONSUMER: Mutex<mpsc::Consumer<'static>> = unsafe {
         let _ = *MMIO_BAR;
-        Mutex::new(Consumer::from_slice(buffer))
+        let consumer =
+            mpsc::Consumer::from_slice(ring(0)).expect("Failed to attach to ivshmem ring");
+        Mutex::new(consumer)
     };
-    static ref PRODUCER: Mutex<Producer<'static>> = unsafe {
-        let buffer = slice::from_raw_parts_mut(
-            (*BUFFER_PTR as *mut u8).offset((BUFFER_SIZE / 2) as isize),
-            BUFFER_SIZE / 2,
-        );
-        Mutex::new(Producer::from_slice(buffer))
+    /// Shared by every CPU and context without a lock, each message is a frame of its own.
+    static ref PRODUCER: mpsc::Producer<'static> = unsafe {
+        mpsc::Producer::from_slice(ring(1)).expect("Failed to attach to ivshmem ring")
     };
     static ref CALL_QUEUE: Mutex<FnvHashMap<CallId, SharedContext>> =
         Mutex::new(FnvHashMap::default());
+    /// Fuses from the host a proxy runs, and whether the host cancelled them since.
+    static ref PROXIES: Mutex<FnvHashMap<CallId, bool>> = Mutex::new(FnvHashMap::default());
+}
+
+/// Half of our slot in the shared memory, the host writes to the first one.
+unsafe fn ring(half: usize) -> &'static mut [u8] {
+    let offset = ring_offset(SLOT.load(Ordering::Relaxed), half);
+    slice::from_raw_parts_mut(
+        (*BUFFER_PTR as *mut u8).offset(offset as isize),
+        SLOT_SIZE / 2,
+    )
 }
 
+fn fault(code: ErrorCode, message: &str) -> OwnedEncodedValues {
+    let err = JustError::with_code(code, message);
+    EncodedValues::from(ReferencedValues(&err.values())).into_owned()
+}
+
+/// Whether a message with `len` bytes of payload fits in the frames agreed on with the host.
+fn admit(len: usize) -> Result<(), OwnedEncodedValues> {
+    if IVSHRPC_HEADER_SIZE + len > MAX_FRAME.load(Ordering::Relaxed) {
+        return Err(fault(
+            ErrorCode::TOO_LARGE,
+            "The message does not fit in a frame",
+        ));
+    }
+    Ok(())
+}
+
+/// Sends a message to the host, failing with the fault the caller fails with.
 #[inline]
-fn write_msg<T: SOS>(args: T, mut header: MsgHeader) {
+fn write_msg<T: SOS, W: WaitStrategy>(
+    args: T,
+    mut header: MsgHeader,
+    wait: &W,
+) -> Result<(), OwnedEncodedValues> {
+    admit(args.encoded_len())?;
     header.length = args.encoded_len() as u32;
-    let mut lock = PRODUCER.lock();
-    let mut buffer = lock.write(IVSHRPC_HEADER_SIZE + header.length as usize);
-    buffer[..IVSHRPC_HEADER_SIZE].copy_from_slice(header.to_slice());
-    args.encode(&mut buffer[IVSHRPC_HEADER_SIZE..]);
+    {
+        let mut frame = PRODUCER
+            .reserve_frame(IVSHRPC_HEADER_SIZE + header.length as usize, wait)
+            .map_err(|_| fault(ErrorCode::TRANSPORT, "Timed out sending to the host"))?;
+        frame[..IVSHRPC_HEADER_SIZE].copy_from_slice(&header.to_bytes());
+        args.encode(&mut frame[IVSHRPC_HEADER_SIZE..]);
+    }
 
     // TODO, check if listening
     send_interrupt();
+    Ok(())
+}
+
+/// Waiting for the host from a context.
+fn context_wait() -> SpinThenYield<fn()> {
+    SpinThenYield::new(RING_SPINS, Some(ROOM_YIELDS), yield_now)
+}
+
+/// Sends a message nobody waits on the outcome of, so a failure can only be logged.
+fn reply<T: SOS, W: WaitStrategy>(args: T, header: MsgHeader, wait: &W) {
+    let callid = header.callid;
+    let sent = match admit(args.encoded_len()) {
+        // A result too large to send back fails the call instead
+        Err(err) => write_msg(
+            EncodedValues::from(err),
+            MsgHeader::new(MsgType::Error, callid),
+            wait,
+        ),
+        Ok(()) => write_msg(args, header, wait),
+    };
+    if sent.is_err() {
+        println!("Dropped reply to call {}, the host is not reading", callid);
+    }
+}
+
+/// Lets other contexts run, or halts until the next interrupt if there are none.
+fn yield_now() {
+    unsafe {
+        interrupt::disable();
+        if context::switch() {
+            interrupt::enable_and_nop();
+        } else {
+            // No other task to switch to, halt and wait for interrupts.
+            interrupt::enable_and_halt();
+        }
+    }
 }
 
 pub fn init() {
     unsafe {
         // Poll until interrupts are available
         while *(*MMIO_BAR as *const i32).offset(2) < 0 {}
-        println!("IVSHRPC_ID {}", *(*MMIO_BAR as *const i32).offset(2));
-        pci_intx(&DEVICE, true);
+        let id = *(*MMIO_BAR as *const i32).offset(2) as PeerId;
+        println!("IVSHRPC_ID {}", id);
+        let slot = guest_slot(id).expect("No slot in the shared memory for our ivshmem id");
+        SLOT.store(slot, Ordering::Relaxed);
+        start_over();
+        if handshake() {
+            pci_intx(&DEVICE, true);
+        }
+    }
+}
+
+/// What we tell the host about ourselves.
+fn hello() -> Hello<'static> {
+    Hello {
+        version: PROTOCOL_VERSION,
+        sos_version: SOS_VERSION,
+        max_frame: min(
+            PRODUCER.max_frame(),
+            IVSHRPC_HEADER_SIZE + ARG_LIMITS.max_bytes,
+        ) as u32,
+        features: FEATURE_CANCEL,
+        name: "kernel",
+    }
+}
+
+/// Greets the host and waits for its answer, before any call goes either way. Returns whether
+/// the host agreed, the device stays down otherwise.
+unsafe fn handshake() -> bool {
+    let ours = hello();
+    {
+        let mut header = MsgHeader::new(MsgType::Hello, 0);
+        header.length = ours.encoded_len() as u32;
+        let mut frame = PRODUCER
+            .reserve_frame(IVSHRPC_HEADER_SIZE + ours.encoded_len(), &Spin::forever())
+            .expect("Waiting forever timed out");
+        frame[..IVSHRPC_HEADER_SIZE].copy_from_slice(&header.to_bytes());
+        ours.encode(&mut frame[IVSHRPC_HEADER_SIZE..]);
+    }
+    send_interrupt();
+
+    let mut consumer = CONSUMER.lock();
+    loop {
+        let frame = match consumer.recv_frame(&Spin::forever()) {
+            Ok(frame) => frame,
+            Err(_) => {
+                go_down("The host corrupted the ivshmem ring");
+                return false;
+            }
+        };
+        if frame.len() < IVSHRPC_HEADER_SIZE
+            || MsgHeader::from_slice(&frame[..IVSHRPC_HEADER_SIZE]).msgtype
+                != MsgType::HelloAck as u8
+        {
+            println!("Dropped a message from before the handshake");
+            continue;
+        }
+        let theirs = match Hello::decode(&frame[IVSHRPC_HEADER_SIZE..]) {
+            Some(theirs) => theirs,
+            None => {
+                go_down("The host sent a malformed hello");
+                return false;
+            }
+        };
+        let agreement = match ours.agree(&theirs) {
+            Ok(agreement) => agreement,
+            Err(e) => {
+                println!("Refused the handshake with {}: {:?}", theirs.name, e);
+                go_down("The host speaks another protocol");
+                return false;
+            }
+        };
+        println!("Hello from {}", theirs.name);
+        FEATURES.store(agreement.features as usize, Ordering::Relaxed);
+        MAX_FRAME.store(agreement.max_frame as usize, Ordering::Relaxed);
+        READY.store(true, Ordering::Release);
+        return true;
+    }
+}
+
+/// Holds a call back until the handshake is done, failing it if the device is down.
+fn wait_for_handshake() -> Result<(), OwnedEncodedValues> {
+    loop {
+        if DOWN.load(Ordering::Acquire) {
+            return Err(down());
+        }
+        if READY.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        yield_now();
+    }
+}
+
+/// What calls fail with once the device is down.
+fn down() -> OwnedEncodedValues {
+    fault(ErrorCode::TRANSPORT, "The connection to the host is down")
+}
+
+/// Stops talking to the host for `reason`, failing the calls waiting on it.
+fn go_down(reason: &str) {
+    println!("ivshmem is down: {}", reason);
+    DOWN.store(true, Ordering::Release);
+    let err = fault(ErrorCode::TRANSPORT, reason);
+    let callids: Vec<CallId> = CALL_QUEUE.lock().keys().cloned().collect();
+    for callid in callids {
+        deliver(callid, err.clone());
+    }
+}
+
+/// Asks the host to set the rings up afresh for this boot, whatever the last one left in them,
+/// and waits for it to. The host tells us apart from the last boot by the epoch, the generation
+/// the rings had when we found them.
+unsafe fn start_over() {
+    let mut generations = [0; 2];
+    for half in 0..2 {
+        // The host sets the rings up once it is running
+        let header =
+            Header::wait_attach(ring(half), &Spin::forever()).expect("Unusable ivshmem ring");
+        generations[half] = header.generation();
+    }
+    let epoch = generations[1];
+    announce(epoch);
+    send_interrupt();
+
+    for half in 0..2 {
+        let header = Spin::forever()
+            .wait(|| match Header::attach(ring(half)) {
+                Ok(header) if header.generation() != generations[half] => Some(header),
+                _ => None,
+            })
+            .expect("Waiting forever timed out");
+        println!("ivshmem ring {} generation {}", half, header.generation());
+    }
+    // Setting the rings up cleared the epoch, it stays ours until the next boot
+    announce(epoch);
+    STARTED.store(true, Ordering::Release);
+}
+
+unsafe fn announce(epoch: usize) {
+    Header::attach(ring(0))
+        .unwrap()
+        .announce(End::Consumer, epoch);
+    Header::attach(ring(1))
+        .unwrap()
+        .announce(End::Producer, epoch);
+}
+
+/// Shows the host that we are still running, called on every timer tick.
+pub fn heartbeat() {
+    if STARTED.load(Ordering::Acquire) {
+        PRODUCER.beat(End::Producer);
     }
 }
 
+/// Runs a fuse from the host, cast by `isr` with the id of the call in front of its values.
 pub extern "C" fn fuse_proxy(values: EncodedValuesPtr) {
     println!("Fuse OK 0x{:x}", values as usize);
 
-    let values = unsafe { EncodedValues::from(slice::from_raw_parts(values, 4096)) };
+    let args = unsafe { EncodedValues::from_ptr(values) };
+    let values: Vec<Value> = args
+        .decode()
+        .expect("Validated SOS failed to decode")
+        .collect();
+    let callid = match values.first() {
+        Some(&Value::UInt64(callid)) => callid,
+        _ => panic!("Fuse proxy cast without a call id"),
+    };
+    if PROXIES.lock().get(&callid) == Some(&true) {
+        PROXIES.lock().remove(&callid);
+        println!("Dropped call {}, the host gave up on it", callid);
+        exit(0);
+    }
 
-    let res = sys_fuse(values);
+    let call = EncodedValues::from(ReferencedValues(&values[1..]));
+    let res = sys_fuse(&call);
+    if PROXIES.lock().remove(&callid) == Some(true) {
+        println!("Dropped the result of call {}, the host gave up", callid);
+        exit(0);
+    }
     match res {
-        // TODO the callid is not zero, I need to pass it through values above.
-        Ok(vals) => write_msg(vals, MsgHeader::new(MsgType::Return, 0)),
-        Err(vals) => write_msg(vals, MsgHeader::new(MsgType::Error, 0)),
+        Ok(vals) => reply(
+            vals,
+            MsgHeader::new(MsgType::Return, callid),
+            &context_wait(),
+        ),
+        Err(vals) => reply(
+            vals,
+            MsgHeader::new(MsgType::Error, callid),
+            &context_wait(),
+        ),
     }
 
     exit(0);
@@ -102,6 +372,10 @@ pub fn isr() {
     unsafe { read_volatile((*MMIO_BAR as *const u32).offset(1)) };
     println!("ivshmem interrupt 2 hit");
 
+    // Nothing the host writes is read anymore
+    if DOWN.load(Ordering::Acquire) {
+        return;
+    }
     let consumer = CONSUMER.try_lock();
     if consumer.is_none() {
         return;
@@ -109,78 +383,171 @@ pub fn isr() {
 
     let mut consumer = consumer.unwrap();
     loop {
-        let header = {
-            let mut header = consumer
-                .try_read(IVSHRPC_HEADER_SIZE, 1000)
-                .map(MsgHeader::from_slice);
-            if header.is_none() {
-                // TODO set not listening
-                // Checking one last time to avoid race condition
-                header = consumer
-                    .try_read(IVSHRPC_HEADER_SIZE, 1)
-                    .map(MsgHeader::from_slice);
-                if header.is_none() {
-                    return;
-                }
+        // The host interrupts again for frames committed after this gives up
+        // TODO set not listening
+        let frame = match consumer.recv_frame(&Spin::times(RING_SPINS)) {
+            Ok(frame) => frame,
+            Err(RecvError::TimedOut) => return,
+            // The host sets the rings up, nothing is left to start over with
+            Err(RecvError::Corrupted) => return go_down("The host corrupted the ivshmem ring"),
+        };
+        if frame.len() < IVSHRPC_HEADER_SIZE {
+            println!("Dropped a frame too short for a header");
+            continue;
+        }
+        let header = MsgHeader::from_slice(&frame[..IVSHRPC_HEADER_SIZE]);
+        let buff = &frame[IVSHRPC_HEADER_SIZE..];
+        let msgtype = match MsgType::from_u8(header.msgtype) {
+            // Not SOS, and only sent before interrupts are on
+            Some(MsgType::Hello) | Some(MsgType::HelloAck) => {
+                println!("Ignored a hello, the handshake is done");
+                continue;
+            }
+            Some(msgtype) => msgtype,
+            None => {
+                let callid = header.callid;
+                println!("Dropped call {} of unknown type {}", callid, header.msgtype);
+                continue;
             }
-            header.unwrap()
         };
 
-        let buff = consumer.read(header.length as usize);
-        let ret = EncodedValues::from(&buff[..]);
-        match MsgType::from_u8(header.msgtype) {
-            Some(MsgType::Error) | Some(MsgType::Return) => {
-                // Deliver result to context
-                let context = CALL_QUEUE.lock().remove(&header.callid);
-                if context.is_none() {
-                    panic!("Received result for unknown context id {}", header.callid);
-                }
-                let context = context.unwrap();
-                let mut context_lock = context.write();
-                context_lock.result = Some(ret.into_owned());
-                context_lock.unblock();
+        if buff.len() > ARG_LIMITS.max_bytes {
+            reject(
+                &header,
+                JustError::with_code(ErrorCode::TOO_LARGE, "Message exceeds decode limits"),
+            );
+            continue;
+        }
+
+        if let Err(e) = validate_sos_limited(buff, ARG_LIMITS) {
+            reject(&header, e.into());
+            continue;
+        }
+        let ret = EncodedValues::from(buff);
+        match msgtype {
+            MsgType::Error | MsgType::Return => {
+                deliver(header.callid, ret.into_owned());
             }
-            Some(MsgType::Fuse) => {
+            MsgType::Fuse => {
                 println!("Proxy ptr: {:x}", fuse_proxy as usize);
-                context::cast_ptr((context::KERNEL_MODULE.clone(), fuse_proxy as usize), &ret)
-                    .expect("Failed to cast proxy");
+                // The proxy answers the call, so it is told which one it runs
+                let callid = header.callid;
+                let mut values = vec![Value::UInt64(callid)];
+                values.extend(ret.decode().expect("Validated SOS failed to decode"));
+                PROXIES.lock().insert(callid, false);
+                let proxy = (context::KERNEL_MODULE.clone(), fuse_proxy as usize);
+                if let Err(err) = context::cast_ptr(proxy, &ReferencedValues(&values)) {
+                    PROXIES.lock().remove(&callid);
+                    reply(
+                        err,
+                        MsgHeader::new(MsgType::Error, callid),
+                        &Spin::times(RING_SPINS),
+                    );
+                }
             }
-            Some(MsgType::Cast) => {
-                let res = sys_cast(ret);
+            MsgType::Cast => {
+                let res = sys_cast(buff);
                 if res.is_err() {
-                    write_msg(
+                    reply(
                         res.unwrap_err(),
                         MsgHeader::new(MsgType::Error, header.callid),
+                        &Spin::times(RING_SPINS),
                     );
                 }
             }
-            None => panic!("Unexpected response to a fuse call"),
+            MsgType::Cancel => {
+                // The proxy drops the call, or its result if it is running already
+                let callid = header.callid;
+                match PROXIES.lock().get_mut(&callid) {
+                    Some(cancelled) => *cancelled = true,
+                    None => println!("Nothing to cancel for call {}, it is done with", callid),
+                }
+            }
+            MsgType::Hello | MsgType::HelloAck => (),
         }
     }
 }
 
+/// Hands the result of a call to the context waiting for it.
+fn deliver(callid: CallId, result: OwnedEncodedValues) {
+    let context = match CALL_QUEUE.lock().remove(&callid) {
+        Some(context) => context,
+        // The call timed out already
+        None => return println!("Dropped the result of call {}, nobody waits for it", callid),
+    };
+    let mut context_lock = context.write();
+    context_lock.result = Some(result);
+    context_lock.unblock();
+}
+
+/// Answers a message whose payload was refused, so that nobody is left waiting on it.
+fn reject(header: &MsgHeader, err: JustError) {
+    match MsgType::from_u8(header.msgtype) {
+        Some(MsgType::Error) | Some(MsgType::Return) => {
+            deliver(
+                header.callid,
+                EncodedValues::from(ReferencedValues(&err.values())).into_owned(),
+            );
+        }
+        // Nobody waits on a cancel or a handshake
+        Some(MsgType::Cancel) | Some(MsgType::Hello) | Some(MsgType::HelloAck) => (),
+        _ => reply(
+            err,
+            MsgHeader::new(MsgType::Error, header.callid),
+            &Spin::times(RING_SPINS),
+        ),
+    }
+}
+
 fn send_interrupt() {
-    // vector 1u16, device 0u16
-    unsafe { *(*MMIO_BAR as *mut [u8; 4]).offset(3) = [0, 0, 0, 0] };
+    // The host's vector for our slot, the host's id above it
+    let doorbell = (HOST_ID as u32) << 16 | SLOT.load(Ordering::Relaxed) as u32;
+    unsafe { *(*MMIO_BAR as *mut u32).offset(3) = doorbell };
 }
 
-pub fn ivshrpc_cast<T: SOS>(args: T) {
+pub fn ivshrpc_cast<T: SOS>(args: T) -> Result<(), OwnedEncodedValues> {
+    wait_for_handshake()?;
     let callid = CALL_ID.fetch_add(1, Ordering::Relaxed);
-    write_msg(args, MsgHeader::new(MsgType::Cast, callid as u64));
+    write_msg(
+        args,
+        MsgHeader::new(MsgType::Cast, callid as u64),
+        &context_wait(),
+    )
 }
 
-pub fn ivshrpc_fuse<'a, T: SOS>(args: T) -> EncodedValues<'a> {
+/// Calls a function on the host and blocks the current context until its result comes back,
+/// failing the call with `ErrorCode::TIMEOUT` if that takes longer than `timeout`.
+pub fn ivshrpc_fuse<'a, T: SOS>(args: T, timeout: Option<Duration>) -> EncodedValues<'a> {
+    if let Err(err) = wait_for_handshake() {
+        return EncodedValues::from(err);
+    }
     let callid = CALL_ID.fetch_add(1, Ordering::Relaxed);
     let current = current_context();
     {
         let mut q = CALL_QUEUE.lock();
         q.insert(callid as u64, current.clone());
     }
-    write_msg(args, MsgHeader::new(MsgType::Fuse, callid as u64));
+    // Queued first, so that the call fails along with the others if the device goes down
+    let sent = if DOWN.load(Ordering::Acquire) {
+        Err(down())
+    } else {
+        write_msg(
+            args,
+            MsgHeader::new(MsgType::Fuse, callid as u64).with_timeout(timeout),
+            &context_wait(),
+        )
+    };
+    if let Err(err) = sent {
+        // Unless it was failed along with the others already, then its result is waiting
+        if CALL_QUEUE.lock().remove(&(callid as u64)).is_some() {
+            return EncodedValues::from(err);
+        }
+    }
     {
         // Atomically checks if return value is already available, if not blocks
         let mut context_lock = current.write();
         if context_lock.result.is_none() {
+            context_lock.wake = timeout.map(deadline);
             context_lock.status = Status::Blocked;
         }
     }
@@ -188,14 +555,17 @@ pub fn ivshrpc_fuse<'a, T: SOS>(args: T) -> EncodedValues<'a> {
     // NOTE yes, this will force the switch even if the return value is already available, but it is very unlikely that this is the case.
 
     while current.read().status == Status::Blocked {
-        unsafe {
-            interrupt::disable();
-            if context::switch() {
-                interrupt::enable_and_nop();
-            } else {
-                // No other task to switch to, halt and wait for interrupts.
-                interrupt::enable_and_halt();
-            }
+        yield_now();
+    }
+    current.write().wake = None;
+
+    if current.read().result.is_none() {
+        // Woken up by the deadline, the call is ours to fail unless its result is on its way
+        if CALL_QUEUE.lock().remove(&(callid as u64)).is_some() {
+            return time_out(callid as u64);
+        }
+        while current.read().result.is_none() {
+            yield_now();
         }
     }
 
@@ -208,3 +578,25 @@ pub fn ivshrpc_fuse<'a, T: SOS>(args: T) -> EncodedValues<'a> {
             .expect("This shouldn't be empty."),
     );
 }
+
+/// The monotonic time `timeout` from now, as context wake times are kept.
+fn deadline(timeout: Duration) -> (u64, u64) {
+    let start = time::monotonic();
+    let sum = start.1 + timeout.subsec_nanos() as u64;
+    (
+        start.0 + timeout.as_secs() + sum / 1_000_000_000,
+        sum % 1_000_000_000,
+    )
+}
+
+/// Cancels a fuse the host took too long on, returning the error it fails with.
+fn time_out<'a>(callid: CallId) -> EncodedValues<'a> {
+    println!("Call {} timed out", callid);
+    let cancels = FEATURES.load(Ordering::Relaxed) as u32 & FEATURE_CANCEL != 0;
+    let cancel = MsgHeader::new(MsgType::Cancel, callid);
+    if cancels && write_msg(sos!(), cancel, &context_wait()).is_err() {
+        println!("Failed to cancel call {}, the host is not reading", callid);
+    }
+    let err = JustError::with_code(ErrorCode::TIMEOUT, "Timed out waiting for the host");
+    EncodedValues::from(EncodedValues::from(ReferencedValues(&err.values())).into_owned())
+}
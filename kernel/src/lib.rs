@@ -145,20 +145,25 @@ pub fn kmain(cpus: usize, env: &[u8]) -> ! {
     let pid = syscall::getpid();
     println!("BSP: {:?} {}", pid, cpus);
     println!("Env: {:?}", ::core::str::from_utf8(env));
+    context::memo::configure(context::memo::MemoConfig::from_env(env));
 
     {
-        use alloc::vec::Vec;
+        use core::time::Duration;
         use devices::ivshmem;
-        use sos::Value;
         ivshmem::init();
-        ivshmem::ivshrpc_cast(sos!(("host", "hello"), "Hello"));
-        ivshmem::ivshrpc_cast(sos!(("host", "cast_test"), "Hello ivsrpcd"));
-        let result = ivshmem::ivshrpc_fuse(sos!(("host", "hello_fuse"), "Fuse"));
-
-        println!(
-            "Received from host {:?}",
-            result.decode().map(|i| i.collect::<Vec<Value>>())
+        ivshmem::ivshrpc_cast(sos!(("host", "hello"), "Hello")).expect("Failed to cast");
+        ivshmem::ivshrpc_cast(sos!(("host", "cast_test"), "Hello ivsrpcd"))
+            .expect("Failed to cast");
+        let result = ivshmem::ivshrpc_fuse(
+            sos!(("host", "hello_fuse"), "Fuse"),
+            Some(Duration::from_secs(5)),
         );
+
+        // The host answers with its greeting, a fault is printed as it is
+        match result.decode().map(sos::from_iter::<&str>) {
+            Some(Ok(greeting)) => println!("Received from host {}", greeting),
+            _ => println!("Received from host {}", result),
+        }
     }
 
     let module = context::initfs_module("call").expect("Failed to load module");
@@ -166,6 +171,7 @@ pub fn kmain(cpus: usize, env: &[u8]) -> ! {
     context::cast_name(module.clone(), "passthrough", &sos!("hello")).expect("Failed to call");
 
     context::fuse_name(module.clone(), "call", &sos!()).expect("Failed to call");
+    println!("Memo: {:?}", context::memo::stats());
 
     loop {
         unsafe {
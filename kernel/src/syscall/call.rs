@@ -1,55 +1,47 @@
 use context;
 
-use alloc::vec::Vec;
-use core::convert::TryInto;
-use sos::{EncodedValues, Function, JustError, Value};
-use syscall::exit;
+use sos::{validate_sos_limited, EncodedValues, ErrorCode, Function, JustError};
+use syscall::{exit, ARG_LIMITS};
 
-pub fn sys_fuse(args: EncodedValues) -> Result<EncodedValues, JustError<'static>> {
-    let mut iter = args
-        .decode()
-        .ok_or(JustError::new("Could not decode SOS"))?;
-    let function: Function = iter
-        .next()
-        .ok_or(JustError::new("Not enough arguments"))?
-        .try_into()
-        .map_err(|e| JustError::new(e))?;
+pub fn sys_fuse(args: &[u8]) -> Result<EncodedValues<'static>, JustError> {
+    let values = validate_sos_limited(args, ARG_LIMITS)?
+        .into_inner()
+        .indexed();
+    let function: Function = values.get_as(0)?;
+    let rest = values
+        .slice(1..values.len())
+        .expect("Validated SOS failed to decode");
 
-    // DEBUG, inefficient, forces decode of args
-    let fargs: Vec<Value> = iter.clone().collect();
-    println!("Doing a fuse call {:?}({:?})", function, fargs);
+    println!("Doing a fuse call {:?}({})", function, rest);
 
-    let module = context::initfs_module(function.module).map_err(|e| JustError::new(e))?;
+    let module = context::initfs_module(function.module).map_err(|e| {
+        JustError::with_code(ErrorCode::NO_SUCH_MODULE, e).with_origin(function.clone())
+    })?;
 
-    let ret = context::fuse_name(module, function.name, &iter).map_err(|e| JustError::new(e))?;
+    let ret = context::fuse_name(module, function.name, &rest)
+        .map_err(|e| e.with_origin(function.clone()))?;
 
-    println!(
-        "Returning from a fuse call {:?} -> {:?}",
-        function,
-        ret.decode().map(|i| i.collect::<Vec<Value>>())
-    );
+    println!("Returning from a fuse call {:?} -> {}", function, ret);
 
     Ok(ret)
 }
 
-pub fn sys_cast(args: EncodedValues) -> Result<(), JustError<'static>> {
-    let mut iter = args
-        .decode()
-        .ok_or(JustError::new("Could not decode SOS"))?;
+pub fn sys_cast(args: &[u8]) -> Result<(), JustError> {
+    let values = validate_sos_limited(args, ARG_LIMITS)?
+        .into_inner()
+        .indexed();
+    let function: Function = values.get_as(0)?;
+    let rest = values
+        .slice(1..values.len())
+        .expect("Validated SOS failed to decode");
 
-    let function: Function = iter
-        .next()
-        .ok_or(JustError::new("Not enough arguments"))?
-        .try_into()
-        .map_err(|e| JustError::new(e))?;
+    println!("Doing a cast call {:?}({})", function, rest);
+    let module = context::initfs_module(function.module).map_err(|e| {
+        JustError::with_code(ErrorCode::NO_SUCH_MODULE, e).with_origin(function.clone())
+    })?;
 
-    // TODO don't do that, I don't need to decode these, just pass them directly through
-    let fargs: Vec<Value> = iter.clone().collect();
-
-    println!("Doing a cast call {:?}({:?})", function, fargs);
-    let module = context::initfs_module(function.module).map_err(|e| JustError::new(e))?;
-
-    context::cast_name(module, function.name, &iter).map_err(|e| JustError::new(e))?;
+    context::cast_name(module, function.name, &rest)
+        .map_err(|e| e.with_origin(function.clone()))?;
 
     Ok(())
 }
@@ -61,11 +53,7 @@ pub fn sys_return(values: EncodedValues) -> ! {
             .expect("No current context")
             .clone();
         let mut context_lock = current_context.write();
-        println!(
-            "Function {} exited with {:?}",
-            context_lock.name(),
-            values.decode().map(|i| i.collect::<Vec<Value>>())
-        );
+        println!("Function {} exited with {}", context_lock.name(), values);
         context_lock.result = Some(values.into_owned());
     }
 
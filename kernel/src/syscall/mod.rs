@@ -17,8 +17,7 @@ use self::call::*;
 use self::number::*;
 use alloc::vec::Vec;
 use context;
-use core::convert::TryInto;
-use sos::{EncodedValues, JustError, Value};
+use sos::{validate_sos_limited, EncodedValues, ErrorCode, JustError, Value};
 
 use interrupt::syscall::SyscallStack;
 
@@ -45,11 +44,11 @@ mod call;
 /// This function is the syscall handler of the kernel, it is composed of an inner function that returns a `Result<usize>`. After the inner function runs, the syscall function calls [`Error::mux`] on it.
 pub fn syscall(a: usize, b: usize, c: usize, stack: &mut SyscallStack) -> usize {
     #[inline(always)]
-    fn inner<'a, 'b>(
+    fn inner<'a>(
         a: usize,
-        args: EncodedValues<'a>,
+        args: &'a [u8],
         _stack: &mut SyscallStack,
-    ) -> Result<(usize), JustError<'static>> {
+    ) -> Result<(usize), JustError<'a>> {
         //SYS_* is declared in kernel/syscall/src/number.rs
         let ret = match a {
             SYS_FUSE => sys_fuse(args),
@@ -57,15 +56,12 @@ pub fn syscall(a: usize, b: usize, c: usize, stack: &mut SyscallStack) -> usize
                 sys_cast(args)?;
                 Ok(EncodedValues::from(Vec::new()))
             }
-            SYS_RETURN => sys_return(args),
+            SYS_RETURN => {
+                validate_sos_limited(args, ARG_LIMITS)?;
+                sys_return(EncodedValues::from(args))
+            }
             SYS_WRITE => {
-                let string: &str = args
-                    .decode()
-                    .ok_or(JustError::new("Could not decode SOS"))?
-                    .next()
-                    .ok_or(JustError::new("First argument to write must be String"))?
-                    .try_into()
-                    .map_err(|e| JustError::new(e))?;
+                let string: &str = validate_sos_limited(args, ARG_LIMITS)?.next_as()?;
 
                 let contexts = ::context::contexts();
                 if let Some(context_lock) = contexts.current() {
@@ -142,7 +138,10 @@ pub fn syscall(a: usize, b: usize, c: usize, stack: &mut SyscallStack) -> usize
                 )
             }
             */
-            _ => Err(JustError::new("Invalid system call")),
+            _ => Err(JustError::with_code(
+                ErrorCode::INVALID_SYSCALL,
+                "Invalid system call",
+            )),
         }?;
         let current_lock = context::contexts_mut()
             .current()
@@ -156,13 +155,7 @@ pub fn syscall(a: usize, b: usize, c: usize, stack: &mut SyscallStack) -> usize
             .get())
     }
 
-    let slice = validate_slice(b as *const u8, c);
-    let result = if slice.is_err() {
-        Err(slice.unwrap_err())
-    } else {
-        let sos = EncodedValues::from(slice.unwrap());
-        inner(a, sos, stack)
-    };
+    let result = validate_slice(b as *const u8, c).and_then(|args| inner(a, args, stack));
 
     let current_lock = context::contexts_mut()
         .current()
@@ -2,15 +2,24 @@ use core::{mem, slice};
 
 use paging::entry::EntryFlags;
 use paging::{ActivePageTable, Page, VirtualAddress};
-use sos::JustError;
+use sos::{DecodeLimits, ErrorCode, JustError};
+
+/// Limits on the SOS arguments of a syscall, which come from user modules or from the host.
+pub const ARG_LIMITS: DecodeLimits = DecodeLimits {
+    max_depth: 16,
+    max_values: 4096,
+    max_bytes: 1024 * 1024,
+};
 
 pub fn validate(address: usize, size: usize, flags: EntryFlags) -> Result<(), JustError<'static>> {
-    let end_offset = size
-        .checked_sub(1)
-        .ok_or(JustError::new("Invalid memory address"))?;
-    let end_address = address
-        .checked_add(end_offset)
-        .ok_or(JustError::new("Invalid memory address"))?;
+    let end_offset = size.checked_sub(1).ok_or(JustError::with_code(
+        ErrorCode::BAD_ADDRESS,
+        "Invalid memory address",
+    ))?;
+    let end_address = address.checked_add(end_offset).ok_or(JustError::with_code(
+        ErrorCode::BAD_ADDRESS,
+        "Invalid memory address",
+    ))?;
 
     let active_table = unsafe { ActivePageTable::new() };
 
@@ -25,11 +34,17 @@ pub fn validate(address: usize, size: usize, flags: EntryFlags) -> Result<(), Ju
                     flags,
                     page_flags
                 );
-                return Err(JustError::new("Invalid memory address"));
+                return Err(JustError::with_code(
+                    ErrorCode::BAD_ADDRESS,
+                    "Invalid memory address",
+                ));
             }
         } else {
             println!("{:X}: Not found", page.start_address().get());
-            return Err(JustError::new("Invalid memory address"));
+            return Err(JustError::with_code(
+                ErrorCode::BAD_ADDRESS,
+                "Invalid memory address",
+            ));
         }
     }
 
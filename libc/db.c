@@ -4,23 +4,37 @@
 #include "string.h"
 
 void set(string key, const void *value, unsigned int value_length) {
-	ClearValues();
-	SetString(key);
-	SetOpaque((const char *)value, value_length);
-	Call("db", "set");
+	uintptr_t len = CALL_BUF_SIZE + strlen(key) + value_length;
+	uint8_t *buf = alloca(len);
+	SosEncoder enc;
+	SosDecoder dec;
+	call_start(&enc, buf, len, "db", "set");
+	sos_push_string(&enc, key);
+	sos_push_opaque(&enc, (const uint8_t *)value, value_length);
+	call_finish(&enc, buf, &dec);
 }
 
 void *get(string key, unsigned int *value_length) {
-	ClearValues();
-	SetString(key);
-	Values dbGet = Call("db", "get");
-	return (void *)GetOpaque(dbGet, value_length);
+	uint8_t buf[CALL_BUF_SIZE];
+	SosEncoder enc;
+	SosDecoder dec;
+	const uint8_t *value = NULL;
+	uintptr_t len = 0;
+	call_start(&enc, buf, sizeof(buf), "db", "get");
+	sos_push_string(&enc, key);
+	if (call_finish(&enc, buf, &dec) == SOS_STATUS_OK)
+		sos_next_opaque(&dec, &value, &len);
+	*value_length = len;
+	return (void *)value;
 }
 
 void delete (string key) {
-	ClearValues();
-	SetString(key);
-	Call("db", "delete");
+	uint8_t buf[CALL_BUF_SIZE];
+	SosEncoder enc;
+	SosDecoder dec;
+	call_start(&enc, buf, sizeof(buf), "db", "delete");
+	sos_push_string(&enc, key);
+	call_finish(&enc, buf, &dec);
 }
 
 static void ListSetSize(string list_name, int size) {
@@ -1,7 +1,23 @@
 #ifndef _RUNTIME_
 #define _RUNTIME_ 1
 
-#include "sos.h"
+#include "sos_capi.h"
+
+// Room for the values of a call the runtime makes on behalf of a module.
+#define CALL_BUF_SIZE 4096
+
+// Calls the function encoded first in the `length` bytes at `args` with the
+// values after it, returning where its results are. Provided by the module,
+// libc makes no system calls of its own.
+const uint8_t *sys_fuse(const uint8_t *args, long length);
+
+// Starts encoding a call to module.name into the `len` bytes at `buf`, its
+// arguments are pushed onto `enc` after.
+SosStatus call_start(SosEncoder *enc, uint8_t *buf, uintptr_t len,
+					 const char *module, const char *name);
+
+// Makes the call `enc` holds and starts `dec` on its results.
+SosStatus call_finish(SosEncoder *enc, const uint8_t *buf, SosDecoder *dec);
 
 unsigned long rand();
 
@@ -1,109 +1,72 @@
 #ifndef _SOS_
 #define _SOS_ 1
 
+// Values are encoded and decoded with the functions in sos_capi.h, the codec
+// sos-rs exports as libsos_capi.a, so modules and the kernel agree on the
+// format. Modules link libsos_capi.a and nothing else to handle values.
+#include <sos_capi.h>
 #include <string.h>
 
 #define MAX_MSG_SIZE 1024 * 1024
 
-#define Public __attribute__((annotate("public")))
-#define Private __attribute__((annotate("private")))
-
-#define IPC_INPUT ((char *)0x900000)
-// some size reserved for message header
-#define IPC_OUTPUT (IPC_INPUT + MAX_MSG_SIZE)
-
-typedef enum {
-	Invalid,
-	Int32,
-	UInt32,
-	Int64,
-	UInt64,
-	Float,
-	Double,
-	Error,
-	String,
-	Opaque,
-	Function
-} Type;
-
-#define _TYPE_Int32 int
-#define _TYPE_UInt32 unsigned int
-#define _TYPE_Int64 long long
-#define _TYPE_UInt64 unsigned long long
+#define _TYPE_Int32 int32_t
+#define _TYPE_UInt32 uint32_t
+#define _TYPE_Int64 int64_t
+#define _TYPE_UInt64 uint64_t
 #define _TYPE_Float float
 #define _TYPE_Double double
+#define _TYPE_Int8 int8_t
+#define _TYPE_UInt8 uint8_t
+#define _TYPE_Int16 int16_t
+#define _TYPE_UInt16 uint16_t
 
 #define SOS_TYPE(name) _TYPE_##name
 
-typedef struct {
-	Type val_type;
-	unsigned int length;
-	char data[];
-} Value;
-
-struct values {
-	unsigned int count;
-	unsigned int current_offset;
-	Value values[];
-};
-
-typedef struct values *Values;
-
-// Read
-
-#define NumValues(vals_ptr) vals_ptr->count
-
-#define _Get_At_Offset_(vals)                                                  \
-	((Value *)((char *)vals->values + vals->current_offset))
-
-void *sos_get_data(Values vals, unsigned int *size);
-
-#define GetValue(vals_ptr, val_type)                                           \
-	(*(SOS_TYPE(val_type) *)sos_get_data((vals_ptr), NULL))
-
-#define CopyValue(vals_ptr, dst)                                               \
-	{                                                                          \
-		int size = GetSize(vals_ptr);                                          \
-		dst = alloca(size);                                                    \
-		void *src = sos_get_data(vals_ptr, NULL);                              \
-		memcpy((void *)dst, src, size);                                        \
-	}
-
-#define GetOpaque(vals_ptr, length_ptr) (sos_get_data(vals_ptr, length_ptr))
+#define _REF_Int32 sos_ref_i32
+#define _REF_UInt32 sos_ref_u32
+#define _REF_Int64 sos_ref_i64
+#define _REF_UInt64 sos_ref_u64
+#define _REF_Float sos_ref_f32
+#define _REF_Double sos_ref_f64
+#define _REF_Int8 sos_ref_i8
+#define _REF_UInt8 sos_ref_u8
+#define _REF_Int16 sos_ref_i16
+#define _REF_UInt16 sos_ref_u16
+
+#define SOS_REF(name) _REF_##name
+
+#define _NEXT_Int32 sos_next_i32
+#define _NEXT_UInt32 sos_next_u32
+#define _NEXT_Int64 sos_next_i64
+#define _NEXT_UInt64 sos_next_u64
+#define _NEXT_Float sos_next_f32
+#define _NEXT_Double sos_next_f64
+#define _NEXT_Int8 sos_next_i8
+#define _NEXT_UInt8 sos_next_u8
+#define _NEXT_Int16 sos_next_i16
+#define _NEXT_UInt16 sos_next_u16
+
+#define SOS_NEXT(name) _NEXT_##name
+
+// Takes the next value off the decoder at dec_ptr as a val_type, 0 if it is
+// of another type. In a buffer encoded with SOS_FLAG_ALIGNED the value is
+// loaded through an aligned pointer into the buffer, otherwise it is copied
+// out byte by byte, so packed buffers work too.
+#define GetValue(dec_ptr, val_type)                                            \
+	({                                                                     \
+		const SOS_TYPE(val_type) *_ref;                                \
+		SOS_TYPE(val_type) _value = 0;                                 \
+		if (SOS_REF(val_type)((dec_ptr), &_ref) == SOS_STATUS_OK)     \
+			_value = *_ref;                                        \
+		else                                                           \
+			SOS_NEXT(val_type)((dec_ptr), &_value);                \
+		_value;                                                        \
+	})
 
-#define GetString(vals_ptr) (char *)sos_get_data(vals_ptr, NULL)
-
-#define GetType(vals_ptr) (_Get_At_Offset_(vals_ptr)->type)
-#define GetSize(vals_ptr) (_Get_At_Offset_(vals_ptr)->length)
-
-#define IsError(vals_ptr) (GetType(vals_ptr) == Error)
-#define GetError(vals_ptr) GetString(vals_ptr)
-
-void GetFunction(Values vals_ptr, char **module, char **func);
-
-// Write
-
-#define vals_ipc ((Values)(IPC_OUTPUT + 512))
-#define ClearValues()                                                          \
-	*vals_ipc = (struct values) { 0, 0 }
-
-void sos_set_data(Values vals, Type type, unsigned int size, const void *data);
-
-#define AddValue(vals_ptr, itype, val_data)                                              \
-	{                                                                          \
-		SOS_TYPE(itype) tmp = (val_data);                                      \
-		sos_set_data(vals_ptr, itype, sizeof(tmp), &tmp);                      \
-	}
-
-#define SetOpaque(vals_ptr, data_ptr, size) sos_set_data(vals_ptr, Opaque, size, data_ptr)
-
-#define SetString(vals_ptr, str) sos_set_data(vals_ptr, String, strlen(str) + 1, str)
-
-#define SetError(vals_ptr, str) sos_set_data(vals_ptr, Error, strlen(str) + 1, str)
-
-void SetFunction(Values ptr, const char *module, const char *func);
-
-#define ReturnValues() return vals_ipc
-#define Call(module, name) sys_fuse(module, name, vals_ipc)
+#define Public __attribute__((annotate("public")))
+#define Private __attribute__((annotate("private")))
+// The function returns the same values whenever it is called with the same
+// arguments and has no other effects, so the kernel may reuse earlier results.
+#define Pure __attribute__((annotate("pure")))
 
 #endif
@@ -0,0 +1,397 @@
+#ifndef SOS_CAPI_H
+#define SOS_CAPI_H
+
+/* Generated by cbindgen from sos-rs/capi, run `make libc/include/sos_capi.h` instead of editing. */
+
+#include <stdint.h>
+
+/**
+ * Header flag for `Layout::Aligned`, pass it to `sos_encoder_init`.
+ */
+#define SOS_FLAG_ALIGNED 1
+
+/**
+ * Returned by `sos_peek_type` once there are no values left.
+ */
+#define SOS_TYPE_INVALID 0
+
+#define SOS_TYPE_INT32 1
+
+#define SOS_TYPE_UINT32 2
+
+#define SOS_TYPE_INT64 3
+
+#define SOS_TYPE_UINT64 4
+
+#define SOS_TYPE_FLOAT 5
+
+#define SOS_TYPE_DOUBLE 6
+
+#define SOS_TYPE_ERROR 7
+
+#define SOS_TYPE_STRING 8
+
+#define SOS_TYPE_OPAQUE 9
+
+#define SOS_TYPE_FUNCTION 10
+
+#define SOS_TYPE_EMBEDDED 11
+
+#define SOS_TYPE_BOOL 12
+
+#define SOS_TYPE_UNIT 13
+
+#define SOS_TYPE_INT8 14
+
+#define SOS_TYPE_UINT8 15
+
+#define SOS_TYPE_INT16 16
+
+#define SOS_TYPE_UINT16 17
+
+#define SOS_TYPE_ARRAY 18
+
+#define SOS_TYPE_MAP 19
+
+#define SOS_TYPE_FAULT 20
+
+/**
+ * Extension values have this type plus their type id.
+ */
+#define SOS_TYPE_EXTENSION 0x80
+
+#define SOS_EXTENSION_MAX_ID 0x7f
+
+typedef enum SosStatus {
+  SOS_STATUS_OK,
+  /**
+   * The buffer is too small for the value, nothing was written.
+   */
+  SOS_STATUS_NO_SPACE,
+  /**
+   * Too many embedded values are open.
+   */
+  SOS_STATUS_TOO_DEEP,
+  /**
+   * `sos_close` without an embedded value open.
+   */
+  SOS_STATUS_NOT_OPEN,
+  /**
+   * `sos_finish` with embedded values still open.
+   */
+  SOS_STATUS_UNCLOSED,
+  /**
+   * The input is not valid SOS, or the flags or an extension type id are out of range.
+   */
+  SOS_STATUS_MALFORMED,
+  /**
+   * A string is not valid UTF-8.
+   */
+  SOS_STATUS_INVALID_UTF8,
+  /**
+   * The next value is of another type, it was left in place.
+   */
+  SOS_STATUS_WRONG_TYPE,
+  /**
+   * There are no values left.
+   */
+  SOS_STATUS_END,
+  /**
+   * The next value is of the right type, but can't be pointed to where it is because the
+   * buffer was not encoded with `SOS_FLAG_ALIGNED` or does not start 8 byte aligned. It was
+   * left in place.
+   */
+  SOS_STATUS_UNALIGNED,
+} SosStatus;
+
+/**
+ * Room for an encoder, C code can put it wherever it likes.
+ */
+typedef struct SosEncoder {
+  uint64_t _private[40];
+} SosEncoder;
+
+/**
+ * Room for a decoder, C code can put it wherever it likes.
+ */
+typedef struct SosDecoder {
+  uint64_t _private[16];
+} SosDecoder;
+
+/**
+ * Starts encoding into the `len` bytes at `buf`, `flags` is 0 or `SOS_FLAG_ALIGNED`.
+ */
+SosStatus sos_encoder_init(SosEncoder *enc, uint8_t *buf, uintptr_t len, uint8_t flags);
+
+SosStatus sos_push_i32(SosEncoder *enc, int32_t value);
+
+SosStatus sos_next_i32(SosDecoder *dec, int32_t *out);
+
+/**
+ * Points `out` at the next value where it is in the buffer, without copying it.
+ */
+SosStatus sos_ref_i32(SosDecoder *dec, const int32_t **out);
+
+/**
+ * Points `out` at the elements of an array where they are in the buffer and stores
+ * how many there are in `len`.
+ */
+SosStatus sos_next_array_i32(SosDecoder *dec, const int32_t **out, uintptr_t *len);
+
+SosStatus sos_push_u32(SosEncoder *enc, uint32_t value);
+
+SosStatus sos_next_u32(SosDecoder *dec, uint32_t *out);
+
+/**
+ * Points `out` at the next value where it is in the buffer, without copying it.
+ */
+SosStatus sos_ref_u32(SosDecoder *dec, const uint32_t **out);
+
+/**
+ * Points `out` at the elements of an array where they are in the buffer and stores
+ * how many there are in `len`.
+ */
+SosStatus sos_next_array_u32(SosDecoder *dec, const uint32_t **out, uintptr_t *len);
+
+SosStatus sos_push_i64(SosEncoder *enc, int64_t value);
+
+SosStatus sos_next_i64(SosDecoder *dec, int64_t *out);
+
+/**
+ * Points `out` at the next value where it is in the buffer, without copying it.
+ */
+SosStatus sos_ref_i64(SosDecoder *dec, const int64_t **out);
+
+/**
+ * Points `out` at the elements of an array where they are in the buffer and stores
+ * how many there are in `len`.
+ */
+SosStatus sos_next_array_i64(SosDecoder *dec, const int64_t **out, uintptr_t *len);
+
+SosStatus sos_push_u64(SosEncoder *enc, uint64_t value);
+
+SosStatus sos_next_u64(SosDecoder *dec, uint64_t *out);
+
+/**
+ * Points `out` at the next value where it is in the buffer, without copying it.
+ */
+SosStatus sos_ref_u64(SosDecoder *dec, const uint64_t **out);
+
+/**
+ * Points `out` at the elements of an array where they are in the buffer and stores
+ * how many there are in `len`.
+ */
+SosStatus sos_next_array_u64(SosDecoder *dec, const uint64_t **out, uintptr_t *len);
+
+SosStatus sos_push_f32(SosEncoder *enc, float value);
+
+SosStatus sos_next_f32(SosDecoder *dec, float *out);
+
+/**
+ * Points `out` at the next value where it is in the buffer, without copying it.
+ */
+SosStatus sos_ref_f32(SosDecoder *dec, const float **out);
+
+/**
+ * Points `out` at the elements of an array where they are in the buffer and stores
+ * how many there are in `len`.
+ */
+SosStatus sos_next_array_f32(SosDecoder *dec, const float **out, uintptr_t *len);
+
+SosStatus sos_push_f64(SosEncoder *enc, double value);
+
+SosStatus sos_next_f64(SosDecoder *dec, double *out);
+
+/**
+ * Points `out` at the next value where it is in the buffer, without copying it.
+ */
+SosStatus sos_ref_f64(SosDecoder *dec, const double **out);
+
+/**
+ * Points `out` at the elements of an array where they are in the buffer and stores
+ * how many there are in `len`.
+ */
+SosStatus sos_next_array_f64(SosDecoder *dec, const double **out, uintptr_t *len);
+
+SosStatus sos_push_i8(SosEncoder *enc, int8_t value);
+
+SosStatus sos_next_i8(SosDecoder *dec, int8_t *out);
+
+/**
+ * Points `out` at the next value where it is in the buffer, without copying it.
+ */
+SosStatus sos_ref_i8(SosDecoder *dec, const int8_t **out);
+
+/**
+ * Points `out` at the elements of an array where they are in the buffer and stores
+ * how many there are in `len`.
+ */
+SosStatus sos_next_array_i8(SosDecoder *dec, const int8_t **out, uintptr_t *len);
+
+SosStatus sos_push_u8(SosEncoder *enc, uint8_t value);
+
+SosStatus sos_next_u8(SosDecoder *dec, uint8_t *out);
+
+/**
+ * Points `out` at the next value where it is in the buffer, without copying it.
+ */
+SosStatus sos_ref_u8(SosDecoder *dec, const uint8_t **out);
+
+/**
+ * Points `out` at the elements of an array where they are in the buffer and stores
+ * how many there are in `len`.
+ */
+SosStatus sos_next_array_u8(SosDecoder *dec, const uint8_t **out, uintptr_t *len);
+
+SosStatus sos_push_i16(SosEncoder *enc, int16_t value);
+
+SosStatus sos_next_i16(SosDecoder *dec, int16_t *out);
+
+/**
+ * Points `out` at the next value where it is in the buffer, without copying it.
+ */
+SosStatus sos_ref_i16(SosDecoder *dec, const int16_t **out);
+
+/**
+ * Points `out` at the elements of an array where they are in the buffer and stores
+ * how many there are in `len`.
+ */
+SosStatus sos_next_array_i16(SosDecoder *dec, const int16_t **out, uintptr_t *len);
+
+SosStatus sos_push_u16(SosEncoder *enc, uint16_t value);
+
+SosStatus sos_next_u16(SosDecoder *dec, uint16_t *out);
+
+/**
+ * Points `out` at the next value where it is in the buffer, without copying it.
+ */
+SosStatus sos_ref_u16(SosDecoder *dec, const uint16_t **out);
+
+/**
+ * Points `out` at the elements of an array where they are in the buffer and stores
+ * how many there are in `len`.
+ */
+SosStatus sos_next_array_u16(SosDecoder *dec, const uint16_t **out, uintptr_t *len);
+
+/**
+ * Any non zero `value` is true.
+ */
+SosStatus sos_push_bool(SosEncoder *enc, uint8_t value);
+
+SosStatus sos_push_unit(SosEncoder *enc);
+
+SosStatus sos_push_string(SosEncoder *enc, const char *s);
+
+SosStatus sos_push_error(SosEncoder *enc, const char *s);
+
+SosStatus sos_push_opaque(SosEncoder *enc, const uint8_t *data, uintptr_t len);
+
+SosStatus sos_push_function(SosEncoder *enc, const char *module, const char *name);
+
+/**
+ * Adds `len` bytes at `data` as a value of the extension type `type_id`.
+ */
+SosStatus sos_push_extension(SosEncoder *enc,
+                             uint8_t type_id,
+                             const uint8_t *data,
+                             uintptr_t len);
+
+/**
+ * Adds the SOS buffer of `len` bytes at `buf` as one embedded value, it is validated first.
+ */
+SosStatus sos_push_embedded(SosEncoder *enc, const uint8_t *buf, uintptr_t len);
+
+/**
+ * Starts an embedded value, everything pushed until the matching `sos_close` goes inside it.
+ */
+SosStatus sos_open(SosEncoder *enc);
+
+SosStatus sos_close(SosEncoder *enc);
+
+/**
+ * Completes the buffer and stores its length in `len`. The encoder cannot be used afterwards.
+ */
+SosStatus sos_finish(SosEncoder *enc, uintptr_t *len);
+
+/**
+ * Length of the SOS buffer at `buf` as its header gives it, 0 if `buf` does not start with a
+ * header. For buffers handed over without their length, like the results of a call.
+ */
+uintptr_t sos_encoded_len(const uint8_t *buf);
+
+/**
+ * Starts decoding the `len` bytes at `buf`, which are validated in full before anything else.
+ */
+SosStatus sos_decoder_init(SosDecoder *dec, const uint8_t *buf, uintptr_t len);
+
+/**
+ * Type of the next value, one of the `SOS_TYPE_` constants or `SOS_TYPE_EXTENSION` plus the
+ * type id of an extension value.
+ */
+uint8_t sos_peek_type(const SosDecoder *dec);
+
+/**
+ * Skips over the next value, whatever its type.
+ */
+SosStatus sos_skip(SosDecoder *dec);
+
+SosStatus sos_next_bool(SosDecoder *dec, uint8_t *out);
+
+SosStatus sos_next_unit(SosDecoder *dec);
+
+/**
+ * Points `out` at the string in the buffer and stores its length, without the NUL, in `len`
+ * unless it is NULL.
+ */
+SosStatus sos_next_string(SosDecoder *dec, const char **out, uintptr_t *len);
+
+/**
+ * Like `sos_next_string`, for an error.
+ */
+SosStatus sos_next_error(SosDecoder *dec, const char **out, uintptr_t *len);
+
+SosStatus sos_next_opaque(SosDecoder *dec, const uint8_t **out, uintptr_t *len);
+
+SosStatus sos_next_extension(SosDecoder *dec,
+                             uint8_t *type_id,
+                             const uint8_t **out,
+                             uintptr_t *len);
+
+/**
+ * Points `module` and `name` at the NUL terminated parts of a function.
+ */
+SosStatus sos_next_function(SosDecoder *dec, const char **module, const char **name);
+
+/**
+ * Points `out` at the little endian elements of an array of any element type, stores the
+ * `SOS_TYPE_` constant of the elements in `elem_type` and the size of the elements in bytes in
+ * `len`. Works on packed buffers too, where the typed `sos_next_array_` functions can't.
+ */
+SosStatus sos_next_array(SosDecoder *dec,
+                         uint8_t *elem_type,
+                         const uint8_t **out,
+                         uintptr_t *len);
+
+/**
+ * Starts `inner` on the keys and values of a map, each key followed by its value, and stores
+ * the number of pairs in `len`. `dec` moves on past the map.
+ */
+SosStatus sos_next_map(SosDecoder *dec, SosDecoder *inner, uintptr_t *len);
+
+/**
+ * Stores the code of a fault in `code` and points `message` at its NUL terminated message.
+ * `module` and `name` are pointed at the function that failed, or set to NULL if it is not
+ * known. The cause of the fault is not handed out.
+ */
+SosStatus sos_next_fault(SosDecoder *dec,
+                         uint32_t *code,
+                         const char **message,
+                         const char **module,
+                         const char **name);
+
+/**
+ * Starts `inner` on the values of an embedded value, `dec` moves on past it.
+ */
+SosStatus sos_next_embedded(SosDecoder *dec, SosDecoder *inner);
+
+#endif /* SOS_CAPI_H */
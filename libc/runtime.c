@@ -1,16 +1,44 @@
 #include <runtime.h>
+#include <sos.h>
+
+SosStatus call_start(SosEncoder *enc, uint8_t *buf, uintptr_t len,
+					 const char *module, const char *name) {
+	SosStatus status = sos_encoder_init(enc, buf, len, 0);
+	if (status != SOS_STATUS_OK)
+		return status;
+	return sos_push_function(enc, module, name);
+}
+
+SosStatus call_finish(SosEncoder *enc, const uint8_t *buf, SosDecoder *dec) {
+	uintptr_t len;
+	SosStatus status = sos_finish(enc, &len);
+	if (status != SOS_STATUS_OK)
+		return status;
+	const uint8_t *results = sys_fuse(buf, len);
+	return sos_decoder_init(dec, results, sos_encoded_len(results));
+}
 
 unsigned long rand() {
-	ClearValues();
-	Values r1 = Call("runtime", "rand");
-	return GetValue(r1, UInt64);
+	uint8_t buf[CALL_BUF_SIZE];
+	SosEncoder enc;
+	SosDecoder dec;
+	uint64_t r = 0;
+	call_start(&enc, buf, sizeof(buf), "runtime", "rand");
+	if (call_finish(&enc, buf, &dec) == SOS_STATUS_OK)
+		sos_next_u64(&dec, &r);
+	return r;
 }
 
 void *malloc(long size) {
-	ClearValues();
-	AddValue(Int64, size);
-	Values r1 = Call("rt", "malloc");
-	return (void *)GetValue(r1, Int64);
+	uint8_t buf[CALL_BUF_SIZE];
+	SosEncoder enc;
+	SosDecoder dec;
+	int64_t ptr = 0;
+	call_start(&enc, buf, sizeof(buf), "rt", "malloc");
+	sos_push_i64(&enc, size);
+	if (call_finish(&enc, buf, &dec) == SOS_STATUS_OK)
+		sos_next_i64(&dec, &ptr);
+	return (void *)ptr;
 }
 
 void *calloc(long count, long elem_size) {
@@ -21,9 +49,12 @@ void *calloc(long count, long elem_size) {
 }
 
 void free(void *ptr) {
-	ClearValues();
-	AddValue(Int64, (long)ptr);
-	Call("rt", "free");
+	uint8_t buf[CALL_BUF_SIZE];
+	SosEncoder enc;
+	SosDecoder dec;
+	call_start(&enc, buf, sizeof(buf), "rt", "free");
+	sos_push_i64(&enc, (long)ptr);
+	call_finish(&enc, buf, &dec);
 }
 
 void *realloc(void *ptr, long size) {
@@ -35,7 +35,8 @@ ifeq ($(UNAME),Linux)
 endif
 ifeq ($(IVSHMEM), yes)
 	QEMUFLAGS+= -chardev socket,path=/tmp/ivshmem_socket,id=ivshmem_socket
-	QEMUFLAGS+= -device ivshmem,msi=off,chardev=ivshmem_socket,vectors=1
+	# One vector per guest slot, as many as MAX_GUESTS in ivshrpc
+	QEMUFLAGS+= -device ivshmem,msi=off,chardev=ivshmem_socket,vectors=4
 endif
 #,int,pcall
 #-device intel-iommu
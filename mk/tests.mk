@@ -6,9 +6,10 @@ $(TEST_PREFIX): FORCE
 	mkdir -p $@
 	make $(TEST_PREFIX)/exit
 	make $(TEST_PREFIX)/call
+	make $(TEST_PREFIX)/aligned
 
-$(TEST_PREFIX)/%: test/%.c build/symbind FORCE
+$(TEST_PREFIX)/%: test/%.c build/symbind build/libsos_capi.a FORCE
 	make -C libc libc.a
 	gcc -fno-builtin -nostdinc -nostdlib -fno-stack-protector -Ilibc/include -c $< -o $@.o
-	ld -e 0x0 -o $@ $@.o libc/*.o
+	ld -e 0x0 -o $@ $@.o libc/*.o build/libsos_capi.a
 	build/symbind -m $@
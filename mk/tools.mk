@@ -3,5 +3,13 @@ FORCE:
 build/symbind: symbind/*.go
 	cd symbind && go build -o ../$@
 
+# The SOS codec for C modules, built without std
+build/libsos_capi.a: sos-rs/capi/src/*.rs sos-rs/src/*.rs
+	cd sos-rs/capi && RUSTFLAGS="-C panic=abort" cargo build --release --features panic-handler
+	cp target/release/libsos_capi.a $@
+
+libc/include/sos_capi.h: sos-rs/capi/src/*.rs sos-rs/capi/cbindgen.toml
+	cd sos-rs/capi && cbindgen --config cbindgen.toml -o ../../$@
+
 ivshrpcd: FORCE
 	cd ivshrpcd && cargo run
\ No newline at end of file
@@ -7,13 +7,29 @@ extern crate alloc;
 #[cfg(feature = "alloc")]
 use core::alloc::Layout;
 use core::cell::Cell;
+use core::cmp;
 use core::mem;
 use core::ops::{Deref, DerefMut};
 use core::sync::atomic::{AtomicUsize, Ordering};
-use core::usize;
+use core::{u32, usize};
+
+mod liveness;
+mod memory;
+pub mod mpsc;
+mod wait;
+
+pub use liveness::{End, Monitor, Peer};
+pub use memory::Memory;
+pub use wait::{Blocking, Notifier, Spin, SpinThenYield, TimedOut, WaitStrategy};
 
 const CACHELINE_LEN: usize = 64;
-const SIZEOF_HEADER: usize = mem::size_of::<Header>();
+/// Bytes of the little endian `u32` length in front of every frame.
+const FRAME_PREFIX: usize = 4;
+/// Marks a header that `Header::new_inline_at` is done setting up, "RING".
+const MAGIC: usize = 0x5249_4e47;
+/// Layout of the header and of what is written to the ring. Both sides of a ring have to agree
+/// on it.
+pub const VERSION: usize = 2;
 
 macro_rules! cacheline_pad {
     ($N:expr) => {
@@ -23,23 +39,63 @@ macro_rules! cacheline_pad {
 
 #[repr(C)]
 pub struct Header {
+    /// Stored last when setting up the ring, attaching waits for it.
+    magic: AtomicUsize,
+    version: usize,
+    /// Counts the times the ring was set up in this memory, so that a peer can tell it apart
+    /// from the ring it attached to before.
+    generation: usize,
     capacity: usize,
     allocated_size: usize,
-    _padding1: [usize; cacheline_pad!(2)],
+    _padding1: [usize; cacheline_pad!(5)],
 
     // Consumer cache line
     head: AtomicUsize,
     shadow_tail: Cell<usize>,
-    _padding2: [usize; cacheline_pad!(2)],
+    consumer_epoch: AtomicUsize,
+    consumer_heartbeat: AtomicUsize,
+    _padding2: [usize; cacheline_pad!(4)],
 
     // Producer cache line
     tail: AtomicUsize,
     shadow_head: Cell<usize>,
-    _padding3: [usize; cacheline_pad!(2)],
+    /// Position where the producer last skipped to the start of the buffer because a region did
+    /// not fit before its end. Everything from there to the end of the buffer is padding, which
+    /// the consumer steps over.
+    padding: AtomicUsize,
+    producer_epoch: AtomicUsize,
+    producer_heartbeat: AtomicUsize,
+    _padding3: [usize; cacheline_pad!(5)],
 }
 
 unsafe impl Sync for Header {}
 
+/// Why a ring can't be attached to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachError {
+    /// The peer has not set the ring up yet, or the memory holds something else.
+    Uninitialized,
+    /// The ring was set up with another layout, which is given.
+    VersionMismatch(usize),
+    /// The header does not match the memory it is in, or its positions are corrupted.
+    Inconsistent,
+}
+
+/// Why no frame was received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    TimedOut,
+    /// The next frame runs past what was written to the ring. Nothing more can be read from it,
+    /// it has to be set up again.
+    Corrupted,
+}
+
+impl From<TimedOut> for RecvError {
+    fn from(_: TimedOut) -> Self {
+        RecvError::TimedOut
+    }
+}
+
 fn prev_power_of_two(mut x: usize) -> usize {
     x = x | (x >> 1);
     x = x | (x >> 2);
@@ -50,37 +106,117 @@ fn prev_power_of_two(mut x: usize) -> usize {
 }
 
 impl Header {
+    /// Bytes the header takes up in front of the data.
+    pub const SIZE: usize = mem::size_of::<Header>();
+
+    /// Sets up a ring in `buff`, zeroing it. A ring that was set up there before is replaced by
+    /// the next generation.
     pub unsafe fn new_inline_at(buff: &mut [u8]) -> &Self {
-        assert!(buff.len() > SIZEOF_HEADER);
+        assert!(buff.len() > Header::SIZE);
         let buff_ptr = buff.as_ptr() as *mut Header;
-        let capacity = prev_power_of_two(buff.len() - SIZEOF_HEADER);
+        let capacity = prev_power_of_two(buff.len() - Header::SIZE);
+        let generation = if (*buff_ptr).magic.load(Ordering::Relaxed) == MAGIC {
+            (*buff_ptr).generation.wrapping_add(1)
+        } else {
+            1
+        };
+        // Turns away peers attaching until the ring is set up again
+        (*buff_ptr).magic.store(0, Ordering::Release);
 
         *buff_ptr = Header {
+            magic: AtomicUsize::new(0),
+            version: VERSION,
+            generation,
             capacity,
             allocated_size: capacity,
-            _padding1: [0; cacheline_pad!(2)],
+            _padding1: [0; cacheline_pad!(5)],
 
             head: AtomicUsize::new(0),
             shadow_tail: Cell::new(0),
-            _padding2: [0; cacheline_pad!(2)],
+            consumer_epoch: AtomicUsize::new(0),
+            consumer_heartbeat: AtomicUsize::new(0),
+            _padding2: [0; cacheline_pad!(4)],
 
             tail: AtomicUsize::new(0),
             shadow_head: Cell::new(0),
-            _padding3: [0; cacheline_pad!(2)],
+            padding: AtomicUsize::new(0),
+            producer_epoch: AtomicUsize::new(0),
+            producer_heartbeat: AtomicUsize::new(0),
+            _padding3: [0; cacheline_pad!(5)],
         };
+        for b in buff[Header::SIZE..].iter_mut() {
+            *b = 0;
+        }
+        (*buff_ptr).magic.store(MAGIC, Ordering::Release);
 
         &*buff_ptr
     }
+
+    /// The ring the peer set up in `buff`, after checking that it is one and that it makes sense.
+    pub unsafe fn attach(buff: &[u8]) -> Result<&Self, AttachError> {
+        assert!(buff.len() > Header::SIZE);
+        let header = &*(buff.as_ptr() as *const Header);
+        if header.magic.load(Ordering::Acquire) != MAGIC {
+            return Err(AttachError::Uninitialized);
+        }
+        if header.version != VERSION {
+            return Err(AttachError::VersionMismatch(header.version));
+        }
+        let capacity = prev_power_of_two(buff.len() - Header::SIZE);
+        let head = header.head.load(Ordering::Acquire);
+        let tail = header.tail.load(Ordering::Acquire);
+        if header.capacity != capacity
+            || header.allocated_size != capacity
+            || tail.wrapping_sub(header.past_padding(head)) > capacity
+        {
+            return Err(AttachError::Inconsistent);
+        }
+        Ok(header)
+    }
+
+    /// Like `attach`, but waits for the peer to set the ring up as `wait` says.
+    pub unsafe fn wait_attach<'a, W: WaitStrategy>(
+        buff: &'a [u8],
+        wait: &W,
+    ) -> Result<&'a Self, AttachError> {
+        wait.wait(|| match Header::attach(buff) {
+            Err(AttachError::Uninitialized) => None,
+            attached => Some(attached),
+        })
+        .unwrap_or(Err(AttachError::Uninitialized))
+    }
+
     pub fn capacity(&self) -> usize {
         self.capacity
     }
 
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// Bytes written but not read yet, including padding. A region written to an empty ring can
+    /// overlap the padding in front of it, then the two take up the whole capacity.
     pub fn size(&self) -> usize {
-        self.tail.load(Ordering::Acquire) - self.head.load(Ordering::Acquire)
+        cmp::min(
+            self.tail
+                .load(Ordering::Acquire)
+                .wrapping_sub(self.head.load(Ordering::Acquire)),
+            self.capacity,
+        )
     }
 
     pub fn free_space(&self) -> usize {
-        self.capacity() - self.size()
+        self.capacity().saturating_sub(self.size())
+    }
+
+    /// Where the data at `head` starts, past the padding if the producer left some there.
+    fn past_padding(&self, head: usize) -> usize {
+        let offset = head & (self.capacity - 1);
+        if offset != 0 && self.padding.load(Ordering::Relaxed) == head {
+            head.wrapping_add(self.capacity - offset)
+        } else {
+            head
+        }
     }
 }
 
@@ -153,41 +289,113 @@ pub struct Producer<'a>(&'a Header, &'a mut [u8]);
 unsafe impl<'a> Send for Producer<'a> {}
 
 impl<'b, 'a: 'b> Producer<'a> {
-    pub unsafe fn from_slice(buff: &'a mut [u8]) -> Self {
-        let capacity = prev_power_of_two(buff.len() - SIZEOF_HEADER);
-        let buffer = &*(buff.as_ptr() as *const Header);
-        assert!(capacity == buffer.capacity);
-        Producer(buffer, &mut buff[SIZEOF_HEADER..SIZEOF_HEADER + capacity])
+    /// Attaches to the ring in `buff`, see `Header::attach`.
+    pub unsafe fn from_slice(buff: &'a mut [u8]) -> Result<Self, AttachError> {
+        let buffer = &*(Header::attach(buff)? as *const Header);
+        let capacity = buffer.capacity;
+        Ok(Producer(
+            buffer,
+            &mut buff[Header::SIZE..Header::SIZE + capacity],
+        ))
     }
 
+    /// Hands out `n` contiguous bytes, `None` if there is no room for them yet. Panics if `n` is
+    /// not less than the capacity.
     pub fn try_write(&'b mut self, n: usize) -> Option<WriteHandle<'b, 'a>> {
-        let current_tail = self.0.tail.load(Ordering::Relaxed);
-
-        if self.0.shadow_head.get() + self.0.capacity <= current_tail + n {
-            self.0.shadow_head.set(self.0.head.load(Ordering::Relaxed));
-            if self.0.shadow_head.get() + self.0.capacity <= current_tail + n {
-                return None;
-            }
+        let (current_tail, padding) = self.region(n);
+        if !self.has_room(current_tail, padding, n) {
+            return None;
         }
 
         Some(WriteHandle {
             buffer: self,
             n,
             current_tail,
+            padding,
+            prefix: 0,
         })
     }
 
-    pub fn write(&'b mut self, n: usize) -> WriteHandle<'b, 'a> {
-        let current_tail = self.0.tail.load(Ordering::Relaxed);
-        while self.0.shadow_head.get() + self.0.capacity <= current_tail + n {
-            self.0.shadow_head.set(self.0.head.load(Ordering::Relaxed));
-        }
+    /// Like `try_write`, but waits for room as `wait` says.
+    pub fn write<W: WaitStrategy>(
+        &'b mut self,
+        n: usize,
+        wait: &W,
+    ) -> Result<WriteHandle<'b, 'a>, TimedOut> {
+        let (current_tail, padding) = self.region(n);
+        wait.wait(|| {
+            if self.has_room(current_tail, padding, n) {
+                Some(())
+            } else {
+                None
+            }
+        })?;
 
-        WriteHandle {
+        Ok(WriteHandle {
             buffer: self,
             n,
             current_tail,
+            padding,
+            prefix: 0,
+        })
+    }
+
+    /// Hands out a frame of `n` bytes, `None` if there is no room for it yet. The consumer sees
+    /// the whole frame at once, and only through `recv_frame`. Frames and plain writes don't mix.
+    pub fn try_reserve_frame(&'b mut self, n: usize) -> Option<WriteHandle<'b, 'a>> {
+        self.try_write(FRAME_PREFIX + n)
+            .map(WriteHandle::into_frame)
+    }
+
+    /// Like `try_reserve_frame`, but waits for room as `wait` says.
+    pub fn reserve_frame<W: WaitStrategy>(
+        &'b mut self,
+        n: usize,
+        wait: &W,
+    ) -> Result<WriteHandle<'b, 'a>, TimedOut> {
+        self.write(FRAME_PREFIX + n, wait)
+            .map(WriteHandle::into_frame)
+    }
+
+    /// Copies `data` into a frame of its own.
+    pub fn send_frame<W: WaitStrategy>(&mut self, data: &[u8], wait: &W) -> Result<(), TimedOut> {
+        self.reserve_frame(data.len(), wait)?.copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Start of the next region of `n` bytes and the padding before it. A region that would run
+    /// past the end of the buffer starts over at the beginning instead.
+    fn region(&self, n: usize) -> (usize, usize) {
+        assert!(
+            n < self.0.capacity,
+            "Region does not fit in the ring buffer"
+        );
+        let tail = self.0.tail.load(Ordering::Relaxed);
+        let offset = tail & (self.0.capacity - 1);
+        let padding = if offset + n > self.0.capacity {
+            self.0.capacity - offset
+        } else {
+            0
+        };
+        (tail.wrapping_add(padding), padding)
+    }
+
+    /// Whether the region fits, looking at where the consumer is again if it doesn't seem to.
+    fn has_room(&self, start: usize, padding: usize, n: usize) -> bool {
+        if !self.is_full(start, padding, n) {
+            return true;
         }
+        self.0.shadow_head.set(self.0.head.load(Ordering::Relaxed));
+        !self.is_full(start, padding, n)
+    }
+
+    /// Whether the region would catch up with the consumer, as far as we know.
+    fn is_full(&self, start: usize, padding: usize, n: usize) -> bool {
+        let head = self.0.shadow_head.get();
+        // With nothing left to read the region can't overwrite anything, even when it and the
+        // padding together take up more than the capacity
+        let empty = head == start.wrapping_sub(padding);
+        !empty && start.wrapping_add(n).wrapping_sub(head) >= self.0.capacity
     }
 }
 
@@ -202,25 +410,52 @@ pub struct WriteHandle<'a, 'b: 'a> {
     buffer: &'a mut Producer<'b>,
     current_tail: usize,
     n: usize,
+    /// Bytes skipped at the end of the buffer to get to `current_tail`.
+    padding: usize,
+    /// Bytes at the start of the region that are not handed out, the length of a frame.
+    prefix: usize,
+}
+
+impl<'a, 'b> WriteHandle<'a, 'b> {
+    /// Fills in the length prefix and hands out what follows it.
+    fn into_frame(mut self) -> Self {
+        let len = self.n - FRAME_PREFIX;
+        assert!(
+            len <= u32::MAX as usize,
+            "Frame length does not fit its prefix"
+        );
+        for (i, b) in self[..FRAME_PREFIX].iter_mut().enumerate() {
+            *b = (len >> (8 * i)) as u8;
+        }
+        self.prefix = FRAME_PREFIX;
+        self
+    }
 }
 
 impl<'a, 'b> Deref for WriteHandle<'a, 'b> {
     type Target = [u8];
     fn deref(&self) -> &Self::Target {
         let offset = self.current_tail & (self.buffer.0.allocated_size - 1);
-        &self.buffer.1[offset..offset + self.n]
+        &self.buffer.1[offset + self.prefix..offset + self.n]
     }
 }
 
 impl<'a, 'b> DerefMut for WriteHandle<'a, 'b> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         let offset = self.current_tail & (self.buffer.0.allocated_size - 1);
-        &mut self.buffer.1[offset..offset + self.n]
+        &mut self.buffer.1[offset + self.prefix..offset + self.n]
     }
 }
 
 impl<'a, 'b> Drop for WriteHandle<'a, 'b> {
     fn drop(&mut self) {
+        if self.padding != 0 {
+            // Published by the release of the tail below
+            self.buffer.0.padding.store(
+                self.current_tail.wrapping_sub(self.padding),
+                Ordering::Relaxed,
+            );
+        }
         self.buffer
             .0
             .tail
@@ -232,59 +467,165 @@ pub struct Consumer<'a>(&'a Header, &'a [u8]);
 unsafe impl<'a> Send for Consumer<'a> {}
 
 impl<'b, 'a: 'b> Consumer<'a> {
-    pub unsafe fn from_slice(buff: &'a mut [u8]) -> Self {
-        let capacity = prev_power_of_two(buff.len() - SIZEOF_HEADER);
-        let buffer = &*(buff.as_ptr() as *const Header);
-        assert!(capacity == buffer.capacity);
-        Consumer(buffer, &buff[SIZEOF_HEADER..SIZEOF_HEADER + capacity])
+    /// Attaches to the ring in `buff`, see `Header::attach`.
+    pub unsafe fn from_slice(buff: &'a mut [u8]) -> Result<Self, AttachError> {
+        let buffer = &*(Header::attach(buff)? as *const Header);
+        let capacity = buffer.capacity;
+        Ok(Consumer(
+            buffer,
+            &buff[Header::SIZE..Header::SIZE + capacity],
+        ))
     }
 
-    pub fn try_read(&'b mut self, n: usize, mut times: usize) -> Option<ReadHandle<'b, 'a>> {
-        let current_head = self.0.head.load(Ordering::Relaxed);
-        while self.0.shadow_tail.get().wrapping_sub(current_head) < n {
-            self.0.shadow_tail.set(self.0.tail.load(Ordering::Acquire));
-            if times == 0 {
-                return None;
-            }
-            times -= 1;
-        }
+    /// Hands out the next `n` bytes, `None` if they have not been written yet. The bytes are
+    /// contiguous as long as they lie within one region handed out by the producer.
+    pub fn try_read(&'b mut self, n: usize) -> Option<ReadHandle<'b, 'a>> {
+        let current_head = self.poll(n)?;
 
         Some(ReadHandle {
             buffer: self,
             current_head,
             n,
+            prefix: 0,
         })
     }
 
+    /// Drops up to `n` of the bytes that are available, returning how many were dropped. Padding
+    /// is stepped over without being counted.
     pub fn skip_n(&mut self, n: usize) -> usize {
-        let current_head = self.0.head.load(Ordering::Relaxed);
-
         self.0.shadow_tail.set(self.0.tail.load(Ordering::Acquire));
-        if current_head == self.0.shadow_tail.get() {
+        let tail = self.0.shadow_tail.get();
+        let current_head = self.0.head.load(Ordering::Relaxed);
+        if current_head == tail {
             return 0;
         }
-        let mut diff = self.0.shadow_tail.get().wrapping_sub(current_head);
+        let current_head = self.skip_padding(current_head);
+        let mut diff = tail.wrapping_sub(current_head);
         if diff > n {
             diff = n
         }
+        // Stop at padding that comes later, its bytes are not part of what is being skipped
+        let ahead = self
+            .0
+            .padding
+            .load(Ordering::Relaxed)
+            .wrapping_sub(current_head);
+        if ahead != 0 && ahead < diff {
+            diff = ahead;
+        }
         self.0
             .head
             .store(current_head.wrapping_add(diff), Ordering::Release);
         diff
     }
 
-    pub fn read(&'b mut self, n: usize) -> ReadHandle<'b, 'a> {
-        let current_head = self.0.head.load(Ordering::Relaxed);
-        //Am I off by one, or am I not? Hmm... use <= if I'm not
-        while self.0.shadow_tail.get().wrapping_sub(current_head) < n {
-            self.0.shadow_tail.set(self.0.tail.load(Ordering::Acquire));
-        }
+    /// Like `try_read`, but waits for the bytes as `wait` says.
+    pub fn read<W: WaitStrategy>(
+        &'b mut self,
+        n: usize,
+        wait: &W,
+    ) -> Result<ReadHandle<'b, 'a>, TimedOut> {
+        let current_head = wait.wait(|| self.poll(n))?;
 
-        ReadHandle {
+        Ok(ReadHandle {
             buffer: self,
             current_head,
             n,
+            prefix: 0,
+        })
+    }
+
+    /// Hands out the next frame, `None` if it has not been written yet.
+    pub fn try_recv_frame(&'b mut self) -> Result<Option<ReadHandle<'b, 'a>>, RecvError> {
+        let (current_head, len) = match self.poll_frame()? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+
+        Ok(Some(ReadHandle {
+            buffer: self,
+            current_head,
+            n: FRAME_PREFIX + len,
+            prefix: FRAME_PREFIX,
+        }))
+    }
+
+    /// Like `try_recv_frame`, but waits for the frame as `wait` says.
+    pub fn recv_frame<W: WaitStrategy>(
+        &'b mut self,
+        wait: &W,
+    ) -> Result<ReadHandle<'b, 'a>, RecvError> {
+        let (current_head, len) = wait.wait(|| match self.poll_frame() {
+            Ok(None) => None,
+            Ok(Some(frame)) => Some(Ok(frame)),
+            Err(e) => Some(Err(e)),
+        })??;
+
+        Ok(ReadHandle {
+            buffer: self,
+            current_head,
+            n: FRAME_PREFIX + len,
+            prefix: FRAME_PREFIX,
+        })
+    }
+
+    /// Start and length of the next frame. The producer publishes a frame as one region, so once
+    /// its prefix is there the rest of it is too.
+    fn poll_frame(&self) -> Result<Option<(usize, usize)>, RecvError> {
+        let head = match self.poll(FRAME_PREFIX) {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+        // The producer always starts a frame where it fits, so anything else came from a
+        // producer that doesn't play by the rules
+        let offset = head & (self.0.allocated_size - 1);
+        let room = self.0.capacity - offset;
+        if room < FRAME_PREFIX {
+            return Err(RecvError::Corrupted);
+        }
+        let len = self.1[offset..offset + FRAME_PREFIX]
+            .iter()
+            .enumerate()
+            .fold(0, |len, (i, b)| len | (*b as usize) << (8 * i));
+        // The length comes from the other side, which may be anything. The frame has to be
+        // written, stay within the buffer and end before any padding that follows it.
+        let padding = self.0.padding.load(Ordering::Relaxed).wrapping_sub(head);
+        if len > room - FRAME_PREFIX
+            || self.0.shadow_tail.get().wrapping_sub(head) < FRAME_PREFIX + len
+            || (padding != 0 && padding < FRAME_PREFIX + len)
+        {
+            return Err(RecvError::Corrupted);
         }
+        Ok(Some((head, len)))
+    }
+
+    /// Like `readable`, but looks at where the producer is again if the bytes don't seem to be
+    /// there.
+    fn poll(&self, n: usize) -> Option<usize> {
+        self.readable(n).or_else(|| {
+            self.0.shadow_tail.set(self.0.tail.load(Ordering::Acquire));
+            self.readable(n)
+        })
+    }
+
+    /// Where the next `n` bytes start, if the producer has written them as far as we know.
+    fn readable(&self, n: usize) -> Option<usize> {
+        let mut head = self.0.head.load(Ordering::Relaxed);
+        let tail = self.0.shadow_tail.get();
+        if head != tail {
+            head = self.skip_padding(head);
+        }
+        if tail.wrapping_sub(head) >= n {
+            Some(head)
+        } else {
+            None
+        }
+    }
+
+    /// Steps over the padding at `head` if the producer left some there. Only call this when
+    /// there is something to read at `head`, otherwise the padding may not be published yet.
+    fn skip_padding(&self, head: usize) -> usize {
+        self.0.past_padding(head)
     }
 }
 
@@ -299,13 +640,15 @@ pub struct ReadHandle<'a, 'b: 'a> {
     buffer: &'a mut Consumer<'b>,
     current_head: usize,
     n: usize,
+    /// Bytes at the start of the region that are not handed out, the length of a frame.
+    prefix: usize,
 }
 
 impl<'a, 'b> Deref for ReadHandle<'a, 'b> {
     type Target = [u8];
     fn deref(&self) -> &Self::Target {
         let offset = self.current_head & (self.buffer.0.allocated_size - 1);
-        &self.buffer.1[offset..offset + self.n]
+        &self.buffer.1[offset + self.prefix..offset + self.n]
     }
 }
 
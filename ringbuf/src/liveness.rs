@@ -0,0 +1,105 @@
+//! Whether the peers at the ends of a ring are still there. A peer announces an epoch at each
+//! end it attaches to, a new one every time it starts over, and keeps the heartbeat of the ring
+//! it writes to beating while it runs. `Header::new_inline_at` clears both, setting a ring up
+//! again is how the other peer acknowledges a new epoch.
+use super::Header;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum End {
+    Producer,
+    Consumer,
+}
+
+/// What a peer has been up to since the last check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Peer {
+    /// No epoch is announced, the ring was set up after the peer last attached.
+    Absent,
+    /// The heartbeat moved.
+    Alive,
+    /// The heartbeat has not moved for as many checks in a row.
+    Silent(usize),
+    /// The peer announced the given epoch, it started over since the last one.
+    Restarted(usize),
+}
+
+impl Header {
+    fn epoch_at(&self, end: End) -> &AtomicUsize {
+        match end {
+            End::Producer => &self.producer_epoch,
+            End::Consumer => &self.consumer_epoch,
+        }
+    }
+
+    fn heartbeat_at(&self, end: End) -> &AtomicUsize {
+        match end {
+            End::Producer => &self.producer_heartbeat,
+            End::Consumer => &self.consumer_heartbeat,
+        }
+    }
+
+    /// Tells the peer at the other end that we are at `end`, starting over as of `epoch`. Zero
+    /// is no epoch.
+    pub fn announce(&self, end: End, epoch: usize) {
+        self.epoch_at(end).store(epoch, Ordering::Release);
+    }
+
+    pub fn epoch(&self, end: End) -> usize {
+        self.epoch_at(end).load(Ordering::Acquire)
+    }
+
+    /// Shows that we are still running at `end`.
+    pub fn beat(&self, end: End) {
+        self.heartbeat_at(end).fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn heartbeat(&self, end: End) -> usize {
+        self.heartbeat_at(end).load(Ordering::Relaxed)
+    }
+}
+
+/// Keeps track of the peer at one end of a ring between checks.
+#[derive(Debug, Clone)]
+pub struct Monitor {
+    end: End,
+    epoch: usize,
+    heartbeat: usize,
+    silent: usize,
+}
+
+impl Monitor {
+    /// Watches the peer at `end`. An epoch it already announced does not count as a restart.
+    pub fn new(header: &Header, end: End) -> Self {
+        Monitor {
+            end,
+            epoch: header.epoch(end),
+            heartbeat: header.heartbeat(end),
+            silent: 0,
+        }
+    }
+
+    /// Meant to be called at a steady interval, several heartbeats long.
+    pub fn check(&mut self, header: &Header) -> Peer {
+        let epoch = header.epoch(self.end);
+        let heartbeat = header.heartbeat(self.end);
+        if epoch == 0 {
+            // Keeps the last epoch, the peer announces it again once the ring is set up
+            return Peer::Absent;
+        }
+        let moved = heartbeat != self.heartbeat;
+        self.heartbeat = heartbeat;
+        if epoch != self.epoch {
+            self.epoch = epoch;
+            self.silent = 0;
+            return Peer::Restarted(epoch);
+        }
+        if moved {
+            self.silent = 0;
+            Peer::Alive
+        } else {
+            self.silent += 1;
+            Peer::Silent(self.silent)
+        }
+    }
+}
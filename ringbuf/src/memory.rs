@@ -0,0 +1,49 @@
+use super::Header;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::{mem, slice};
+
+/// Memory for a ring with both ends in this process, rather than in memory shared with a peer.
+/// Backed by words, which keeps the header and the frames aligned.
+pub struct Memory {
+    words: *mut [usize],
+    len: usize,
+}
+
+unsafe impl Send for Memory {}
+unsafe impl Sync for Memory {}
+
+impl Memory {
+    /// Room for a header and `capacity` bytes of data, every word of it set to `fill`.
+    pub fn new(capacity: usize, fill: usize) -> Self {
+        let len = Header::SIZE + capacity;
+        let mut words = Vec::new();
+        words.resize(
+            (len + mem::size_of::<usize>() - 1) / mem::size_of::<usize>(),
+            fill,
+        );
+        Memory {
+            words: Box::into_raw(words.into_boxed_slice()),
+            len,
+        }
+    }
+
+    /// The memory to set a ring up in or attach to, as often as there are ends to it. Nothing
+    /// borrowed from it may outlive `self`.
+    pub unsafe fn buff<'a>(&self) -> &'a mut [u8] {
+        slice::from_raw_parts_mut(self.words as *mut usize as *mut u8, self.len)
+    }
+
+    /// Never frees the memory, so that it can be borrowed for good.
+    pub fn leak(self) -> &'static mut [u8] {
+        let buff = unsafe { self.buff() };
+        mem::forget(self);
+        buff
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        unsafe { drop(Box::from_raw(self.words)) };
+    }
+}
@@ -0,0 +1,288 @@
+//! Rings with any number of producers. Every write is a frame, which producers reserve and fill
+//! at the same time and commit in any order. The consumer gets to a frame once it is committed
+//! and the frames before it have been read.
+//!
+//! A frame starts with a word holding its length, which stays zero until the frame is committed.
+//! The consumer zeroes frames once it is done with them, so the ring has to start out zeroed, as
+//! `Header::new_inline_at` leaves it.
+use super::{AttachError, Header, RecvError, TimedOut, WaitStrategy};
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{mem, ptr, slice};
+
+const WORD: usize = mem::size_of::<usize>();
+/// Set in the word of a frame that is committed.
+const COMMITTED: usize = 1;
+/// Set in the word a producer leaves when a frame does not fit before the end of the buffer. The
+/// rest of the buffer is skipped.
+const PADDING: usize = 2;
+/// The length of the frame takes up the rest of the word.
+const LENGTH_SHIFT: usize = 2;
+
+/// Bytes a frame of `n` bytes takes up, its word included. Frames stay word aligned.
+fn frame_len(n: usize) -> usize {
+    WORD + (n + WORD - 1) / WORD * WORD
+}
+
+unsafe fn split(buff: &mut [u8]) -> Result<(&Header, *mut u8), AttachError> {
+    let header = &*(Header::attach(buff)? as *const Header);
+    let data = buff.as_mut_ptr().add(Header::SIZE);
+    assert!(data as usize % WORD == 0, "Ring buffer is not word aligned");
+    Ok((header, data))
+}
+
+/// The word at `position`, which is word aligned.
+unsafe fn word(header: &Header, data: *mut u8, position: usize) -> &AtomicUsize {
+    &*(data.add(position & (header.capacity - 1)) as *const AtomicUsize)
+}
+
+/// Can be shared between threads, each of them writing frames of its own.
+pub struct Producer<'a> {
+    header: &'a Header,
+    data: *mut u8,
+    _buffer: PhantomData<&'a mut [u8]>,
+}
+
+unsafe impl<'a> Send for Producer<'a> {}
+unsafe impl<'a> Sync for Producer<'a> {}
+
+impl<'b, 'a: 'b> Producer<'a> {
+    /// Attaches to the ring in `buff`, see `Header::attach`.
+    pub unsafe fn from_slice(buff: &'a mut [u8]) -> Result<Self, AttachError> {
+        let (header, data) = split(buff)?;
+        Ok(Producer {
+            header,
+            data,
+            _buffer: PhantomData,
+        })
+    }
+
+    /// The largest frame there can be room for.
+    pub fn max_frame(&self) -> usize {
+        self.header.capacity / 2 - WORD
+    }
+
+    /// Reserves a frame of `n` bytes, `None` if there is no room for it yet. Panics if the frame
+    /// would take up more than half of the capacity.
+    pub fn try_reserve_frame(&'b self, n: usize) -> Option<WriteHandle<'b, 'a>> {
+        let start = self.reserve(n)?;
+        Some(WriteHandle {
+            producer: self,
+            start,
+            n,
+        })
+    }
+
+    /// Like `try_reserve_frame`, but waits for room as `wait` says.
+    pub fn reserve_frame<W: WaitStrategy>(
+        &'b self,
+        n: usize,
+        wait: &W,
+    ) -> Result<WriteHandle<'b, 'a>, TimedOut> {
+        let start = wait.wait(|| self.reserve(n))?;
+        Ok(WriteHandle {
+            producer: self,
+            start,
+            n,
+        })
+    }
+
+    /// Copies `data` into a frame of its own.
+    pub fn send_frame<W: WaitStrategy>(&self, data: &[u8], wait: &W) -> Result<(), TimedOut> {
+        self.reserve_frame(data.len(), wait)?.copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Moves the tail past a frame of `n` bytes if it fits, returning where the frame starts.
+    fn reserve(&self, n: usize) -> Option<usize> {
+        let len = frame_len(n);
+        let capacity = self.header.capacity;
+        // Any larger and a frame might never fit, padding included
+        assert!(len <= capacity / 2, "Frame does not fit in the ring buffer");
+        loop {
+            // The head first, so that it can't be past the tail
+            let head = self.header.head.load(Ordering::Acquire);
+            let tail = self.header.tail.load(Ordering::Relaxed);
+            let offset = tail & (capacity - 1);
+            let padding = if offset + len > capacity {
+                capacity - offset
+            } else {
+                0
+            };
+            let end = tail.wrapping_add(padding + len);
+            if end.wrapping_sub(head) > capacity {
+                return None;
+            }
+            if self
+                .header
+                .tail
+                .compare_exchange_weak(tail, end, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+            {
+                if padding != 0 {
+                    self.word(tail)
+                        .store(PADDING | COMMITTED, Ordering::Release);
+                }
+                return Some(tail.wrapping_add(padding));
+            }
+        }
+    }
+
+    fn word(&self, position: usize) -> &AtomicUsize {
+        unsafe { word(self.header, self.data, position) }
+    }
+}
+
+impl<'a> Deref for Producer<'a> {
+    type Target = Header;
+    fn deref(&self) -> &Self::Target {
+        self.header
+    }
+}
+
+/// A reserved frame, committed when dropped.
+pub struct WriteHandle<'a, 'b: 'a> {
+    producer: &'a Producer<'b>,
+    start: usize,
+    n: usize,
+}
+
+impl<'a, 'b> Deref for WriteHandle<'a, 'b> {
+    type Target = [u8];
+    fn deref(&self) -> &Self::Target {
+        let offset = self.start.wrapping_add(WORD) & (self.producer.header.capacity - 1);
+        unsafe { slice::from_raw_parts(self.producer.data.add(offset), self.n) }
+    }
+}
+
+impl<'a, 'b> DerefMut for WriteHandle<'a, 'b> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        let offset = self.start.wrapping_add(WORD) & (self.producer.header.capacity - 1);
+        unsafe { slice::from_raw_parts_mut(self.producer.data.add(offset), self.n) }
+    }
+}
+
+impl<'a, 'b> Drop for WriteHandle<'a, 'b> {
+    fn drop(&mut self) {
+        self.producer
+            .word(self.start)
+            .store(self.n << LENGTH_SHIFT | COMMITTED, Ordering::Release);
+    }
+}
+
+pub struct Consumer<'a> {
+    header: &'a Header,
+    data: *mut u8,
+    _buffer: PhantomData<&'a mut [u8]>,
+}
+
+unsafe impl<'a> Send for Consumer<'a> {}
+
+impl<'b, 'a: 'b> Consumer<'a> {
+    /// Attaches to the ring in `buff`, see `Header::attach`.
+    pub unsafe fn from_slice(buff: &'a mut [u8]) -> Result<Self, AttachError> {
+        let (header, data) = split(buff)?;
+        Ok(Consumer {
+            header,
+            data,
+            _buffer: PhantomData,
+        })
+    }
+
+    /// Hands out the next frame, `None` if it is not committed yet.
+    pub fn try_recv_frame(&'b mut self) -> Result<Option<ReadHandle<'b, 'a>>, RecvError> {
+        let (start, n) = match self.poll()? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        Ok(Some(ReadHandle {
+            consumer: self,
+            start,
+            n,
+        }))
+    }
+
+    /// Like `try_recv_frame`, but waits for the frame as `wait` says.
+    pub fn recv_frame<W: WaitStrategy>(
+        &'b mut self,
+        wait: &W,
+    ) -> Result<ReadHandle<'b, 'a>, RecvError> {
+        let (start, n) = wait.wait(|| match self.poll() {
+            Ok(None) => None,
+            Ok(Some(frame)) => Some(Ok(frame)),
+            Err(e) => Some(Err(e)),
+        })??;
+        Ok(ReadHandle {
+            consumer: self,
+            start,
+            n,
+        })
+    }
+
+    /// Start and length of the next frame if it is committed, stepping over padding.
+    fn poll(&self) -> Result<Option<(usize, usize)>, RecvError> {
+        loop {
+            let head = self.header.head.load(Ordering::Relaxed);
+            let word = self.word(head);
+            let value = word.load(Ordering::Acquire);
+            if value & COMMITTED == 0 {
+                return Ok(None);
+            }
+            let offset = head & (self.header.capacity - 1);
+            if value & PADDING == 0 {
+                let n = value >> LENGTH_SHIFT;
+                // The word comes from the producers, which may have written anything
+                if n > self.header.capacity - offset - WORD {
+                    return Err(RecvError::Corrupted);
+                }
+                return Ok(Some((head, n)));
+            }
+            word.store(0, Ordering::Relaxed);
+            self.header.head.store(
+                head.wrapping_add(self.header.capacity - offset),
+                Ordering::Release,
+            );
+        }
+    }
+
+    fn word(&self, position: usize) -> &AtomicUsize {
+        unsafe { word(self.header, self.data, position) }
+    }
+}
+
+impl<'a> Deref for Consumer<'a> {
+    type Target = Header;
+    fn deref(&self) -> &Self::Target {
+        self.header
+    }
+}
+
+/// A committed frame, released to the producers when dropped.
+pub struct ReadHandle<'a, 'b: 'a> {
+    consumer: &'a mut Consumer<'b>,
+    start: usize,
+    n: usize,
+}
+
+impl<'a, 'b> Deref for ReadHandle<'a, 'b> {
+    type Target = [u8];
+    fn deref(&self) -> &Self::Target {
+        let offset = self.start.wrapping_add(WORD) & (self.consumer.header.capacity - 1);
+        unsafe { slice::from_raw_parts(self.consumer.data.add(offset), self.n) }
+    }
+}
+
+impl<'a, 'b> Drop for ReadHandle<'a, 'b> {
+    fn drop(&mut self) {
+        let header = self.consumer.header;
+        let len = frame_len(self.n);
+        unsafe {
+            let offset = self.start & (header.capacity - 1);
+            ptr::write_bytes(self.consumer.data.add(offset), 0, len);
+        }
+        header
+            .head
+            .store(self.start.wrapping_add(len), Ordering::Release);
+    }
+}
@@ -0,0 +1,124 @@
+use core::sync::atomic;
+
+/// The other side did not catch up in time, nothing was written or read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedOut;
+
+/// How to pass the time until the other side of the ring catches up.
+pub trait WaitStrategy {
+    /// Calls `poll` until it returns a value, at least once, waiting in between.
+    fn wait<T, F: FnMut() -> Option<T>>(&self, poll: F) -> Result<T, TimedOut>;
+}
+
+/// Polls again right away. Only suited to waits that are known to be short, or to places where
+/// nothing else may run anyway.
+#[derive(Debug, Clone, Copy)]
+pub struct Spin {
+    limit: Option<usize>,
+}
+
+impl Spin {
+    pub fn forever() -> Self {
+        Spin { limit: None }
+    }
+
+    /// Gives up after `n` polls.
+    pub fn times(n: usize) -> Self {
+        Spin { limit: Some(n) }
+    }
+}
+
+impl WaitStrategy for Spin {
+    fn wait<T, F: FnMut() -> Option<T>>(&self, mut poll: F) -> Result<T, TimedOut> {
+        let mut polls = 0;
+        loop {
+            if let Some(value) = poll() {
+                return Ok(value);
+            }
+            polls += 1;
+            if self.limit.map_or(false, |limit| polls >= limit) {
+                return Err(TimedOut);
+            }
+            atomic::spin_loop_hint();
+        }
+    }
+}
+
+/// Spins for a while, then lets something else run between polls.
+#[derive(Debug, Clone, Copy)]
+pub struct SpinThenYield<Y> {
+    spins: usize,
+    yields: Option<usize>,
+    yield_now: Y,
+}
+
+impl<Y: Fn()> SpinThenYield<Y> {
+    /// Polls `spins` times back to back, then calls `yield_now` before every poll, giving up
+    /// after `yields` calls if there is a limit.
+    pub fn new(spins: usize, yields: Option<usize>, yield_now: Y) -> Self {
+        SpinThenYield {
+            spins,
+            yields,
+            yield_now,
+        }
+    }
+}
+
+impl<Y: Fn()> WaitStrategy for SpinThenYield<Y> {
+    fn wait<T, F: FnMut() -> Option<T>>(&self, mut poll: F) -> Result<T, TimedOut> {
+        if let Ok(value) = Spin::times(self.spins).wait(&mut poll) {
+            return Ok(value);
+        }
+        let mut yields = 0;
+        loop {
+            if self.yields.map_or(false, |limit| yields >= limit) {
+                return Err(TimedOut);
+            }
+            (self.yield_now)();
+            yields += 1;
+            if let Some(value) = poll() {
+                return Ok(value);
+            }
+        }
+    }
+}
+
+/// Something the other side signals after making progress, such as an eventfd.
+pub trait Notifier {
+    /// Blocks until signalled, or gives up. A signal sent while nobody was waiting must not be
+    /// lost, but waking up without one is fine.
+    fn wait(&self) -> Result<(), TimedOut>;
+}
+
+impl<'a, N: Notifier> Notifier for &'a N {
+    fn wait(&self) -> Result<(), TimedOut> {
+        (**self).wait()
+    }
+}
+
+/// Spins for a while, then blocks on a notifier between polls. Times out when the notifier does.
+#[derive(Debug, Clone, Copy)]
+pub struct Blocking<N> {
+    spins: usize,
+    notifier: N,
+}
+
+impl<N: Notifier> Blocking<N> {
+    pub fn new(spins: usize, notifier: N) -> Self {
+        Blocking { spins, notifier }
+    }
+}
+
+impl<N: Notifier> WaitStrategy for Blocking<N> {
+    fn wait<T, F: FnMut() -> Option<T>>(&self, mut poll: F) -> Result<T, TimedOut> {
+        if let Ok(value) = Spin::times(self.spins).wait(&mut poll) {
+            return Ok(value);
+        }
+        loop {
+            self.notifier.wait()?;
+            if let Some(value) = poll() {
+                return Ok(value);
+            }
+        }
+    }
+}
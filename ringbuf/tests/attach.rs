@@ -0,0 +1,101 @@
+extern crate ringbuf;
+use ringbuf::*;
+use std::cell::Cell;
+
+/// Memory for a ring with `capacity` bytes of data, holding whatever `fill` says.
+fn memory(capacity: usize, fill: usize) -> &'static mut [u8] {
+    Memory::new(capacity, fill).leak()
+}
+
+/// The word of the header at `index`.
+fn word(buff: &mut [u8], index: usize) -> &mut usize {
+    unsafe { &mut *(buff.as_mut_ptr() as *mut usize).add(index) }
+}
+
+#[test]
+fn uninitialized() {
+    for &fill in &[0, !0] {
+        let buff = memory(256, fill);
+        unsafe {
+            assert_eq!(Header::attach(buff).err(), Some(AttachError::Uninitialized));
+            assert!(Consumer::from_slice(buff).is_err());
+            assert!(mpsc::Producer::from_slice(buff).is_err());
+        }
+    }
+}
+
+#[test]
+fn version_mismatch() {
+    let buff = memory(256, 0);
+    unsafe {
+        Header::new_inline_at(buff);
+        *word(buff, 1) = VERSION + 1;
+        assert_eq!(
+            Header::attach(buff).err(),
+            Some(AttachError::VersionMismatch(VERSION + 1))
+        );
+    }
+}
+
+#[test]
+fn inconsistent() {
+    let buff = memory(512, 0);
+    unsafe {
+        Header::new_inline_at(&mut buff[..Header::SIZE + 256]);
+        assert!(Header::attach(&buff[..Header::SIZE + 256]).is_ok());
+        // Attached with the wrong length
+        assert_eq!(Header::attach(buff).err(), Some(AttachError::Inconsistent));
+
+        Header::new_inline_at(buff);
+        let mut producer = Producer::from_slice(&mut *(buff as *mut [u8])).unwrap();
+        producer.try_write(100).unwrap();
+        // The head of the consumer cacheline, now more than the capacity behind the tail
+        *word(buff, 8) = 0usize.wrapping_sub(1000);
+        assert_eq!(Header::attach(buff).err(), Some(AttachError::Inconsistent));
+    }
+}
+
+#[test]
+fn generations() {
+    let buff = memory(256, !0);
+    unsafe {
+        assert_eq!(Header::new_inline_at(buff).generation(), 1);
+        assert_eq!(Header::new_inline_at(buff).generation(), 2);
+        assert_eq!(Header::attach(buff).unwrap().generation(), 2);
+    }
+}
+
+/// Sets the ring up after being waited on a number of times, standing in for the peer.
+struct Peer {
+    buff: *mut [u8],
+    waits: Cell<usize>,
+}
+
+impl Notifier for Peer {
+    fn wait(&self) -> Result<(), TimedOut> {
+        self.waits.set(self.waits.get() + 1);
+        if self.waits.get() == 3 {
+            unsafe { Header::new_inline_at(&mut *self.buff) };
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn wait_attach() {
+    let buff = memory(256, 0);
+    unsafe {
+        assert_eq!(
+            Header::wait_attach(buff, &Spin::times(10)).err(),
+            Some(AttachError::Uninitialized)
+        );
+
+        let peer = Peer {
+            buff: buff as *mut [u8],
+            waits: Cell::new(0),
+        };
+        let header = Header::wait_attach(&*peer.buff, &Blocking::new(10, &peer)).unwrap();
+        assert_eq!(header.capacity(), 256);
+        assert_eq!(peer.waits.get(), 3);
+    }
+}
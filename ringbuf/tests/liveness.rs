@@ -0,0 +1,56 @@
+extern crate ringbuf;
+use ringbuf::*;
+
+fn memory(capacity: usize) -> &'static mut [u8] {
+    Memory::new(capacity, 0).leak()
+}
+
+#[test]
+fn heartbeats() {
+    let buff = memory(256);
+    let header = unsafe { Header::new_inline_at(buff) };
+    let mut monitor = Monitor::new(header, End::Producer);
+    assert_eq!(monitor.check(header), Peer::Absent);
+
+    header.announce(End::Producer, 7);
+    assert_eq!(monitor.check(header), Peer::Restarted(7));
+    header.beat(End::Producer);
+    assert_eq!(monitor.check(header), Peer::Alive);
+    assert_eq!(monitor.check(header), Peer::Silent(1));
+    assert_eq!(monitor.check(header), Peer::Silent(2));
+    // The other end has a heartbeat of its own
+    header.beat(End::Consumer);
+    assert_eq!(monitor.check(header), Peer::Silent(3));
+    header.beat(End::Producer);
+    assert_eq!(monitor.check(header), Peer::Alive);
+}
+
+#[test]
+fn reset_handshake() {
+    let buff = memory(256);
+    let header = unsafe { Header::new_inline_at(&mut *(buff as *mut [u8])) };
+    let mut monitor = Monitor::new(header, End::Producer);
+    header.announce(End::Producer, 3);
+    assert_eq!(monitor.check(header), Peer::Restarted(3));
+
+    // Setting the ring up again acknowledges the epoch, which the peer then announces again
+    let header = unsafe { Header::new_inline_at(&mut *(buff as *mut [u8])) };
+    assert_eq!(header.epoch(End::Producer), 0);
+    assert_eq!(monitor.check(header), Peer::Absent);
+    header.announce(End::Producer, 3);
+    header.beat(End::Producer);
+    assert_eq!(monitor.check(header), Peer::Alive);
+
+    // Starting over again
+    header.announce(End::Producer, 4);
+    assert_eq!(monitor.check(header), Peer::Restarted(4));
+}
+
+#[test]
+fn announced_before_watching() {
+    let buff = memory(256);
+    let header = unsafe { Header::new_inline_at(buff) };
+    header.announce(End::Consumer, 5);
+    let mut monitor = Monitor::new(header, End::Consumer);
+    assert_eq!(monitor.check(header), Peer::Silent(1));
+}
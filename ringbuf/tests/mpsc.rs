@@ -0,0 +1,145 @@
+extern crate ringbuf;
+use ringbuf::mpsc::{Consumer, Producer};
+use ringbuf::*;
+use std::sync::Arc;
+use std::thread;
+
+fn ring(capacity: usize) -> (Producer<'static>, Consumer<'static>) {
+    let buff = Memory::new(capacity, !0).leak();
+    unsafe {
+        Header::new_inline_at(buff);
+        (
+            Producer::from_slice(&mut *(buff as *mut [u8])).unwrap(),
+            Consumer::from_slice(&mut *(buff as *mut [u8])).unwrap(),
+        )
+    }
+}
+
+fn patient() -> SpinThenYield<fn()> {
+    SpinThenYield::new(100, None, thread::yield_now)
+}
+
+#[test]
+fn frames() {
+    let (producer, mut consumer) = ring(256);
+    assert!(consumer.try_recv_frame().unwrap().is_none());
+    // Lengths that aren't word multiples and keep frames landing across the end
+    for i in 0..500 {
+        let len = (i * 13) % 100;
+        {
+            let mut frame = producer.try_reserve_frame(len).unwrap();
+            for (j, b) in frame.iter_mut().enumerate() {
+                *b = (i + j) as u8;
+            }
+        }
+        let frame = consumer.try_recv_frame().unwrap().unwrap();
+        assert_eq!(frame.len(), len);
+        for (j, b) in frame.iter().enumerate() {
+            assert_eq!(*b, (i + j) as u8);
+        }
+    }
+    assert!(consumer.try_recv_frame().unwrap().is_none());
+}
+
+#[test]
+fn out_of_order_commits() {
+    let (producer, mut consumer) = ring(256);
+    let mut first = producer.try_reserve_frame(3).unwrap();
+    let mut second = producer.try_reserve_frame(5).unwrap();
+    second.copy_from_slice(b"world");
+    drop(second);
+    // The second frame waits for the first
+    assert!(consumer.try_recv_frame().unwrap().is_none());
+    first.copy_from_slice(b"hey");
+    drop(first);
+    assert_eq!(&consumer.try_recv_frame().unwrap().unwrap()[..], b"hey");
+    assert_eq!(&consumer.try_recv_frame().unwrap().unwrap()[..], b"world");
+    assert!(consumer.try_recv_frame().unwrap().is_none());
+}
+
+#[test]
+fn full() {
+    let (producer, mut consumer) = ring(256);
+    // Frames of 64 bytes, their word included
+    for _ in 0..4 {
+        producer.try_reserve_frame(56).unwrap();
+    }
+    assert!(producer.try_reserve_frame(1).is_none());
+    assert_eq!(
+        producer.reserve_frame(1, &Spin::times(10)).err(),
+        Some(TimedOut)
+    );
+    assert_eq!(consumer.try_recv_frame().unwrap().unwrap().len(), 56);
+    assert!(producer.try_reserve_frame(56).is_some());
+    assert_eq!(consumer.recv_frame(&Spin::times(10)).unwrap().len(), 56);
+}
+
+#[test]
+#[should_panic]
+fn frame_larger_than_half() {
+    let (producer, _consumer) = ring(256);
+    producer.try_reserve_frame(121);
+}
+
+#[test]
+fn max_frame() {
+    let (producer, mut consumer) = ring(256);
+    assert_eq!(producer.max_frame(), 120);
+    assert!(producer.try_reserve_frame(producer.max_frame()).is_some());
+    assert_eq!(consumer.recv_frame(&Spin::times(10)).unwrap().len(), 120);
+}
+
+#[test]
+fn producers() {
+    const PRODUCERS: usize = 4;
+    const FRAMES: usize = 2000;
+    let (producer, mut consumer) = ring(1024);
+    let producer = Arc::new(producer);
+    let threads: Vec<_> = (0..PRODUCERS)
+        .map(|id| {
+            let producer = producer.clone();
+            thread::spawn(move || {
+                for seq in 0..FRAMES {
+                    let len = 2 + (seq * 7 + id) % 200;
+                    let mut frame = producer.reserve_frame(len, &patient()).unwrap();
+                    frame[0] = id as u8;
+                    frame[1] = seq as u8;
+                    for b in frame[2..].iter_mut() {
+                        *b = id as u8;
+                    }
+                }
+            })
+        })
+        .collect();
+
+    // Each producer's frames arrive whole and in the order they were written
+    let mut next = [0usize; PRODUCERS];
+    for _ in 0..PRODUCERS * FRAMES {
+        let frame = consumer.recv_frame(&patient()).unwrap();
+        let id = frame[0] as usize;
+        assert_eq!(frame[1], next[id] as u8);
+        assert_eq!(frame.len(), 2 + (next[id] * 7 + id) % 200);
+        assert!(frame[2..].iter().all(|b| *b == id as u8));
+        next[id] += 1;
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert!(consumer.try_recv_frame().unwrap().is_none());
+}
+
+#[test]
+fn corrupt_length() {
+    let buff = Memory::new(256, 0).leak();
+    unsafe {
+        Header::new_inline_at(buff);
+        let mut consumer = Consumer::from_slice(&mut *(buff as *mut [u8])).unwrap();
+        // A committed frame claiming more than the ring holds, its length above two flag bits
+        *(buff.as_mut_ptr().add(Header::SIZE) as *mut usize) = 300 << 2 | 1;
+        assert_eq!(consumer.try_recv_frame().err(), Some(RecvError::Corrupted));
+        assert_eq!(
+            consumer.recv_frame(&Spin::times(10)).err(),
+            Some(RecvError::Corrupted)
+        );
+    }
+}
@@ -0,0 +1,260 @@
+extern crate ringbuf;
+use ringbuf::*;
+use std::cell::{Cell, RefCell};
+use std::thread;
+
+/// A ring with `capacity` bytes of data, shared by a producer and a consumer the way both ends of
+/// an ivshmem mapping share it.
+fn ring(capacity: usize) -> (Producer<'static>, Consumer<'static>) {
+    let buff = Memory::new(capacity, 0).leak();
+    unsafe {
+        assert_eq!(Header::new_inline_at(buff).capacity(), capacity);
+        (
+            Producer::from_slice(&mut *(buff as *mut [u8])).unwrap(),
+            Consumer::from_slice(&mut *(buff as *mut [u8])).unwrap(),
+        )
+    }
+}
+
+/// Yields to the other thread instead of spinning through its time slice.
+fn patient() -> SpinThenYield<fn()> {
+    SpinThenYield::new(100, None, thread::yield_now)
+}
+
+/// Writes a length prefixed message, the prefix and the payload as one region.
+fn send(producer: &mut Producer, id: u32, len: usize) {
+    let mut buffer = producer.write(4 + len, &patient()).unwrap();
+    for (i, b) in buffer[..4].iter_mut().enumerate() {
+        *b = (len >> (8 * i)) as u8;
+    }
+    for (i, b) in buffer[4..].iter_mut().enumerate() {
+        *b = (id as usize + i) as u8;
+    }
+}
+
+/// Reads the prefix and the payload separately, like the ivshrpc listeners do.
+fn recv(consumer: &mut Consumer, id: u32) -> usize {
+    let len = consumer
+        .read(4, &patient())
+        .unwrap()
+        .iter()
+        .enumerate()
+        .fold(0, |len, (i, b)| len | (*b as usize) << (8 * i));
+    let payload = consumer.read(len, &patient()).unwrap();
+    for (i, b) in payload.iter().enumerate() {
+        assert_eq!(*b, (id as usize + i) as u8, "message {} byte {}", id, i);
+    }
+    len
+}
+
+#[test]
+fn wraparound() {
+    let (mut producer, mut consumer) = ring(256);
+    // Lengths that don't divide the capacity, so messages keep landing across the end
+    for id in 0..1000 {
+        let len = (id as usize * 37) % 200;
+        send(&mut producer, id, len);
+        assert_eq!(recv(&mut consumer, id), len);
+        assert_eq!(consumer.size(), 0);
+    }
+}
+
+#[test]
+fn padding_is_not_data() {
+    let (mut producer, mut consumer) = ring(256);
+    send(&mut producer, 0, 196);
+    assert_eq!(recv(&mut consumer, 0), 196);
+
+    // 100 bytes don't fit in the 56 left before the end
+    send(&mut producer, 1, 96);
+    assert_eq!(producer.size(), 56 + 100);
+    assert!(consumer.try_read(101).is_none());
+    assert_eq!(consumer.try_read(100).unwrap().len(), 100);
+    assert_eq!(consumer.size(), 0);
+
+    // Skipping stops at padding and steps over it without counting it
+    for id in 2..6 {
+        send(&mut producer, id, 46);
+    }
+    assert_eq!(consumer.skip_n(1000), 150);
+    assert_eq!(consumer.skip_n(1000), 50);
+    assert_eq!(consumer.skip_n(1000), 0);
+}
+
+#[test]
+fn full() {
+    let (mut producer, mut consumer) = ring(256);
+    assert!(producer.try_write(200).is_some());
+    // The padding needed to wrap counts against the free space
+    assert!(producer.try_write(100).is_none());
+    assert_eq!(consumer.skip_n(200), 200);
+    assert!(producer.try_write(100).is_some());
+    assert_eq!(consumer.try_read(100).unwrap().len(), 100);
+}
+
+#[test]
+fn wrap_into_empty_ring() {
+    let buff = Memory::new(64, 0).leak();
+    let (mut producer, mut consumer) = unsafe {
+        Header::new_inline_at(buff);
+        (
+            Producer::from_slice(&mut *(buff as *mut [u8])).unwrap(),
+            Consumer::from_slice(&mut *(buff as *mut [u8])).unwrap(),
+        )
+    };
+    assert!(producer.try_write(8).is_some());
+    assert_eq!(consumer.skip_n(8), 8);
+    // The 56 bytes of padding and the region overlap, with nothing to read they can
+    assert!(producer.try_write(60).is_some());
+    assert_eq!(producer.size(), 64);
+    assert_eq!(producer.free_space(), 0);
+    assert!(producer.try_write(1).is_none());
+    assert!(unsafe { Header::attach(buff) }.is_ok());
+    assert_eq!(consumer.try_read(60).unwrap().len(), 60);
+    assert_eq!(consumer.size(), 0);
+    assert!(producer.try_write(60).is_some());
+}
+
+#[test]
+#[should_panic]
+fn region_larger_than_ring() {
+    let (mut producer, _consumer) = ring(256);
+    producer.try_write(256);
+}
+
+#[test]
+fn threaded() {
+    let (mut producer, mut consumer) = ring(1024);
+    let writer = thread::spawn(move || {
+        for id in 0..5000 {
+            send(&mut producer, id, (id as usize * 131) % 900);
+        }
+    });
+    for id in 0..5000 {
+        assert_eq!(recv(&mut consumer, id), (id as usize * 131) % 900);
+    }
+    writer.join().unwrap();
+}
+
+#[test]
+fn frames() {
+    let (mut producer, mut consumer) = ring(256);
+    assert!(consumer.try_recv_frame().unwrap().is_none());
+    for id in 0..1000 {
+        let len = (id * 37) % 200;
+        let data: Vec<u8> = (0..len).map(|i| (id + i) as u8).collect();
+        producer.send_frame(&data, &patient()).unwrap();
+        assert_eq!(&consumer.try_recv_frame().unwrap().unwrap()[..], &data[..]);
+        assert_eq!(consumer.size(), 0);
+    }
+    producer.send_frame(&[], &patient()).unwrap();
+    assert_eq!(consumer.try_recv_frame().unwrap().unwrap().len(), 0);
+    assert!(consumer.try_recv_frame().unwrap().is_none());
+}
+
+#[test]
+fn frame_is_visible_whole() {
+    let (mut producer, mut consumer) = ring(256);
+    {
+        let mut frame = producer.try_reserve_frame(5).unwrap();
+        frame.copy_from_slice(b"hello");
+        assert!(consumer.try_recv_frame().unwrap().is_none());
+    }
+    assert_eq!(&consumer.try_recv_frame().unwrap().unwrap()[..], b"hello");
+    // Prefix included, a frame of 252 bytes no longer fits
+    assert!(producer.try_reserve_frame(251).is_some());
+    assert_eq!(consumer.try_recv_frame().unwrap().unwrap().len(), 251);
+}
+
+#[test]
+#[should_panic]
+fn frame_larger_than_ring() {
+    let (mut producer, _consumer) = ring(256);
+    producer.try_reserve_frame(252);
+}
+
+struct Countdown(Cell<usize>);
+
+impl Notifier for Countdown {
+    fn wait(&self) -> Result<(), TimedOut> {
+        match self.0.get() {
+            0 => Err(TimedOut),
+            left => {
+                self.0.set(left - 1);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[test]
+fn timeouts() {
+    let (mut producer, mut consumer) = ring(256);
+    assert_eq!(consumer.read(1, &Spin::times(10)).err(), Some(TimedOut));
+
+    producer.try_write(200).unwrap();
+    let yields = Cell::new(0);
+    let wait = SpinThenYield::new(10, Some(3), || yields.set(yields.get() + 1));
+    assert_eq!(producer.write(100, &wait).err(), Some(TimedOut));
+    assert_eq!(yields.get(), 3);
+
+    let notifier = Countdown(Cell::new(2));
+    let wait = Blocking::new(10, &notifier);
+    assert_eq!(consumer.read(201, &wait).err(), Some(TimedOut));
+    assert_eq!(notifier.0.get(), 0);
+    assert_eq!(consumer.read(200, &wait).unwrap().len(), 200);
+}
+
+/// Writes a message each time it is waited on, standing in for the other side.
+struct Writer<'a>(RefCell<Producer<'a>>);
+
+impl<'a> Notifier for Writer<'a> {
+    fn wait(&self) -> Result<(), TimedOut> {
+        self.0.borrow_mut().try_write(10).ok_or(TimedOut)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn blocking() {
+    let (producer, mut consumer) = ring(256);
+    let wait = Blocking::new(0, Writer(RefCell::new(producer)));
+    assert_eq!(consumer.read(30, &wait).unwrap().len(), 30);
+    assert_eq!(consumer.size(), 0);
+}
+
+#[test]
+fn corrupt_frame_length() {
+    let (mut producer, mut consumer) = ring(256);
+    // A prefix claiming far more than was written
+    producer
+        .try_write(4)
+        .unwrap()
+        .copy_from_slice(&[0xff, 0xff, 0, 0]);
+    assert_eq!(consumer.try_recv_frame().err(), Some(RecvError::Corrupted));
+    assert_eq!(
+        consumer.recv_frame(&Spin::times(10)).err(),
+        Some(RecvError::Corrupted)
+    );
+}
+
+#[test]
+fn frame_across_padding() {
+    let (mut producer, mut consumer) = ring(256);
+    producer.try_write(200).unwrap();
+    assert_eq!(consumer.skip_n(200), 200);
+    // A prefix that runs its frame into the padding and past the end of the buffer, while
+    // enough has been written after it for the length to look plausible
+    producer.try_write(40).unwrap()[..4].copy_from_slice(&[100, 0, 0, 0]);
+    producer.try_write(60).unwrap();
+    assert_eq!(consumer.size(), 116);
+    assert_eq!(consumer.try_recv_frame().err(), Some(RecvError::Corrupted));
+
+    // One that stays within the buffer, but still reaches into the padding
+    let (mut producer, mut consumer) = ring(256);
+    producer.try_write(200).unwrap();
+    assert_eq!(consumer.skip_n(200), 200);
+    producer.try_write(40).unwrap()[..4].copy_from_slice(&[40, 0, 0, 0]);
+    producer.try_write(60).unwrap();
+    assert_eq!(consumer.try_recv_frame().err(), Some(RecvError::Corrupted));
+}
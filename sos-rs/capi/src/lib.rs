@@ -0,0 +1,697 @@
+//! C interface to the SOS codec, so that C modules encode and decode values with the same code
+//! as the kernel. `libc/include/sos_capi.h` is generated from this crate with cbindgen.
+//!
+//! Encoders and decoders live in storage the caller provides and borrow the buffer they were
+//! started with, which has to outlive them. Every call reports how it went with a `SosStatus`.
+
+#![no_std]
+#![cfg_attr(feature = "panic-handler", feature(lang_items, panic_implementation))]
+
+extern crate sos;
+#[cfg(not(feature = "panic-handler"))]
+extern crate std;
+
+use core::{mem, ptr, slice, str};
+use sos::{
+    validate_sos, ArrayElement, ArrayType, DecodeIter, EncodeError, Encoder, Extension, Function,
+    Layout, Value, EXTENSION_MAX_ID, SOS_HEADER_SIZE, SOS_MAGIC, SOS_VERSION,
+};
+
+#[allow(non_camel_case_types)]
+type c_char = i8;
+
+/// Header flag for `Layout::Aligned`, pass it to `sos_encoder_init`.
+pub const SOS_FLAG_ALIGNED: u8 = 1;
+
+/// Returned by `sos_peek_type` once there are no values left.
+pub const SOS_TYPE_INVALID: u8 = 0;
+pub const SOS_TYPE_INT32: u8 = 1;
+pub const SOS_TYPE_UINT32: u8 = 2;
+pub const SOS_TYPE_INT64: u8 = 3;
+pub const SOS_TYPE_UINT64: u8 = 4;
+pub const SOS_TYPE_FLOAT: u8 = 5;
+pub const SOS_TYPE_DOUBLE: u8 = 6;
+pub const SOS_TYPE_ERROR: u8 = 7;
+pub const SOS_TYPE_STRING: u8 = 8;
+pub const SOS_TYPE_OPAQUE: u8 = 9;
+pub const SOS_TYPE_FUNCTION: u8 = 10;
+pub const SOS_TYPE_EMBEDDED: u8 = 11;
+pub const SOS_TYPE_BOOL: u8 = 12;
+pub const SOS_TYPE_UNIT: u8 = 13;
+pub const SOS_TYPE_INT8: u8 = 14;
+pub const SOS_TYPE_UINT8: u8 = 15;
+pub const SOS_TYPE_INT16: u8 = 16;
+pub const SOS_TYPE_UINT16: u8 = 17;
+pub const SOS_TYPE_ARRAY: u8 = 18;
+pub const SOS_TYPE_MAP: u8 = 19;
+pub const SOS_TYPE_FAULT: u8 = 20;
+/// Extension values have this type plus their type id.
+pub const SOS_TYPE_EXTENSION: u8 = 0x80;
+pub const SOS_EXTENSION_MAX_ID: u8 = 0x7f;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SosStatus {
+    Ok,
+    /// The buffer is too small for the value, nothing was written.
+    NoSpace,
+    /// Too many embedded values are open.
+    TooDeep,
+    /// `sos_close` without an embedded value open.
+    NotOpen,
+    /// `sos_finish` with embedded values still open.
+    Unclosed,
+    /// The input is not valid SOS, or the flags or an extension type id are out of range.
+    Malformed,
+    /// A string is not valid UTF-8.
+    InvalidUtf8,
+    /// The next value is of another type, it was left in place.
+    WrongType,
+    /// There are no values left.
+    End,
+    /// The next value is of the right type, but can't be pointed to where it is because the
+    /// buffer was not encoded with `SOS_FLAG_ALIGNED` or does not start 8 byte aligned. It was
+    /// left in place.
+    Unaligned,
+}
+
+impl From<EncodeError> for SosStatus {
+    fn from(e: EncodeError) -> Self {
+        match e {
+            EncodeError::NoSpace => SosStatus::NoSpace,
+            EncodeError::TooDeep => SosStatus::TooDeep,
+            EncodeError::NotOpen => SosStatus::NotOpen,
+            EncodeError::Unclosed => SosStatus::Unclosed,
+        }
+    }
+}
+
+/// Room for an encoder, C code can put it wherever it likes.
+#[repr(C)]
+pub struct SosEncoder {
+    _private: [u64; 40],
+}
+
+/// Room for a decoder, C code can put it wherever it likes.
+#[repr(C)]
+pub struct SosDecoder {
+    _private: [u64; 16],
+}
+
+// The storage C sets aside has to fit what is kept in it, otherwise these arrays get the wrong
+// length and the build fails
+#[allow(dead_code)]
+const ENCODER_FITS: [(); 0] =
+    [(); (mem::size_of::<Encoder<'static>>() > mem::size_of::<SosEncoder>()) as usize];
+#[allow(dead_code)]
+const DECODER_FITS: [(); 0] =
+    [(); (mem::size_of::<DecodeIter<'static>>() > mem::size_of::<SosDecoder>()) as usize];
+
+fn status(result: Result<(), SosStatus>) -> SosStatus {
+    match result {
+        Ok(()) => SosStatus::Ok,
+        Err(e) => e,
+    }
+}
+
+unsafe fn encoder<'a>(enc: *mut SosEncoder) -> &'a mut Encoder<'static> {
+    &mut *(enc as *mut Encoder<'static>)
+}
+
+unsafe fn decoder<'a>(dec: *mut SosDecoder) -> &'a mut DecodeIter<'static> {
+    &mut *(dec as *mut DecodeIter<'static>)
+}
+
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(data, len)
+    }
+}
+
+/// The NUL terminated string at `s`.
+unsafe fn c_str<'a>(s: *const c_char) -> Result<&'a str, SosStatus> {
+    let mut len = 0;
+    while *s.offset(len as isize) != 0 {
+        len += 1;
+    }
+    str::from_utf8(bytes(s as *const u8, len)).map_err(|_| SosStatus::InvalidUtf8)
+}
+
+unsafe fn push<'b, V: Into<Value<'b>>>(enc: *mut SosEncoder, value: V) -> SosStatus {
+    status(encoder(enc).push(value).map_err(SosStatus::from))
+}
+
+/// Takes the next value if `f` accepts it, otherwise it is left for the next call.
+unsafe fn take<T, F>(dec: *mut SosDecoder, f: F) -> Result<T, SosStatus>
+where
+    F: FnOnce(Value<'static>) -> Option<T>,
+{
+    let dec = decoder(dec);
+    let mut rest = dec.clone();
+    let value = rest.next().ok_or(SosStatus::End)?;
+    let taken = f(value).ok_or(SosStatus::WrongType)?;
+    *dec = rest;
+    Ok(taken)
+}
+
+/// Strings stay NUL terminated in the buffer, so C can use them in place.
+unsafe fn write_str(s: &str, out: *mut *const c_char, len: *mut usize) {
+    *out = s.as_ptr() as *const c_char;
+    if !len.is_null() {
+        *len = s.len();
+    }
+}
+
+/// Starts encoding into the `len` bytes at `buf`, `flags` is 0 or `SOS_FLAG_ALIGNED`.
+#[no_mangle]
+pub unsafe extern "C" fn sos_encoder_init(
+    enc: *mut SosEncoder,
+    buf: *mut u8,
+    len: usize,
+    flags: u8,
+) -> SosStatus {
+    let layout = match Layout::from_flags(flags) {
+        Some(layout) => layout,
+        None => return SosStatus::Malformed,
+    };
+    let buf = if len == 0 {
+        &mut []
+    } else {
+        slice::from_raw_parts_mut(buf, len)
+    };
+    match Encoder::with_layout(buf, layout) {
+        Ok(e) => {
+            ptr::write(enc as *mut Encoder, e);
+            SosStatus::Ok
+        }
+        Err(e) => e.into(),
+    }
+}
+
+macro_rules! scalar {
+    ($push:ident, $next:ident, $ref:ident, $array:ident, $t:ty, $variant:ident) => {
+        #[no_mangle]
+        pub unsafe extern "C" fn $push(enc: *mut SosEncoder, value: $t) -> SosStatus {
+            push(enc, Value::$variant(value))
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn $next(dec: *mut SosDecoder, out: *mut $t) -> SosStatus {
+            status(
+                take(dec, |v| match v {
+                    Value::$variant(x) => Some(x),
+                    _ => None,
+                })
+                .map(|x| *out = x),
+            )
+        }
+
+        /// Points `out` at the next value where it is in the buffer, without copying it.
+        #[no_mangle]
+        pub unsafe extern "C" fn $ref(dec: *mut SosDecoder, out: *mut *const $t) -> SosStatus {
+            if let Some(x) = decoder(dec).next_ref::<$t>() {
+                *out = x;
+                return SosStatus::Ok;
+            }
+            match decoder(dec).clone().next() {
+                Some(Value::$variant(_)) => SosStatus::Unaligned,
+                Some(_) => SosStatus::WrongType,
+                None => SosStatus::End,
+            }
+        }
+
+        /// Points `out` at the elements of an array where they are in the buffer and stores
+        /// how many there are in `len`.
+        #[no_mangle]
+        pub unsafe extern "C" fn $array(
+            dec: *mut SosDecoder,
+            out: *mut *const $t,
+            len: *mut usize,
+        ) -> SosStatus {
+            let array = match decoder(dec).clone().next() {
+                Some(Value::Array(array)) => array,
+                Some(_) => return SosStatus::WrongType,
+                None => return SosStatus::End,
+            };
+            match array.as_slice::<$t>() {
+                Some(elements) => {
+                    decoder(dec).next();
+                    *out = elements.as_ptr();
+                    *len = elements.len();
+                    SosStatus::Ok
+                }
+                None if array.elem_type() == <$t as ArrayElement>::TYPE => SosStatus::Unaligned,
+                None => SosStatus::WrongType,
+            }
+        }
+    };
+}
+
+scalar!(
+    sos_push_i32,
+    sos_next_i32,
+    sos_ref_i32,
+    sos_next_array_i32,
+    i32,
+    Int32
+);
+scalar!(
+    sos_push_u32,
+    sos_next_u32,
+    sos_ref_u32,
+    sos_next_array_u32,
+    u32,
+    UInt32
+);
+scalar!(
+    sos_push_i64,
+    sos_next_i64,
+    sos_ref_i64,
+    sos_next_array_i64,
+    i64,
+    Int64
+);
+scalar!(
+    sos_push_u64,
+    sos_next_u64,
+    sos_ref_u64,
+    sos_next_array_u64,
+    u64,
+    UInt64
+);
+scalar!(
+    sos_push_f32,
+    sos_next_f32,
+    sos_ref_f32,
+    sos_next_array_f32,
+    f32,
+    Float
+);
+scalar!(
+    sos_push_f64,
+    sos_next_f64,
+    sos_ref_f64,
+    sos_next_array_f64,
+    f64,
+    Double
+);
+scalar!(
+    sos_push_i8,
+    sos_next_i8,
+    sos_ref_i8,
+    sos_next_array_i8,
+    i8,
+    Int8
+);
+scalar!(
+    sos_push_u8,
+    sos_next_u8,
+    sos_ref_u8,
+    sos_next_array_u8,
+    u8,
+    UInt8
+);
+scalar!(
+    sos_push_i16,
+    sos_next_i16,
+    sos_ref_i16,
+    sos_next_array_i16,
+    i16,
+    Int16
+);
+scalar!(
+    sos_push_u16,
+    sos_next_u16,
+    sos_ref_u16,
+    sos_next_array_u16,
+    u16,
+    UInt16
+);
+
+/// Any non zero `value` is true.
+#[no_mangle]
+pub unsafe extern "C" fn sos_push_bool(enc: *mut SosEncoder, value: u8) -> SosStatus {
+    push(enc, value != 0)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sos_push_unit(enc: *mut SosEncoder) -> SosStatus {
+    push(enc, ())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sos_push_string(enc: *mut SosEncoder, s: *const c_char) -> SosStatus {
+    match c_str(s) {
+        Ok(s) => push(enc, s),
+        Err(e) => e,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sos_push_error(enc: *mut SosEncoder, s: *const c_char) -> SosStatus {
+    match c_str(s) {
+        Ok(s) => push(enc, Value::Error(s)),
+        Err(e) => e,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sos_push_opaque(
+    enc: *mut SosEncoder,
+    data: *const u8,
+    len: usize,
+) -> SosStatus {
+    push(enc, Value::Opaque(bytes(data, len)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sos_push_function(
+    enc: *mut SosEncoder,
+    module: *const c_char,
+    name: *const c_char,
+) -> SosStatus {
+    match (c_str(module), c_str(name)) {
+        (Ok(module), Ok(name)) => push(enc, Function { module, name }),
+        (Err(e), _) | (_, Err(e)) => e,
+    }
+}
+
+/// Adds `len` bytes at `data` as a value of the extension type `type_id`.
+#[no_mangle]
+pub unsafe extern "C" fn sos_push_extension(
+    enc: *mut SosEncoder,
+    type_id: u8,
+    data: *const u8,
+    len: usize,
+) -> SosStatus {
+    if type_id > EXTENSION_MAX_ID {
+        return SosStatus::Malformed;
+    }
+    push(enc, Extension::new(type_id, bytes(data, len)))
+}
+
+/// Adds the SOS buffer of `len` bytes at `buf` as one embedded value, it is validated first.
+#[no_mangle]
+pub unsafe extern "C" fn sos_push_embedded(
+    enc: *mut SosEncoder,
+    buf: *const u8,
+    len: usize,
+) -> SosStatus {
+    match validate_sos(bytes(buf, len)) {
+        Ok(values) => status(encoder(enc).push_embedded(&values).map_err(SosStatus::from)),
+        Err(_) => SosStatus::Malformed,
+    }
+}
+
+/// Starts an embedded value, everything pushed until the matching `sos_close` goes inside it.
+#[no_mangle]
+pub unsafe extern "C" fn sos_open(enc: *mut SosEncoder) -> SosStatus {
+    status(encoder(enc).open().map_err(SosStatus::from))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sos_close(enc: *mut SosEncoder) -> SosStatus {
+    status(encoder(enc).close().map_err(SosStatus::from))
+}
+
+/// Completes the buffer and stores its length in `len`. The encoder cannot be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn sos_finish(enc: *mut SosEncoder, len: *mut usize) -> SosStatus {
+    match ptr::read(enc as *mut Encoder).finish() {
+        Ok(n) => {
+            *len = n;
+            SosStatus::Ok
+        }
+        Err(e) => e.into(),
+    }
+}
+
+/// Length of the SOS buffer at `buf` as its header gives it, 0 if `buf` does not start with a
+/// header. For buffers handed over without their length, like the results of a call.
+#[no_mangle]
+pub unsafe extern "C" fn sos_encoded_len(buf: *const u8) -> usize {
+    let header = bytes(buf, SOS_HEADER_SIZE);
+    if header[..2] != SOS_MAGIC || header[2] != SOS_VERSION {
+        return 0;
+    }
+    header[8..12]
+        .iter()
+        .rev()
+        .fold(0, |len, b| len << 8 | *b as usize)
+}
+
+/// Starts decoding the `len` bytes at `buf`, which are validated in full before anything else.
+#[no_mangle]
+pub unsafe extern "C" fn sos_decoder_init(
+    dec: *mut SosDecoder,
+    buf: *const u8,
+    len: usize,
+) -> SosStatus {
+    match validate_sos(bytes(buf, len)) {
+        Ok(values) => {
+            ptr::write(dec as *mut DecodeIter, values.into_inner());
+            SosStatus::Ok
+        }
+        Err(_) => SosStatus::Malformed,
+    }
+}
+
+/// Type of the next value, one of the `SOS_TYPE_` constants or `SOS_TYPE_EXTENSION` plus the
+/// type id of an extension value.
+#[no_mangle]
+pub unsafe extern "C" fn sos_peek_type(dec: *const SosDecoder) -> u8 {
+    let dec = &*(dec as *const DecodeIter);
+    dec.clone().next().map_or(SOS_TYPE_INVALID, |v| v.tag())
+}
+
+/// Skips over the next value, whatever its type.
+#[no_mangle]
+pub unsafe extern "C" fn sos_skip(dec: *mut SosDecoder) -> SosStatus {
+    status(take(dec, |_| Some(())))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sos_next_bool(dec: *mut SosDecoder, out: *mut u8) -> SosStatus {
+    status(
+        take(dec, |v| match v {
+            Value::Bool(x) => Some(x),
+            _ => None,
+        })
+        .map(|x| *out = x as u8),
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sos_next_unit(dec: *mut SosDecoder) -> SosStatus {
+    status(take(dec, |v| match v {
+        Value::Unit => Some(()),
+        _ => None,
+    }))
+}
+
+/// Points `out` at the string in the buffer and stores its length, without the NUL, in `len`
+/// unless it is NULL.
+#[no_mangle]
+pub unsafe extern "C" fn sos_next_string(
+    dec: *mut SosDecoder,
+    out: *mut *const c_char,
+    len: *mut usize,
+) -> SosStatus {
+    status(
+        take(dec, |v| match v {
+            Value::String(s) => Some(s),
+            _ => None,
+        })
+        .map(|s| write_str(s, out, len)),
+    )
+}
+
+/// Like `sos_next_string`, for an error.
+#[no_mangle]
+pub unsafe extern "C" fn sos_next_error(
+    dec: *mut SosDecoder,
+    out: *mut *const c_char,
+    len: *mut usize,
+) -> SosStatus {
+    status(
+        take(dec, |v| match v {
+            Value::Error(s) => Some(s),
+            _ => None,
+        })
+        .map(|s| write_str(s, out, len)),
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sos_next_opaque(
+    dec: *mut SosDecoder,
+    out: *mut *const u8,
+    len: *mut usize,
+) -> SosStatus {
+    status(
+        take(dec, |v| match v {
+            Value::Opaque(data) => Some(data),
+            _ => None,
+        })
+        .map(|data| {
+            *out = data.as_ptr();
+            *len = data.len();
+        }),
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sos_next_extension(
+    dec: *mut SosDecoder,
+    type_id: *mut u8,
+    out: *mut *const u8,
+    len: *mut usize,
+) -> SosStatus {
+    status(
+        take(dec, |v| match v {
+            Value::Extension(e) => Some(e),
+            _ => None,
+        })
+        .map(|e| {
+            *type_id = e.type_id();
+            *out = e.data().as_ptr();
+            *len = e.data().len();
+        }),
+    )
+}
+
+/// Points `module` and `name` at the NUL terminated parts of a function.
+#[no_mangle]
+pub unsafe extern "C" fn sos_next_function(
+    dec: *mut SosDecoder,
+    module: *mut *const c_char,
+    name: *mut *const c_char,
+) -> SosStatus {
+    status(
+        take(dec, |v| match v {
+            Value::Function(f) => Some(f),
+            _ => None,
+        })
+        .map(|f| {
+            write_str(f.module, module, ptr::null_mut());
+            write_str(f.name, name, ptr::null_mut());
+        }),
+    )
+}
+
+/// Points `out` at the little endian elements of an array of any element type, stores the
+/// `SOS_TYPE_` constant of the elements in `elem_type` and the size of the elements in bytes in
+/// `len`. Works on packed buffers too, where the typed `sos_next_array_` functions can't.
+#[no_mangle]
+pub unsafe extern "C" fn sos_next_array(
+    dec: *mut SosDecoder,
+    elem_type: *mut u8,
+    out: *mut *const u8,
+    len: *mut usize,
+) -> SosStatus {
+    status(
+        take(dec, |v| match v {
+            Value::Array(array) => Some(array),
+            _ => None,
+        })
+        .map(|array| {
+            *elem_type = match array.elem_type() {
+                ArrayType::Int8 => SOS_TYPE_INT8,
+                ArrayType::UInt8 => SOS_TYPE_UINT8,
+                ArrayType::Int16 => SOS_TYPE_INT16,
+                ArrayType::UInt16 => SOS_TYPE_UINT16,
+                ArrayType::Int32 => SOS_TYPE_INT32,
+                ArrayType::UInt32 => SOS_TYPE_UINT32,
+                ArrayType::Int64 => SOS_TYPE_INT64,
+                ArrayType::UInt64 => SOS_TYPE_UINT64,
+                ArrayType::Float => SOS_TYPE_FLOAT,
+                ArrayType::Double => SOS_TYPE_DOUBLE,
+            };
+            *out = array.as_bytes().as_ptr();
+            *len = array.as_bytes().len();
+        }),
+    )
+}
+
+/// Starts `inner` on the keys and values of a map, each key followed by its value, and stores
+/// the number of pairs in `len`. `dec` moves on past the map.
+#[no_mangle]
+pub unsafe extern "C" fn sos_next_map(
+    dec: *mut SosDecoder,
+    inner: *mut SosDecoder,
+    len: *mut usize,
+) -> SosStatus {
+    status(
+        take(dec, |v| match v {
+            Value::MapOut(pairs) => Some(pairs),
+            _ => None,
+        })
+        .map(|pairs| {
+            *len = pairs.len();
+            ptr::write(inner as *mut DecodeIter, pairs.into_inner());
+        }),
+    )
+}
+
+/// Stores the code of a fault in `code` and points `message` at its NUL terminated message.
+/// `module` and `name` are pointed at the function that failed, or set to NULL if it is not
+/// known. The cause of the fault is not handed out.
+#[no_mangle]
+pub unsafe extern "C" fn sos_next_fault(
+    dec: *mut SosDecoder,
+    code: *mut u32,
+    message: *mut *const c_char,
+    module: *mut *const c_char,
+    name: *mut *const c_char,
+) -> SosStatus {
+    status(
+        take(dec, |v| match v {
+            Value::Fault(fault) => Some(fault),
+            _ => None,
+        })
+        .map(|fault| {
+            *code = fault.code.0;
+            write_str(fault.message, message, ptr::null_mut());
+            match fault.origin {
+                Some(f) => {
+                    write_str(f.module, module, ptr::null_mut());
+                    write_str(f.name, name, ptr::null_mut());
+                }
+                None => {
+                    *module = ptr::null();
+                    *name = ptr::null();
+                }
+            }
+        }),
+    )
+}
+
+/// Starts `inner` on the values of an embedded value, `dec` moves on past it.
+#[no_mangle]
+pub unsafe extern "C" fn sos_next_embedded(
+    dec: *mut SosDecoder,
+    inner: *mut SosDecoder,
+) -> SosStatus {
+    status(
+        take(dec, |v| match v {
+            Value::EmbeddedOut(iter) => Some(iter),
+            _ => None,
+        })
+        .map(|iter| ptr::write(inner as *mut DecodeIter, iter)),
+    )
+}
+
+#[cfg(feature = "panic-handler")]
+#[lang = "eh_personality"]
+#[no_mangle]
+pub extern "C" fn rust_eh_personality() {}
+
+/// C modules cannot unwind, a broken invariant in the codec stops the module right there.
+#[cfg(feature = "panic-handler")]
+#[panic_implementation]
+#[no_mangle]
+pub extern "C" fn rust_begin_unwind(_info: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
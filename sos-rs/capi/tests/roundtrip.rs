@@ -0,0 +1,421 @@
+#[macro_use]
+extern crate sos;
+extern crate sos_capi;
+use sos::{
+    decode_sos, AlignedValues, Array, ErrorCode, Extension, Fault, Function, Layout,
+    ReferencedValues, Value, SOS,
+};
+use sos_capi::*;
+use std::ffi::{CStr, CString};
+use std::{mem, ptr, slice};
+
+fn encode_vec<S: SOS>(values: &S) -> Vec<u8> {
+    let mut buf = vec![0; values.encoded_len()];
+    values.encode(&mut buf[..]);
+    buf
+}
+
+/// Encodes the same values as `expected` through the C interface.
+unsafe fn encode_c(buf: &mut [u8], flags: u8) -> Result<usize, SosStatus> {
+    let check = |status| match status {
+        SosStatus::Ok => Ok(()),
+        e => Err(e),
+    };
+    let tail = encode_vec(&sos!["hello", -1i32]);
+    let module = CString::new("call").unwrap();
+    let name = CString::new("print").unwrap();
+    let text = CString::new("text").unwrap();
+
+    let mut enc: SosEncoder = mem::zeroed();
+    check(sos_encoder_init(
+        &mut enc,
+        buf.as_mut_ptr(),
+        buf.len(),
+        flags,
+    ))?;
+    check(sos_push_function(&mut enc, module.as_ptr(), name.as_ptr()))?;
+    check(sos_push_u64(&mut enc, 7))?;
+    check(sos_push_f64(&mut enc, 2.5))?;
+    check(sos_open(&mut enc))?;
+    check(sos_push_string(&mut enc, text.as_ptr()))?;
+    check(sos_push_bool(&mut enc, 1))?;
+    check(sos_push_unit(&mut enc))?;
+    check(sos_push_i8(&mut enc, -3))?;
+    check(sos_close(&mut enc))?;
+    check(sos_push_opaque(&mut enc, b"\x00\xff".as_ptr(), 2))?;
+    check(sos_push_error(&mut enc, text.as_ptr()))?;
+    check(sos_push_embedded(&mut enc, tail.as_ptr(), tail.len()))?;
+    let mut len = 0;
+    check(sos_finish(&mut enc, &mut len))?;
+    Ok(len)
+}
+
+fn expected(layout: Layout) -> Vec<u8> {
+    let tail = encode_vec(&sos!["hello", -1i32]);
+    let inner = [
+        Value::String("text"),
+        Value::Bool(true),
+        Value::Unit,
+        Value::Int8(-3),
+    ];
+    let values = [
+        ("call", "print").into(),
+        7u64.into(),
+        2.5f64.into(),
+        Value::EmbeddedIn(ReferencedValues(&inner)),
+        Value::Opaque(b"\x00\xff"),
+        Value::Error("text"),
+        Value::EmbeddedOut(decode_sos(&tail, true).unwrap()),
+    ];
+    match layout {
+        Layout::Packed => encode_vec(&ReferencedValues(&values)),
+        Layout::Aligned => encode_vec(&AlignedValues(&values)),
+    }
+}
+
+#[test]
+fn encode_matches_rust() {
+    let mut buf = vec![0; 4096];
+    let len = unsafe { encode_c(&mut buf, 0) }.unwrap();
+    assert_eq!(&buf[..len], &expected(Layout::Packed)[..]);
+
+    let len = unsafe { encode_c(&mut buf, SOS_FLAG_ALIGNED) }.unwrap();
+    assert_eq!(&buf[..len], &expected(Layout::Aligned)[..]);
+
+    // A buffer one byte short is refused
+    let mut short = vec![0; len - 1];
+    let err = unsafe { encode_c(&mut short, SOS_FLAG_ALIGNED) }.unwrap_err();
+    assert_eq!(err, SosStatus::NoSpace);
+}
+
+#[test]
+fn encoded_len_from_header() {
+    for &layout in &[Layout::Packed, Layout::Aligned] {
+        let mut buf = expected(layout);
+        let len = buf.len();
+        // Whatever follows the values is not counted
+        buf.extend_from_slice(&[0xff; 16]);
+        assert_eq!(unsafe { sos_encoded_len(buf.as_ptr()) }, len);
+    }
+    assert_eq!(unsafe { sos_encoded_len([0u8; 12].as_ptr()) }, 0);
+}
+
+#[test]
+fn decode_rust_values() {
+    let buf = expected(Layout::Packed);
+    unsafe {
+        let mut dec: SosDecoder = mem::zeroed();
+        assert_eq!(
+            sos_decoder_init(&mut dec, buf.as_ptr(), buf.len()),
+            SosStatus::Ok
+        );
+
+        let (mut module, mut name) = (ptr::null(), ptr::null());
+        assert_eq!(sos_peek_type(&dec), SOS_TYPE_FUNCTION);
+        assert_eq!(
+            sos_next_function(&mut dec, &mut module, &mut name),
+            SosStatus::Ok
+        );
+        assert_eq!(CStr::from_ptr(module).to_str(), Ok("call"));
+        assert_eq!(CStr::from_ptr(name).to_str(), Ok("print"));
+
+        // A value of another type is left in place
+        let mut int = 0i32;
+        assert_eq!(sos_next_i32(&mut dec, &mut int), SosStatus::WrongType);
+        let mut long = 0u64;
+        assert_eq!(sos_next_u64(&mut dec, &mut long), SosStatus::Ok);
+        assert_eq!(long, 7);
+        let mut double = 0f64;
+        assert_eq!(sos_next_f64(&mut dec, &mut double), SosStatus::Ok);
+        assert_eq!(double, 2.5);
+
+        let mut inner: SosDecoder = mem::zeroed();
+        assert_eq!(sos_next_embedded(&mut dec, &mut inner), SosStatus::Ok);
+        let (mut text, mut len) = (ptr::null(), 0);
+        assert_eq!(
+            sos_next_string(&mut inner, &mut text, &mut len),
+            SosStatus::Ok
+        );
+        assert_eq!(CStr::from_ptr(text).to_str(), Ok("text"));
+        assert_eq!(len, 4);
+        let mut flag = 0u8;
+        assert_eq!(sos_next_bool(&mut inner, &mut flag), SosStatus::Ok);
+        assert_eq!(flag, 1);
+        assert_eq!(sos_next_unit(&mut inner), SosStatus::Ok);
+        let mut small = 0i8;
+        assert_eq!(sos_next_i8(&mut inner, &mut small), SosStatus::Ok);
+        assert_eq!(small, -3);
+        assert_eq!(sos_peek_type(&inner), SOS_TYPE_INVALID);
+        assert_eq!(sos_skip(&mut inner), SosStatus::End);
+
+        let (mut data, mut len) = (ptr::null(), 0);
+        assert_eq!(
+            sos_next_opaque(&mut dec, &mut data, &mut len),
+            SosStatus::Ok
+        );
+        assert_eq!(slice::from_raw_parts(data, len), b"\x00\xff");
+        assert_eq!(
+            sos_next_error(&mut dec, &mut text, ptr::null_mut()),
+            SosStatus::Ok
+        );
+        assert_eq!(CStr::from_ptr(text).to_str(), Ok("text"));
+        assert_eq!(sos_peek_type(&dec), SOS_TYPE_EMBEDDED);
+        assert_eq!(sos_skip(&mut dec), SosStatus::Ok);
+        assert_eq!(sos_peek_type(&dec), SOS_TYPE_INVALID);
+
+        assert_eq!(
+            sos_decoder_init(&mut dec, buf.as_ptr(), buf.len() - 1),
+            SosStatus::Malformed
+        );
+    }
+}
+
+/// Copies `bytes` into 8 byte aligned memory, `shift` bytes in.
+fn aligned_copy(bytes: &[u8], shift: usize) -> Vec<u64> {
+    let mut words = vec![0u64; (shift + bytes.len() + 7) / 8];
+    unsafe {
+        let start = (words.as_mut_ptr() as *mut u8).offset(shift as isize);
+        ptr::copy_nonoverlapping(bytes.as_ptr(), start, bytes.len());
+    }
+    words
+}
+
+#[test]
+fn aligned_values_in_place() {
+    let ints = [1u32, 2, 3];
+    let values = [
+        Value::UInt8(1),
+        Value::UInt64(7),
+        Value::Double(2.5),
+        Array::new(&ints[..]).into(),
+    ];
+    let bytes = encode_vec(&AlignedValues(&values));
+    let buf = aligned_copy(&bytes, 0);
+    unsafe {
+        let mut dec: SosDecoder = mem::zeroed();
+        assert_eq!(
+            sos_decoder_init(&mut dec, buf.as_ptr() as *const u8, bytes.len()),
+            SosStatus::Ok
+        );
+        let mut long = ptr::null();
+        assert_eq!(sos_ref_u64(&mut dec, &mut long), SosStatus::WrongType);
+        assert_eq!(sos_skip(&mut dec), SosStatus::Ok);
+        assert_eq!(sos_ref_u64(&mut dec, &mut long), SosStatus::Ok);
+        assert_eq!((*long, long as usize % 8), (7, 0));
+        let mut double = ptr::null();
+        assert_eq!(sos_ref_f64(&mut dec, &mut double), SosStatus::Ok);
+        assert_eq!((*double, double as usize % 8), (2.5, 0));
+
+        let (mut signed, mut unsigned, mut len) = (ptr::null(), ptr::null(), 0);
+        assert_eq!(
+            sos_next_array_i32(&mut dec, &mut signed, &mut len),
+            SosStatus::WrongType
+        );
+        assert_eq!(
+            sos_next_array_u32(&mut dec, &mut unsigned, &mut len),
+            SosStatus::Ok
+        );
+        assert_eq!(slice::from_raw_parts(unsigned, len), &ints[..]);
+        assert_eq!(sos_ref_u64(&mut dec, &mut long), SosStatus::End);
+    }
+}
+
+#[test]
+fn unaligned_values_stay() {
+    let ints = [1u32, 2, 3];
+    let values = [Value::UInt64(7), Array::new(&ints[..]).into()];
+    let bytes = encode_vec(&AlignedValues(&values));
+    let buf = aligned_copy(&bytes, 1);
+    unsafe {
+        let mut dec: SosDecoder = mem::zeroed();
+        let start = (buf.as_ptr() as *const u8).offset(1);
+        assert_eq!(
+            sos_decoder_init(&mut dec, start, bytes.len()),
+            SosStatus::Ok
+        );
+        let mut long = ptr::null();
+        assert_eq!(sos_ref_u64(&mut dec, &mut long), SosStatus::Unaligned);
+        let mut copy = 0u64;
+        assert_eq!(sos_next_u64(&mut dec, &mut copy), SosStatus::Ok);
+        assert_eq!(copy, 7);
+
+        let (mut elems, mut len) = (ptr::null(), 0);
+        assert_eq!(
+            sos_next_array_u32(&mut dec, &mut elems, &mut len),
+            SosStatus::Unaligned
+        );
+        let (mut elem_type, mut data) = (0, ptr::null());
+        assert_eq!(
+            sos_next_array(&mut dec, &mut elem_type, &mut data, &mut len),
+            SosStatus::Ok
+        );
+        assert_eq!(elem_type, SOS_TYPE_UINT32);
+        assert_eq!(
+            slice::from_raw_parts(data, len),
+            &[1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0][..]
+        );
+    }
+}
+
+#[test]
+fn map_and_fault_values() {
+    let pairs = [
+        (Value::String("one"), Value::UInt32(1)),
+        (Value::String("two"), Value::UInt32(2)),
+    ];
+    let origin = Function {
+        module: "call",
+        name: "print",
+    };
+    let values = [
+        Value::MapIn(&pairs),
+        Value::Fault(Fault::new(ErrorCode::BAD_ARGUMENTS, "no").with_origin(origin)),
+        Value::Fault(Fault::new(ErrorCode::user(5), "broke")),
+    ];
+    let buf = encode_vec(&ReferencedValues(&values));
+    unsafe {
+        let mut dec: SosDecoder = mem::zeroed();
+        assert_eq!(
+            sos_decoder_init(&mut dec, buf.as_ptr(), buf.len()),
+            SosStatus::Ok
+        );
+        let mut code = 0;
+        let (mut message, mut module, mut name) = (ptr::null(), ptr::null(), ptr::null());
+        assert_eq!(
+            sos_next_fault(&mut dec, &mut code, &mut message, &mut module, &mut name),
+            SosStatus::WrongType
+        );
+
+        let (mut inner, mut len): (SosDecoder, _) = (mem::zeroed(), 0);
+        assert_eq!(sos_next_map(&mut dec, &mut inner, &mut len), SosStatus::Ok);
+        assert_eq!(len, 2);
+        let (mut key, mut value) = (ptr::null(), 0);
+        for &(k, v) in &[("one", 1), ("two", 2)] {
+            assert_eq!(
+                sos_next_string(&mut inner, &mut key, ptr::null_mut()),
+                SosStatus::Ok
+            );
+            assert_eq!(sos_next_u32(&mut inner, &mut value), SosStatus::Ok);
+            assert_eq!((CStr::from_ptr(key).to_str(), value), (Ok(k), v));
+        }
+        assert_eq!(sos_skip(&mut inner), SosStatus::End);
+
+        assert_eq!(
+            sos_next_fault(&mut dec, &mut code, &mut message, &mut module, &mut name),
+            SosStatus::Ok
+        );
+        assert_eq!(code, ErrorCode::BAD_ARGUMENTS.0);
+        assert_eq!(CStr::from_ptr(message).to_str(), Ok("no"));
+        assert_eq!(CStr::from_ptr(module).to_str(), Ok("call"));
+        assert_eq!(CStr::from_ptr(name).to_str(), Ok("print"));
+        assert_eq!(
+            sos_next_fault(&mut dec, &mut code, &mut message, &mut module, &mut name),
+            SosStatus::Ok
+        );
+        assert_eq!(code, ErrorCode::user(5).0);
+        assert_eq!(CStr::from_ptr(message).to_str(), Ok("broke"));
+        assert!(module.is_null() && name.is_null());
+    }
+}
+
+#[test]
+fn encoder_errors() {
+    let mut buf = [0u8; 64];
+    unsafe {
+        let mut enc: SosEncoder = mem::zeroed();
+        assert_eq!(
+            sos_encoder_init(&mut enc, buf.as_mut_ptr(), buf.len(), 0x80),
+            SosStatus::Malformed
+        );
+        assert_eq!(
+            sos_encoder_init(&mut enc, buf.as_mut_ptr(), 4, 0),
+            SosStatus::NoSpace
+        );
+        assert_eq!(
+            sos_encoder_init(&mut enc, buf.as_mut_ptr(), buf.len(), 0),
+            SosStatus::Ok
+        );
+        assert_eq!(sos_close(&mut enc), SosStatus::NotOpen);
+        let bad = [0xffu8, 0];
+        assert_eq!(
+            sos_push_string(&mut enc, bad.as_ptr() as *const _),
+            SosStatus::InvalidUtf8
+        );
+        assert_eq!(
+            sos_push_embedded(&mut enc, bad.as_ptr(), bad.len()),
+            SosStatus::Malformed
+        );
+        assert_eq!(sos_open(&mut enc), SosStatus::Ok);
+        let mut len = 0;
+        assert_eq!(sos_finish(&mut enc, &mut len), SosStatus::Unclosed);
+    }
+}
+
+#[test]
+fn extension_values() {
+    let mut buf = [0u8; 64];
+    unsafe {
+        let mut enc: SosEncoder = mem::zeroed();
+        sos_encoder_init(&mut enc, buf.as_mut_ptr(), buf.len(), 0);
+        assert_eq!(
+            sos_push_extension(&mut enc, 9, b"id".as_ptr(), 2),
+            SosStatus::Ok
+        );
+        assert_eq!(
+            sos_push_extension(&mut enc, SOS_EXTENSION_MAX_ID + 1, ptr::null(), 0),
+            SosStatus::Malformed
+        );
+        let mut len = 0;
+        assert_eq!(sos_finish(&mut enc, &mut len), SosStatus::Ok);
+        assert_eq!(
+            &buf[..len],
+            &encode_vec(&sos![Extension::new(9, b"id")])[..]
+        );
+
+        let mut dec: SosDecoder = mem::zeroed();
+        assert_eq!(sos_decoder_init(&mut dec, buf.as_ptr(), len), SosStatus::Ok);
+        assert_eq!(sos_peek_type(&dec), SOS_TYPE_EXTENSION + 9);
+        let (mut type_id, mut data, mut len) = (0, ptr::null(), 0);
+        assert_eq!(
+            sos_next_extension(&mut dec, &mut type_id, &mut data, &mut len),
+            SosStatus::Ok
+        );
+        assert_eq!((type_id, slice::from_raw_parts(data, len)), (9, &b"id"[..]));
+    }
+}
+
+#[test]
+fn constants_match_codec() {
+    assert_eq!(SOS_FLAG_ALIGNED, sos::SOS_FLAG_ALIGNED);
+    assert_eq!(SOS_TYPE_EXTENSION, sos::SOS_EXTENSION_TAG);
+    assert_eq!(SOS_EXTENSION_MAX_ID, sos::EXTENSION_MAX_ID);
+    let ints = [1u16];
+    let tags = [
+        (SOS_TYPE_INT32, Value::Int32(0)),
+        (SOS_TYPE_UINT32, Value::UInt32(0)),
+        (SOS_TYPE_INT64, Value::Int64(0)),
+        (SOS_TYPE_UINT64, Value::UInt64(0)),
+        (SOS_TYPE_FLOAT, Value::Float(0.0)),
+        (SOS_TYPE_DOUBLE, Value::Double(0.0)),
+        (SOS_TYPE_ERROR, Value::Error("")),
+        (SOS_TYPE_STRING, Value::String("")),
+        (SOS_TYPE_OPAQUE, Value::Opaque(b"")),
+        (SOS_TYPE_FUNCTION, ("", "").into()),
+        (SOS_TYPE_EMBEDDED, Value::EmbeddedIn(ReferencedValues(&[]))),
+        (SOS_TYPE_BOOL, Value::Bool(false)),
+        (SOS_TYPE_UNIT, Value::Unit),
+        (SOS_TYPE_INT8, Value::Int8(0)),
+        (SOS_TYPE_UINT8, Value::UInt8(0)),
+        (SOS_TYPE_INT16, Value::Int16(0)),
+        (SOS_TYPE_UINT16, Value::UInt16(0)),
+        (SOS_TYPE_ARRAY, Array::new(&ints[..]).into()),
+        (SOS_TYPE_MAP, Value::MapIn(&[])),
+        (
+            SOS_TYPE_FAULT,
+            Value::Fault(Fault::new(ErrorCode::UNKNOWN, "")),
+        ),
+    ];
+    for &(tag, ref value) in tags.iter() {
+        assert_eq!(tag, value.tag(), "{:?}", value);
+    }
+}
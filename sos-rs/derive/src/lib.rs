@@ -0,0 +1,94 @@
+//! `#[derive(SOS)]` for structs, implements `sos::SOS` to encode the fields as a list of values,
+//! and `sos::FromValues` to take them back out of a `DecodeIter` in the same order.
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use syn::{Data, DeriveInput, Fields, GenericParam, Index, Lifetime, LifetimeDef};
+
+#[proc_macro_derive(SOS)]
+pub fn derive_sos(input: TokenStream) -> TokenStream {
+    let input: DeriveInput = syn::parse(input).expect("Failed to parse derive input");
+    let name = &input.ident;
+
+    let fields = match input.data {
+        Data::Struct(ref s) => &s.fields,
+        _ => panic!("#[derive(SOS)] is only supported on structs"),
+    };
+
+    let num_fields = fields.iter().count();
+    let accessors: Vec<_> = match fields {
+        Fields::Named(named) => named
+            .named
+            .iter()
+            .map(|f| {
+                let ident = &f.ident;
+                quote!(self.#ident)
+            }).collect(),
+        Fields::Unnamed(unnamed) => (0..unnamed.unnamed.len())
+            .map(|i| {
+                let index = Index::from(i);
+                quote!(self.#index)
+            }).collect(),
+        Fields::Unit => Vec::new(),
+    };
+    let construct = match fields {
+        Fields::Named(named) => {
+            let idents = named.named.iter().map(|f| &f.ident);
+            quote!(#name { #(#idents: iter.next_as()?,)* })
+        }
+        Fields::Unnamed(unnamed) => {
+            let values = unnamed.unnamed.iter().map(|_| quote!(iter.next_as()?));
+            quote!(#name(#(#values,)*))
+        }
+        Fields::Unit => quote!(#name),
+    };
+
+    // Decoding borrows from the buffer for the struct's first lifetime, or a fresh one if it has
+    // none.
+    let mut decode_generics = input.generics.clone();
+    let lifetime = match input.generics.lifetimes().next() {
+        Some(def) => def.lifetime.clone(),
+        None => {
+            let lifetime = Lifetime::new("'sos", Span::call_site());
+            decode_generics
+                .params
+                .insert(0, GenericParam::Lifetime(LifetimeDef::new(lifetime.clone())));
+            lifetime
+        }
+    };
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let (decode_impl_generics, _, _) = decode_generics.split_for_impl();
+    let accessors = &accessors;
+
+    let expanded = quote! {
+        impl #impl_generics ::sos::SOS for #name #ty_generics #where_clause {
+            fn encode(&self, buf: &mut [u8]) -> usize {
+                let values: [::sos::Value; #num_fields] =
+                    [#(::sos::Value::from(#accessors.clone()),)*];
+                ::sos::SOS::encode(&::sos::ReferencedValues(&values[..]), buf)
+            }
+
+            fn encoded_len(&self) -> usize {
+                let values: [::sos::Value; #num_fields] =
+                    [#(::sos::Value::from(#accessors.clone()),)*];
+                ::sos::SOS::encoded_len(&::sos::ReferencedValues(&values[..]))
+            }
+        }
+
+        impl #decode_impl_generics ::sos::FromValues<#lifetime> for #name #ty_generics #where_clause {
+            fn from_values(
+                iter: &mut ::sos::DecodeIter<#lifetime>,
+            ) -> ::sos::ArgResult<Self> {
+                Ok(#construct)
+            }
+        }
+    };
+
+    expanded.into()
+}
@@ -0,0 +1,25 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate sos;
+use sos::{decode_sos_limited, validate_sos_limited, DecodeLimits};
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 3 {
+        return;
+    }
+    // The first bytes pick small limits so that they are actually hit
+    let limits = DecodeLimits {
+        max_depth: data[0] as usize % 8,
+        max_values: data[1] as usize,
+        max_bytes: data[2] as usize * 16,
+    };
+    let data = &data[3..];
+
+    let _ = decode_sos_limited(data, true, limits).map(|i| i.count());
+    let eager = decode_sos_limited(data, false, limits).map(|i| i.count());
+    if let Ok(values) = validate_sos_limited(data, limits) {
+        // Everything that validates within the limits also decodes within them
+        assert_eq!(eager, Some(values.count()));
+    }
+});
@@ -0,0 +1,242 @@
+use super::{
+    Array, DecodeIter, ErrorCode, Extension, ExtensionType, Fault, Function, JustError, MapIter,
+    Value,
+};
+#[cfg(feature = "alloc")]
+use super::{IndexedValues, OwnedFault};
+#[cfg(feature = "alloc")]
+use alloc::string::ToString;
+use core::fmt::{self, Display};
+
+/// Describes why an argument list could not be extracted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArgError {
+    /// Position of the offending value in the argument list.
+    pub index: usize,
+    /// Name of the type that was wanted at `index`.
+    pub expected: &'static str,
+    /// Name of the type of the value that was found, `None` if the arguments ran out.
+    pub found: Option<&'static str>,
+}
+
+impl ArgError {
+    fn new(index: usize, expected: &'static str, found: Option<&Value>) -> Self {
+        ArgError {
+            index,
+            expected,
+            found: found.map(type_name),
+        }
+    }
+}
+
+/// Name of the type of `value`, like the ones in `FromValue::EXPECTED`.
+fn type_name(value: &Value) -> &'static str {
+    match *value {
+        Value::Int32(_) => "i32",
+        Value::UInt32(_) => "u32",
+        Value::Int64(_) => "i64",
+        Value::UInt64(_) => "u64",
+        Value::Float(_) => "f32",
+        Value::Double(_) => "f64",
+        Value::Bool(_) => "bool",
+        Value::Unit => "()",
+        Value::Int8(_) => "i8",
+        Value::UInt8(_) => "u8",
+        Value::Int16(_) => "i16",
+        Value::UInt16(_) => "u16",
+        Value::String(_) | Value::Error(_) => "&str",
+        Value::Opaque(_) => "&[u8]",
+        Value::Function(_) => "Function",
+        Value::EmbeddedOut(_) | Value::EmbeddedIn(_) => "DecodeIter",
+        #[cfg(feature = "alloc")]
+        Value::EmbeddedVec(_) => "DecodeIter",
+        Value::Array(_) => "Array",
+        Value::MapOut(_) | Value::MapIn(_) => "MapIter",
+        #[cfg(feature = "alloc")]
+        Value::MapVec(_) => "MapIter",
+        Value::Fault(_) => "Fault",
+        Value::Extension(_) => "Extension",
+    }
+}
+
+pub type ArgResult<T> = Result<T, ArgError>;
+
+impl Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.found {
+            Some(found) => write!(
+                f,
+                "argument {}: expected {}, found {}",
+                self.index, self.expected, found
+            ),
+            None => write!(
+                f,
+                "argument {}: expected {}, but there are no more arguments",
+                self.index, self.expected
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'a> From<ArgError> for JustError<'a> {
+    fn from(e: ArgError) -> Self {
+        JustError::from(OwnedFault::new(ErrorCode::BAD_ARGUMENTS, &e.to_string()))
+    }
+}
+
+/// Without an allocator the message can only say what went wrong, not where.
+#[cfg(not(feature = "alloc"))]
+impl<'a> From<ArgError> for JustError<'a> {
+    fn from(e: ArgError) -> Self {
+        match e.found {
+            Some(_) => {
+                JustError::with_code(ErrorCode::BAD_ARGUMENTS, "Argument has incorrect type")
+            }
+            None => JustError::with_code(ErrorCode::BAD_ARGUMENTS, "Not enough arguments"),
+        }
+    }
+}
+
+/// A type that can be taken from a single value.
+pub trait FromValue<'a>: Sized {
+    /// Type name reported in `ArgError::expected`.
+    const EXPECTED: &'static str;
+
+    fn from_value(value: Value<'a>) -> Option<Self>;
+}
+
+/// A type that can be taken from a run of values, such as an argument list. Implemented for
+/// tuples of `FromValue` types and by `#[derive(SOS)]`.
+pub trait FromValues<'a>: Sized {
+    /// Takes as many values from `iter` as needed, the rest are left in place.
+    fn from_values(iter: &mut DecodeIter<'a>) -> ArgResult<Self>;
+}
+
+impl<'a> DecodeIter<'a> {
+    /// Takes the next value as `T`.
+    pub fn next_as<T: FromValue<'a>>(&mut self) -> ArgResult<T> {
+        let index = self.index();
+        let value = self.next().ok_or(ArgError::new(index, T::EXPECTED, None))?;
+        let err = ArgError::new(index, T::EXPECTED, Some(&value));
+        T::from_value(value).ok_or(err)
+    }
+
+    /// Takes the next values as `T`, for example `iter.extract::<(Function, &str, u64)>()`.
+    pub fn extract<T: FromValues<'a>>(&mut self) -> ArgResult<T> {
+        T::from_values(self)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'a> IndexedValues<'a> {
+    /// Takes the value at `index` as `T`.
+    pub fn get_as<T: FromValue<'a>>(&self, index: usize) -> ArgResult<T> {
+        let position = self.iter().index() + index;
+        let value = self
+            .get(index)
+            .ok_or(ArgError::new(position, T::EXPECTED, None))?;
+        let err = ArgError::new(position, T::EXPECTED, Some(&value));
+        T::from_value(value).ok_or(err)
+    }
+}
+
+macro_rules! impl_from_value {
+    ($src:path, $dst:ty, $name:expr) => {
+        impl<'a> FromValue<'a> for $dst {
+            const EXPECTED: &'static str = $name;
+
+            fn from_value(value: Value<'a>) -> Option<Self> {
+                match value {
+                    $src(i) => Some(i),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+do_list!(impl_from_value[
+    (Value::Int32, i32, "i32"),
+    (Value::UInt32, u32, "u32"),
+    (Value::Int64, i64, "i64"),
+    (Value::UInt64, u64, "u64"),
+    (Value::Float, f32, "f32"),
+    (Value::Double, f64, "f64"),
+    (Value::Bool, bool, "bool"),
+    (Value::Int8, i8, "i8"),
+    (Value::UInt8, u8, "u8"),
+    (Value::Int16, i16, "i16"),
+    (Value::UInt16, u16, "u16"),
+    (Value::Opaque, &'a [u8], "&[u8]"),
+    (Value::Function, Function<'a>, "Function"),
+    (Value::EmbeddedOut, DecodeIter<'a>, "DecodeIter"),
+    (Value::Array, Array<'a>, "Array"),
+    (Value::MapOut, MapIter<'a>, "MapIter"),
+    (Value::Fault, Fault<'a>, "Fault"),
+    (Value::Extension, Extension<'a>, "Extension")
+]);
+
+impl<'a> FromValue<'a> for () {
+    const EXPECTED: &'static str = "()";
+
+    fn from_value(value: Value<'a>) -> Option<Self> {
+        match value {
+            Value::Unit => Some(()),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> FromValue<'a> for &'a str {
+    const EXPECTED: &'static str = "&str";
+
+    fn from_value(value: Value<'a>) -> Option<Self> {
+        match value {
+            Value::String(i) | Value::Error(i) => Some(i),
+            _ => None,
+        }
+    }
+}
+
+impl<'a, T: ExtensionType<'a>> FromValue<'a> for T {
+    const EXPECTED: &'static str = T::NAME;
+
+    fn from_value(value: Value<'a>) -> Option<Self> {
+        match value {
+            Value::Extension(e) => e.decode(),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> FromValue<'a> for Value<'a> {
+    const EXPECTED: &'static str = "Value";
+
+    fn from_value(value: Value<'a>) -> Option<Self> {
+        Some(value)
+    }
+}
+
+macro_rules! impl_from_values_tuple {
+    ($($name:ident)*) => {
+        impl<'a, $($name: FromValue<'a>),*> FromValues<'a> for ($($name,)*) {
+            #[allow(unused_variables)]
+            fn from_values(iter: &mut DecodeIter<'a>) -> ArgResult<Self> {
+                Ok(($(iter.next_as::<$name>()?,)*))
+            }
+        }
+    };
+}
+
+do_list!(impl_from_values_tuple[
+    (),
+    (A),
+    (A B),
+    (A B C),
+    (A B C D),
+    (A B C D E),
+    (A B C D E F),
+    (A B C D E F G),
+    (A B C D E F G H)
+]);
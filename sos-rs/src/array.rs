@@ -0,0 +1,322 @@
+use super::byteorder::{ByteOrder, LittleEndian};
+use super::{CType, Value};
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem;
+use core::slice;
+
+/// Element type of a packed array.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ArrayType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Int64,
+    UInt64,
+    Float,
+    Double,
+}
+
+pub const ARRAY_TYPES: [ArrayType; 10] = [
+    ArrayType::Int8,
+    ArrayType::UInt8,
+    ArrayType::Int16,
+    ArrayType::UInt16,
+    ArrayType::Int32,
+    ArrayType::UInt32,
+    ArrayType::Int64,
+    ArrayType::UInt64,
+    ArrayType::Float,
+    ArrayType::Double,
+];
+
+impl ArrayType {
+    /// Size of one element in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            ArrayType::Int8 | ArrayType::UInt8 => 1,
+            ArrayType::Int16 | ArrayType::UInt16 => 2,
+            ArrayType::Int32 | ArrayType::UInt32 | ArrayType::Float => 4,
+            ArrayType::Int64 | ArrayType::UInt64 | ArrayType::Double => 8,
+        }
+    }
+
+    pub(crate) fn ctype(&self) -> CType {
+        match self {
+            ArrayType::Int8 => CType::Int8,
+            ArrayType::UInt8 => CType::UInt8,
+            ArrayType::Int16 => CType::Int16,
+            ArrayType::UInt16 => CType::UInt16,
+            ArrayType::Int32 => CType::Int32,
+            ArrayType::UInt32 => CType::UInt32,
+            ArrayType::Int64 => CType::Int64,
+            ArrayType::UInt64 => CType::UInt64,
+            ArrayType::Float => CType::Float,
+            ArrayType::Double => CType::Double,
+        }
+    }
+
+    pub(crate) fn from_u8(i: u8) -> Option<Self> {
+        match CType::from_u8(i)? {
+            CType::Int8 => Some(ArrayType::Int8),
+            CType::UInt8 => Some(ArrayType::UInt8),
+            CType::Int16 => Some(ArrayType::Int16),
+            CType::UInt16 => Some(ArrayType::UInt16),
+            CType::Int32 => Some(ArrayType::Int32),
+            CType::UInt32 => Some(ArrayType::UInt32),
+            CType::Int64 => Some(ArrayType::Int64),
+            CType::UInt64 => Some(ArrayType::UInt64),
+            CType::Float => Some(ArrayType::Float),
+            CType::Double => Some(ArrayType::Double),
+            _ => None,
+        }
+    }
+}
+
+/// A primitive that can be packed into an `Array`. Unsafe because arrays of it are reinterpreted
+/// from raw bytes, so every bit pattern must be a valid `Self`.
+pub unsafe trait ArrayElement: Copy {
+    const TYPE: ArrayType;
+
+    /// Reads one little endian element from the start of `buf`.
+    fn read(buf: &[u8]) -> Self;
+    /// Writes one little endian element to the start of `buf`.
+    fn write(self, buf: &mut [u8]);
+}
+
+unsafe impl ArrayElement for i8 {
+    const TYPE: ArrayType = ArrayType::Int8;
+
+    fn read(buf: &[u8]) -> Self {
+        buf[0] as i8
+    }
+
+    fn write(self, buf: &mut [u8]) {
+        buf[0] = self as u8;
+    }
+}
+
+unsafe impl ArrayElement for u8 {
+    const TYPE: ArrayType = ArrayType::UInt8;
+
+    fn read(buf: &[u8]) -> Self {
+        buf[0]
+    }
+
+    fn write(self, buf: &mut [u8]) {
+        buf[0] = self;
+    }
+}
+
+macro_rules! impl_array_element {
+    ($ty:ty, $array_type:path, $read:ident, $write:ident) => {
+        unsafe impl ArrayElement for $ty {
+            const TYPE: ArrayType = $array_type;
+
+            fn read(buf: &[u8]) -> Self {
+                LittleEndian::$read(buf)
+            }
+
+            fn write(self, buf: &mut [u8]) {
+                LittleEndian::$write(buf, self)
+            }
+        }
+    };
+}
+
+do_list!(impl_array_element[
+    (i16, ArrayType::Int16, read_i16, write_i16),
+    (u16, ArrayType::UInt16, read_u16, write_u16),
+    (i32, ArrayType::Int32, read_i32, write_i32),
+    (u32, ArrayType::UInt32, read_u32, write_u32),
+    (i64, ArrayType::Int64, read_i64, write_i64),
+    (u64, ArrayType::UInt64, read_u64, write_u64),
+    (f32, ArrayType::Float, read_f32, write_f32),
+    (f64, ArrayType::Double, read_f64, write_f64)
+]);
+
+/// A packed array of one primitive type, held as little endian bytes. Encoded as the element
+/// type followed by the elements.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Array<'a> {
+    elem: ArrayType,
+    data: &'a [u8],
+}
+
+impl<'a> Array<'a> {
+    /// Borrows `values` without copying, only possible where the in memory layout is the wire
+    /// layout. Use `OwnedArray::from_slice` elsewhere.
+    #[cfg(target_endian = "little")]
+    pub fn new<T: ArrayElement>(values: &'a [T]) -> Self {
+        let data = unsafe {
+            slice::from_raw_parts(values.as_ptr() as *const u8, values.len() * T::TYPE.size())
+        };
+        Array {
+            elem: T::TYPE,
+            data,
+        }
+    }
+
+    /// Wraps little endian elements, fails if `data` is not a whole number of elements.
+    pub fn from_bytes(elem: ArrayType, data: &'a [u8]) -> Option<Self> {
+        if data.len() % elem.size() != 0 {
+            return None;
+        }
+        Some(Array { elem, data })
+    }
+
+    pub fn elem_type(&self) -> ArrayType {
+        self.elem
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len() / self.elem.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// The little endian elements.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn get<T: ArrayElement>(&self, index: usize) -> Option<T> {
+        if T::TYPE != self.elem || index >= self.len() {
+            return None;
+        }
+        Some(T::read(&self.data[index * self.elem.size()..]))
+    }
+
+    /// Iterates over the elements as `T`, `None` if they are not `T`s.
+    pub fn iter<T: ArrayElement>(&self) -> Option<ArrayIter<'a, T>> {
+        if T::TYPE != self.elem {
+            return None;
+        }
+        Some(ArrayIter {
+            data: self.data,
+            _elem: PhantomData,
+        })
+    }
+
+    /// Iterates over the elements as `Value`s of the element type.
+    pub fn values(&self) -> ArrayValues<'a> {
+        ArrayValues {
+            elem: self.elem,
+            data: self.data,
+        }
+    }
+
+    /// Borrows the elements straight from the buffer. `None` if they are not `T`s, or the
+    /// buffer is not suitably aligned for `T` or not in the native byte order.
+    pub fn as_slice<T: ArrayElement>(&self) -> Option<&'a [T]> {
+        if T::TYPE != self.elem
+            || cfg!(target_endian = "big")
+            || self.data.as_ptr() as usize % mem::align_of::<T>() != 0
+        {
+            return None;
+        }
+        Some(unsafe { slice::from_raw_parts(self.data.as_ptr() as *const T, self.len()) })
+    }
+}
+
+pub struct ArrayIter<'a, T> {
+    data: &'a [u8],
+    _elem: PhantomData<T>,
+}
+
+impl<'a, T: ArrayElement> Iterator for ArrayIter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let size = T::TYPE.size();
+        if self.data.len() < size {
+            return None;
+        }
+        let value = T::read(self.data);
+        self.data = &self.data[size..];
+        Some(value)
+    }
+}
+
+pub struct ArrayValues<'a> {
+    elem: ArrayType,
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for ArrayValues<'a> {
+    type Item = Value<'a>;
+
+    fn next(&mut self) -> Option<Value<'a>> {
+        let size = self.elem.size();
+        if self.data.len() < size {
+            return None;
+        }
+        let buf = self.data;
+        self.data = &self.data[size..];
+        Some(match self.elem {
+            ArrayType::Int8 => Value::Int8(i8::read(buf)),
+            ArrayType::UInt8 => Value::UInt8(u8::read(buf)),
+            ArrayType::Int16 => Value::Int16(i16::read(buf)),
+            ArrayType::UInt16 => Value::UInt16(u16::read(buf)),
+            ArrayType::Int32 => Value::Int32(i32::read(buf)),
+            ArrayType::UInt32 => Value::UInt32(u32::read(buf)),
+            ArrayType::Int64 => Value::Int64(i64::read(buf)),
+            ArrayType::UInt64 => Value::UInt64(u64::read(buf)),
+            ArrayType::Float => Value::Float(f32::read(buf)),
+            ArrayType::Double => Value::Double(f64::read(buf)),
+        })
+    }
+}
+
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct OwnedArray {
+    elem: ArrayType,
+    data: Vec<u8>,
+}
+
+#[cfg(feature = "alloc")]
+impl OwnedArray {
+    pub fn from_slice<T: ArrayElement>(values: &[T]) -> Self {
+        let size = T::TYPE.size();
+        let mut data = vec![0; values.len() * size];
+        for (value, buf) in values.iter().zip(data.chunks_mut(size)) {
+            value.write(buf);
+        }
+        OwnedArray {
+            elem: T::TYPE,
+            data,
+        }
+    }
+
+    /// Takes little endian elements, fails if `data` is not a whole number of elements.
+    pub fn from_bytes(elem: ArrayType, data: Vec<u8>) -> Option<Self> {
+        if data.len() % elem.size() != 0 {
+            return None;
+        }
+        Some(OwnedArray { elem, data })
+    }
+
+    pub fn borrow(&self) -> Array {
+        Array {
+            elem: self.elem,
+            data: &self.data,
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'a> From<Array<'a>> for OwnedArray {
+    fn from(array: Array<'a>) -> Self {
+        OwnedArray {
+            elem: array.elem,
+            data: array.data.to_vec(),
+        }
+    }
+}
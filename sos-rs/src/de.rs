@@ -0,0 +1,418 @@
+use super::ser::{Error, FUNCTION_TOKEN};
+use super::{decode_sos, DecodeIter, Function, Value};
+use alloc::string::ToString;
+use core::fmt::{self, Display};
+use core::iter;
+use serde::de::{
+    self, Deserialize, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    Unexpected, VariantAccess, Visitor,
+};
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
+
+/// Deserializes a type from the values of `iter`, borrowing strings and opaque data from the
+/// underlying buffer. This is the inverse of `to_vec`.
+pub fn from_iter<'de, T: Deserialize<'de>>(iter: DecodeIter<'de>) -> Result<T, Error> {
+    let mut deserializer = Deserializer(iter);
+    let value = T::deserialize(&mut deserializer)?;
+    if deserializer.0.next().is_some() {
+        return Err(Error::TrailingValues);
+    }
+    Ok(value)
+}
+
+pub fn from_slice<'de, T: Deserialize<'de>>(buf: &'de [u8]) -> Result<T, Error> {
+    from_iter(decode_sos(buf, true).ok_or(Error::Decode)?)
+}
+
+/// Top level deserializer, structs, tuples and sequences consume all of the values, anything else
+/// consumes just one. No values at all is read as `()` or `None`, any value at all is `Some`, so
+/// `Some(())` and `Some(None)` are not mistaken for `None`.
+pub struct Deserializer<'de>(DecodeIter<'de>);
+
+impl<'de> Deserializer<'de> {
+    pub fn new(iter: DecodeIter<'de>) -> Self {
+        Deserializer(iter)
+    }
+
+    fn next_value(&mut self) -> Result<ValueDeserializer<'de>, Error> {
+        self.0.next().map(ValueDeserializer).ok_or(Error::Eof)
+    }
+}
+
+macro_rules! forward_to_next_value {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                self.next_value()?.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
+    type Error = Error;
+
+    forward_to_next_value! {
+        deserialize_any deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32
+        deserialize_i64 deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_f32 deserialize_f64 deserialize_char deserialize_str deserialize_string
+        deserialize_bytes deserialize_byte_buf deserialize_map deserialize_identifier
+        deserialize_ignored_any
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0.next() {
+            None => visitor.visit_none(),
+            Some(value) => visitor.visit_some(ValueDeserializer(value)),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        if name == FUNCTION_TOKEN {
+            self.next_value()?.deserialize_newtype_struct(name, visitor)
+        } else {
+            visitor.visit_newtype_struct(self)
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(ValueSeq(&mut self.0))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.next_value()?.deserialize_enum(name, variants, visitor)
+    }
+}
+
+/// Deserializer for a single value.
+pub struct ValueDeserializer<'de>(pub Value<'de>);
+
+impl<'de> ValueDeserializer<'de> {
+    fn unexpected(&self) -> Unexpected {
+        match self.0 {
+            Value::Int32(i) => Unexpected::Signed(i as i64),
+            Value::UInt32(i) => Unexpected::Unsigned(i as u64),
+            Value::Int64(i) => Unexpected::Signed(i),
+            Value::UInt64(i) => Unexpected::Unsigned(i),
+            Value::Float(i) => Unexpected::Float(i as f64),
+            Value::Double(i) => Unexpected::Float(i),
+            Value::Bool(i) => Unexpected::Bool(i),
+            Value::Unit => Unexpected::Unit,
+            Value::Int8(i) => Unexpected::Signed(i as i64),
+            Value::UInt8(i) => Unexpected::Unsigned(i as u64),
+            Value::Int16(i) => Unexpected::Signed(i as i64),
+            Value::UInt16(i) => Unexpected::Unsigned(i as u64),
+            Value::String(i) | Value::Error(i) => Unexpected::Str(i),
+            Value::Opaque(i) => Unexpected::Bytes(i),
+            Value::Function(_) => Unexpected::Other("function"),
+            Value::Fault(_) => Unexpected::Other("fault"),
+            Value::Extension(_) => Unexpected::Other("extension value"),
+            Value::MapOut(_) | Value::MapIn(_) | Value::MapVec(_) => Unexpected::Map,
+            _ => Unexpected::Seq,
+        }
+    }
+
+    /// Applies `f` to an iterator over the values of an embedded value or array.
+    fn embedded<T, F>(self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Iterator<Item = Value<'de>>) -> Result<T, Error>,
+    {
+        match self.0 {
+            Value::EmbeddedOut(mut iter) => f(&mut iter),
+            Value::EmbeddedVec(vec) => f(&mut vec.into_iter()),
+            Value::EmbeddedIn(values) => f(&mut values.0.iter().cloned()),
+            Value::Array(array) => f(&mut array.values()),
+            Value::Fault(fault) => f(&mut fault.values().iter().cloned()),
+            _ => Err(de::Error::invalid_type(
+                self.unexpected(),
+                &"embedded values",
+            )),
+        }
+    }
+
+    /// Applies `f` to an iterator over the keys and values of a map, in turn.
+    fn map<T, F>(self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Iterator<Item = Value<'de>>) -> Result<T, Error>,
+    {
+        match self.0 {
+            Value::MapOut(iter) => {
+                f(&mut iter.flat_map(|(k, v)| iter::once(k).chain(iter::once(v))))
+            }
+            Value::MapVec(vec) => f(&mut vec
+                .into_iter()
+                .flat_map(|(k, v)| iter::once(k).chain(iter::once(v)))),
+            Value::MapIn(pairs) => f(&mut pairs
+                .iter()
+                .cloned()
+                .flat_map(|(k, v)| iter::once(k).chain(iter::once(v)))),
+            _ => Err(de::Error::invalid_type(self.unexpected(), &"a map")),
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::Int32(i) => visitor.visit_i32(i),
+            Value::UInt32(i) => visitor.visit_u32(i),
+            Value::Int64(i) => visitor.visit_i64(i),
+            Value::UInt64(i) => visitor.visit_u64(i),
+            Value::Float(i) => visitor.visit_f32(i),
+            Value::Double(i) => visitor.visit_f64(i),
+            Value::Bool(i) => visitor.visit_bool(i),
+            Value::Unit => visitor.visit_unit(),
+            Value::Int8(i) => visitor.visit_i8(i),
+            Value::UInt8(i) => visitor.visit_u8(i),
+            Value::Int16(i) => visitor.visit_i16(i),
+            Value::UInt16(i) => visitor.visit_u16(i),
+            Value::String(i) | Value::Error(i) => visitor.visit_borrowed_str(i),
+            Value::Opaque(i) => visitor.visit_borrowed_bytes(i),
+            Value::Function(f) => visitor.visit_seq(ValueSeq(
+                &mut iter::once(Value::String(f.module)).chain(iter::once(Value::String(f.name))),
+            )),
+            Value::MapOut(_) | Value::MapIn(_) | Value::MapVec(_) => {
+                self.map(|iter| visitor.visit_map(ValueSeq(iter)))
+            }
+            _ => self.embedded(|iter| visitor.visit_seq(ValueSeq(iter))),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        if let Value::Unit = self.0 {
+            return visitor.visit_none();
+        }
+        self.embedded(|iter| match iter.next() {
+            None => visitor.visit_none(),
+            Some(value) => {
+                let res = visitor.visit_some(ValueDeserializer(value))?;
+                match iter.next() {
+                    None => Ok(res),
+                    Some(_) => Err(Error::TrailingValues),
+                }
+            }
+        })
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::Unit => visitor.visit_unit(),
+            _ => Err(de::Error::invalid_type(self.unexpected(), &visitor)),
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::Function(_) if name == FUNCTION_TOKEN => self.deserialize_any(visitor),
+            _ if name == FUNCTION_TOKEN => {
+                Err(de::Error::invalid_type(self.unexpected(), &"function"))
+            }
+            _ => visitor.visit_newtype_struct(self),
+        }
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.map(|iter| visitor.visit_map(ValueSeq(iter)))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::String(variant) => visitor.visit_enum(variant.into_deserializer()),
+            _ => self.embedded(|iter| {
+                let variant = match iter.next() {
+                    Some(Value::String(variant)) => variant,
+                    Some(other) => {
+                        return Err(de::Error::invalid_type(
+                            ValueDeserializer(other).unexpected(),
+                            &"variant name",
+                        ))
+                    }
+                    None => return Err(Error::Eof),
+                };
+                let value = iter.next().ok_or(Error::Eof)?;
+                if iter.next().is_some() {
+                    return Err(Error::TrailingValues);
+                }
+                visitor.visit_enum(Variant(variant, value))
+            }),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf seq tuple
+        tuple_struct struct identifier ignored_any
+    }
+}
+
+/// Walks values of an embedded value, maps are walked as key, value, key, value...
+struct ValueSeq<'a, 'de: 'a>(&'a mut Iterator<Item = Value<'de>>);
+
+impl<'a, 'de> SeqAccess<'de> for ValueSeq<'a, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        match self.0.next() {
+            Some(value) => seed.deserialize(ValueDeserializer(value)).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<'a, 'de> MapAccess<'de> for ValueSeq<'a, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        self.next_element_seed(seed)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        self.next_element_seed(seed)?.ok_or(Error::Eof)
+    }
+}
+
+struct Variant<'de>(&'de str, Value<'de>);
+
+impl<'de> EnumAccess<'de> for Variant<'de> {
+    type Error = Error;
+    type Variant = ValueDeserializer<'de>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, ValueDeserializer<'de>), Error> {
+        let variant = seed.deserialize(self.0.into_deserializer())?;
+        Ok((variant, ValueDeserializer(self.1)))
+    }
+}
+
+impl<'de> VariantAccess<'de> for ValueDeserializer<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Err(de::Error::invalid_type(self.unexpected(), &"unit variant"))
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.embedded(|iter| visitor.visit_seq(ValueSeq(iter)))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.embedded(|iter| visitor.visit_seq(ValueSeq(iter)))
+    }
+}
+
+struct FunctionVisitor;
+
+impl<'de> Visitor<'de> for FunctionVisitor {
+    type Value = Function<'de>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a function")
+    }
+
+    fn visit_newtype_struct<D: de::Deserializer<'de>>(self, d: D) -> Result<Self::Value, D::Error> {
+        let (module, name) = <(&'de str, &'de str)>::deserialize(d)?;
+        Ok(Function { module, name })
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let module = seq
+            .next_element()?
+            .ok_or(de::Error::invalid_length(0, &self))?;
+        let name = seq
+            .next_element()?
+            .ok_or(de::Error::invalid_length(1, &self))?;
+        Ok(Function { module, name })
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for Function<'a> {
+    fn deserialize<D: de::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        d.deserialize_newtype_struct(FUNCTION_TOKEN, FunctionVisitor)
+    }
+}
@@ -0,0 +1,208 @@
+use super::byteorder::{ByteOrder, LittleEndian};
+use super::{
+    encode_value, write_header, CType, ErrorCode, ExtensionType, JustError, Layout, Value,
+    EXTENSION_MAX_ID, SOS, SOS_HEADER_SIZE,
+};
+use core::fmt::{self, Display};
+
+/// How many embedded sections an `Encoder` can have open at once.
+pub const ENCODER_MAX_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeError {
+    /// The buffer is too small for the value, nothing was written.
+    NoSpace,
+    /// More than `ENCODER_MAX_DEPTH` embedded sections are open.
+    TooDeep,
+    /// `close` was called with no embedded section open.
+    NotOpen,
+    /// `finish` was called with embedded sections still open.
+    Unclosed,
+}
+
+impl Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            EncodeError::NoSpace => "not enough space to encode",
+            EncodeError::TooDeep => "too many nested embedded values",
+            EncodeError::NotOpen => "no embedded value to close",
+            EncodeError::Unclosed => "embedded value left open",
+        })
+    }
+}
+
+impl<'a> From<EncodeError> for JustError<'a> {
+    fn from(e: EncodeError) -> Self {
+        match e {
+            EncodeError::NoSpace => {
+                JustError::with_code(ErrorCode::TOO_LARGE, "Values do not fit in the buffer")
+            }
+            // The encoder was used wrong, the values themselves are fine
+            EncodeError::TooDeep => {
+                JustError::with_code(ErrorCode::MALFORMED, "Too many nested embedded values")
+            }
+            EncodeError::NotOpen => {
+                JustError::with_code(ErrorCode::MALFORMED, "No embedded value to close")
+            }
+            EncodeError::Unclosed => {
+                JustError::with_code(ErrorCode::MALFORMED, "Embedded value left open")
+            }
+        }
+    }
+}
+
+/// Start of a header that is patched once its values are known.
+#[derive(Debug, Clone, Copy, Default)]
+struct Section {
+    start: usize,
+    count: usize,
+    /// Where the length of the embedded value goes.
+    length: usize,
+}
+
+/// Encodes values one at a time straight into a buffer, without knowing the total length up
+/// front. Embedded values can be built in place with `open` and `close`.
+pub struct Encoder<'a> {
+    buf: &'a mut [u8],
+    offset: usize,
+    /// The top level is `sections[0]`, open embedded values follow it.
+    sections: [Section; ENCODER_MAX_DEPTH + 1],
+    depth: usize,
+    layout: Layout,
+}
+
+impl<'a> Encoder<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Result<Self, EncodeError> {
+        Encoder::with_layout(buf, Layout::Packed)
+    }
+
+    /// Embedded values opened with `open` share the layout, ones added with `push` or
+    /// `push_embedded` keep their own.
+    pub fn with_layout(buf: &'a mut [u8], layout: Layout) -> Result<Self, EncodeError> {
+        if buf.len() < SOS_HEADER_SIZE {
+            return Err(EncodeError::NoSpace);
+        }
+        Ok(Encoder {
+            buf,
+            offset: SOS_HEADER_SIZE,
+            sections: [Section::default(); ENCODER_MAX_DEPTH + 1],
+            depth: 0,
+            layout,
+        })
+    }
+
+    /// Bytes written so far, including headers that have not been patched yet.
+    pub fn len(&self) -> usize {
+        self.offset
+    }
+
+    /// Bytes left in the buffer.
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.offset
+    }
+
+    /// How many embedded values are open.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Appends a value to the innermost open section.
+    pub fn push<'b, V: Into<Value<'b>>>(&mut self, value: V) -> Result<(), EncodeError> {
+        let value = value.into();
+        if self.remaining() < value.encoded_size(self.offset, self.layout) {
+            return Err(EncodeError::NoSpace);
+        }
+        // Every section starts aligned, so offsets into the whole buffer align the same way
+        self.offset = encode_value(self.buf, self.offset, &value, self.layout);
+        self.sections[self.depth].count += 1;
+        Ok(())
+    }
+
+    /// Starts a value with a length at the current offset, returning where its length and data
+    /// go.
+    fn start(&mut self, val_type: CType, len: usize) -> Result<(usize, usize), EncodeError> {
+        let (length, data) = self.layout.place(val_type, self.offset);
+        let length = length.expect("Value has no length");
+        if self.buf.len() < data || self.buf.len() - data < len {
+            return Err(EncodeError::NoSpace);
+        }
+        self.buf[self.offset] = val_type.tag();
+        for b in &mut self.buf[self.offset + 1..data] {
+            *b = 0;
+        }
+        Ok((length, data))
+    }
+
+    /// Appends already encoded values as a single embedded value.
+    pub fn push_embedded<S: SOS>(&mut self, values: &S) -> Result<(), EncodeError> {
+        let len = values.encoded_len();
+        let (length, data) = self.start(CType::Embedded, len)?;
+        LittleEndian::write_u32(&mut self.buf[length..length + 4], len as u32);
+        values.encode(&mut self.buf[data..data + len]);
+        self.offset = data + len;
+        self.sections[self.depth].count += 1;
+        Ok(())
+    }
+
+    /// Appends a value of an extension type, encoding it straight into the buffer.
+    pub fn push_extension<'b, T: ExtensionType<'b>>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), EncodeError> {
+        assert!(
+            T::TYPE_ID <= EXTENSION_MAX_ID,
+            "Extension type id out of range"
+        );
+        let len = value.encoded_len();
+        let (length, data) = self.start(CType::Extension(T::TYPE_ID), len)?;
+        LittleEndian::write_u32(&mut self.buf[length..length + 4], len as u32);
+        value.encode(&mut self.buf[data..data + len]);
+        self.offset = data + len;
+        self.sections[self.depth].count += 1;
+        Ok(())
+    }
+
+    /// Starts an embedded value, everything pushed until the matching `close` goes inside it.
+    pub fn open(&mut self) -> Result<(), EncodeError> {
+        if self.depth == ENCODER_MAX_DEPTH {
+            return Err(EncodeError::TooDeep);
+        }
+        // The length and header are filled in by close
+        let (length, data) = self.start(CType::Embedded, SOS_HEADER_SIZE)?;
+        self.depth += 1;
+        self.sections[self.depth] = Section {
+            start: data,
+            count: 0,
+            length,
+        };
+        self.offset = data + SOS_HEADER_SIZE;
+        Ok(())
+    }
+
+    /// Ends the innermost embedded value.
+    pub fn close(&mut self) -> Result<(), EncodeError> {
+        if self.depth == 0 {
+            return Err(EncodeError::NotOpen);
+        }
+        let Section {
+            start,
+            count,
+            length,
+        } = self.sections[self.depth];
+        let len = self.offset - start;
+        LittleEndian::write_u32(&mut self.buf[length..length + 4], len as u32);
+        write_header(&mut self.buf[start..], count, len, self.layout);
+        self.depth -= 1;
+        self.sections[self.depth].count += 1;
+        Ok(())
+    }
+
+    /// Writes the top level header and returns the encoded length.
+    pub fn finish(self) -> Result<usize, EncodeError> {
+        if self.depth != 0 {
+            return Err(EncodeError::Unclosed);
+        }
+        write_header(self.buf, self.sections[0].count, self.offset, self.layout);
+        Ok(self.offset)
+    }
+}
@@ -0,0 +1,115 @@
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+/// Type bytes from this one up hold extension values, the low 7 bits are the type id.
+pub const SOS_EXTENSION_TAG: u8 = 0x80;
+/// Highest type id an extension type can have.
+pub const EXTENSION_MAX_ID: u8 = 0x7f;
+
+/// A type that travels as an extension value, the bytes are its own to lay out. Implementing
+/// this registers the type under `TYPE_ID`, which is shared by everything a program talks to,
+/// so two types must never claim the same id.
+pub trait ExtensionType<'a>: Sized {
+    /// At most `EXTENSION_MAX_ID`.
+    const TYPE_ID: u8;
+    /// Type name reported in `ArgError::expected`.
+    const NAME: &'static str;
+
+    fn encoded_len(&self) -> usize;
+    /// Writes the value to `buf`, which is exactly `encoded_len` bytes long.
+    fn encode(&self, buf: &mut [u8]);
+    /// `None` if `data` is not a valid encoding of the type.
+    fn decode(data: &'a [u8]) -> Option<Self>;
+}
+
+/// An encoded extension value. Extension types that a program has no `ExtensionType` for decode
+/// to this as well, keeping their type id, so they can be passed on untouched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Extension<'a> {
+    type_id: u8,
+    data: &'a [u8],
+}
+
+impl<'a> Extension<'a> {
+    /// Panics if `type_id` is above `EXTENSION_MAX_ID`.
+    pub fn new(type_id: u8, data: &'a [u8]) -> Self {
+        assert!(
+            type_id <= EXTENSION_MAX_ID,
+            "Extension type id out of range"
+        );
+        Extension { type_id, data }
+    }
+
+    /// Encodes `value` to the start of `buf`, which needs `value.encoded_len()` bytes.
+    pub fn encode<'b, T: ExtensionType<'b>>(value: &T, buf: &'a mut [u8]) -> Self {
+        let buf = &mut buf[..value.encoded_len()];
+        value.encode(buf);
+        Extension::new(T::TYPE_ID, buf)
+    }
+
+    pub fn type_id(&self) -> u8 {
+        self.type_id
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn is<T: ExtensionType<'a>>(&self) -> bool {
+        self.type_id == T::TYPE_ID
+    }
+
+    /// The value as a `T`, `None` if it has another type id or fails to decode.
+    pub fn decode<T: ExtensionType<'a>>(&self) -> Option<T> {
+        if !self.is::<T>() {
+            return None;
+        }
+        T::decode(self.data)
+    }
+}
+
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OwnedExtension {
+    type_id: u8,
+    data: Vec<u8>,
+}
+
+#[cfg(feature = "alloc")]
+impl OwnedExtension {
+    /// Panics if `type_id` is above `EXTENSION_MAX_ID`.
+    pub fn new(type_id: u8, data: Vec<u8>) -> Self {
+        assert!(
+            type_id <= EXTENSION_MAX_ID,
+            "Extension type id out of range"
+        );
+        OwnedExtension { type_id, data }
+    }
+
+    pub fn encode<'b, T: ExtensionType<'b>>(value: &T) -> Self {
+        let mut data = vec![0; value.encoded_len()];
+        value.encode(&mut data);
+        OwnedExtension::new(T::TYPE_ID, data)
+    }
+
+    pub fn type_id(&self) -> u8 {
+        self.type_id
+    }
+
+    pub fn borrow(&self) -> Extension {
+        Extension {
+            type_id: self.type_id,
+            data: &self.data,
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'a> From<Extension<'a>> for OwnedExtension {
+    fn from(ext: Extension<'a>) -> Self {
+        OwnedExtension {
+            type_id: ext.type_id,
+            data: ext.data.to_vec(),
+        }
+    }
+}
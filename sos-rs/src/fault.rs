@@ -0,0 +1,274 @@
+#[cfg(feature = "alloc")]
+use super::OwnedFunction;
+use super::{CType, DecodeIter, Function, ReferencedValues, Value, SOS};
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+use core::fmt::{self, Debug, Display};
+
+/// Numeric error code. The space is split by where a call failed, so callers can tell a bad
+/// request from a missing callee, a transport failure, or an error the callee chose to return.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ErrorCode(pub u32);
+
+impl ErrorCode {
+    pub const UNKNOWN: ErrorCode = ErrorCode(0);
+
+    // The request itself is bad
+    pub const MALFORMED: ErrorCode = ErrorCode(0x100);
+    pub const BAD_ARGUMENTS: ErrorCode = ErrorCode(0x101);
+    pub const BAD_ADDRESS: ErrorCode = ErrorCode(0x102);
+    pub const INVALID_SYSCALL: ErrorCode = ErrorCode(0x103);
+
+    // The callee could not be found or called
+    pub const NO_SUCH_MODULE: ErrorCode = ErrorCode(0x200);
+    pub const NO_SUCH_FUNCTION: ErrorCode = ErrorCode(0x201);
+    pub const WRONG_CALL_KIND: ErrorCode = ErrorCode(0x202);
+
+    // The call was made but could not complete
+    pub const RESOURCES: ErrorCode = ErrorCode(0x300);
+    pub const TOO_LARGE: ErrorCode = ErrorCode(0x301);
+
+    // Between the guest and the host
+    pub const TRANSPORT: ErrorCode = ErrorCode(0x400);
+    pub const TIMEOUT: ErrorCode = ErrorCode(0x401);
+
+    /// Codes from here up belong to the functions returning them.
+    pub const USER: ErrorCode = ErrorCode(0x1_0000);
+
+    pub fn user(code: u32) -> Self {
+        ErrorCode(ErrorCode::USER.0 + code)
+    }
+
+    pub fn is_user(&self) -> bool {
+        *self >= ErrorCode::USER
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        Some(match *self {
+            ErrorCode::UNKNOWN => "unknown",
+            ErrorCode::MALFORMED => "malformed",
+            ErrorCode::BAD_ARGUMENTS => "bad arguments",
+            ErrorCode::BAD_ADDRESS => "bad address",
+            ErrorCode::INVALID_SYSCALL => "invalid syscall",
+            ErrorCode::NO_SUCH_MODULE => "no such module",
+            ErrorCode::NO_SUCH_FUNCTION => "no such function",
+            ErrorCode::WRONG_CALL_KIND => "wrong call kind",
+            ErrorCode::RESOURCES => "out of resources",
+            ErrorCode::TOO_LARGE => "too large",
+            ErrorCode::TRANSPORT => "transport",
+            ErrorCode::TIMEOUT => "timed out",
+            _ => return None,
+        })
+    }
+}
+
+impl Debug for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{:#x} ({})", self.0, name),
+            None if self.is_user() => write!(f, "user {:#x}", self.0 - ErrorCode::USER.0),
+            None => write!(f, "{:#x}", self.0),
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+#[derive(Clone)]
+enum Cause<'a> {
+    /// Payload of a decoded fault, decoded on demand within the limits left to the fault holding
+    /// it.
+    Encoded(DecodeIter<'a>),
+    Borrowed(&'a Fault<'a>),
+    #[cfg(feature = "alloc")]
+    Boxed(Box<Fault<'a>>),
+}
+
+/// A structured error value. Encoded like an embedded value holding the code, the origin
+/// function or unit, the message, and the cause or unit.
+#[derive(Clone)]
+pub struct Fault<'a> {
+    pub code: ErrorCode,
+    /// The function that failed, if known.
+    pub origin: Option<Function<'a>>,
+    pub message: &'a str,
+    cause: Option<Cause<'a>>,
+}
+
+impl<'a> Fault<'a> {
+    pub fn new(code: ErrorCode, message: &'a str) -> Self {
+        Fault {
+            code,
+            origin: None,
+            message,
+            cause: None,
+        }
+    }
+
+    pub fn with_origin(mut self, origin: Function<'a>) -> Self {
+        self.origin = Some(origin);
+        self
+    }
+
+    pub fn with_cause(mut self, cause: &'a Fault<'a>) -> Self {
+        self.cause = Some(Cause::Borrowed(cause));
+        self
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn with_boxed_cause(mut self, cause: Fault<'a>) -> Self {
+        self.cause = Some(Cause::Boxed(Box::new(cause)));
+        self
+    }
+
+    /// The fault that led to this one. A cause that fails to decode is treated as missing, use
+    /// `validate_sos` to rule that out.
+    pub fn cause(&self) -> Option<Fault<'a>> {
+        match self.cause {
+            None => None,
+            Some(Cause::Encoded(ref iter)) => Fault::decode(iter.clone()),
+            Some(Cause::Borrowed(cause)) => Some(cause.clone()),
+            #[cfg(feature = "alloc")]
+            Some(Cause::Boxed(ref cause)) => Some((**cause).clone()),
+        }
+    }
+
+    /// The values this fault is encoded as.
+    pub(crate) fn values(&self) -> [Value<'a>; 4] {
+        [
+            Value::UInt32(self.code.0),
+            self.origin.clone().map_or(Value::Unit, Value::Function),
+            Value::String(self.message),
+            self.cause().map_or(Value::Unit, Value::Fault),
+        ]
+    }
+
+    /// Decodes the fault `iter` is over. Its cause is decoded from a buffer nested in `iter`, so
+    /// a chain of causes stays within the limits `iter` was decoded under.
+    pub(crate) fn decode(mut iter: DecodeIter<'a>) -> Option<Self> {
+        if iter.count != 4 {
+            return None;
+        }
+        let code = match iter.next()? {
+            Value::UInt32(code) => ErrorCode(code),
+            _ => return None,
+        };
+        let origin = match iter.next()? {
+            Value::Function(f) => Some(f),
+            Value::Unit => None,
+            _ => return None,
+        };
+        let message = match iter.next()? {
+            Value::String(message) => message,
+            _ => return None,
+        };
+        let cause = match iter.peek_raw()? {
+            (CType::Fault, data, _) => Some(Cause::Encoded(iter.nested(data)?)),
+            (CType::Unit, _, _) => None,
+            _ => return None,
+        };
+        Some(Fault {
+            code,
+            origin,
+            message,
+            cause,
+        })
+    }
+}
+
+impl<'a> SOS for Fault<'a> {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        ReferencedValues(&self.values()).encode(buf)
+    }
+
+    fn encoded_len(&self) -> usize {
+        ReferencedValues(&self.values()).encoded_len()
+    }
+}
+
+impl<'a> PartialEq for Fault<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.code == other.code
+            && self.origin == other.origin
+            && self.message == other.message
+            && self.cause() == other.cause()
+    }
+}
+
+impl<'a> Debug for Fault<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Fault")
+            .field("code", &self.code)
+            .field("origin", &self.origin)
+            .field("message", &self.message)
+            .field("cause", &self.cause())
+            .finish()
+    }
+}
+
+impl<'a> Display for Fault<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref origin) = self.origin {
+            write!(f, "{:?}: ", origin)?;
+        }
+        write!(f, "{} [{}]", self.message, self.code)?;
+        if let Some(cause) = self.cause() {
+            write!(f, ", caused by {}", cause)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct OwnedFault {
+    pub code: ErrorCode,
+    pub origin: Option<OwnedFunction>,
+    pub message: String,
+    pub cause: Option<Box<OwnedFault>>,
+}
+
+#[cfg(feature = "alloc")]
+impl OwnedFault {
+    pub fn new(code: ErrorCode, message: &str) -> Self {
+        OwnedFault {
+            code,
+            origin: None,
+            message: String::from(message),
+            cause: None,
+        }
+    }
+
+    pub fn borrow(&self) -> Fault {
+        Fault {
+            code: self.code,
+            origin: self.origin.as_ref().map(|f| Function {
+                module: &f.module,
+                name: &f.name,
+            }),
+            message: &self.message,
+            cause: self
+                .cause
+                .as_ref()
+                .map(|cause| Cause::Boxed(Box::new(cause.borrow()))),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'a> From<Fault<'a>> for OwnedFault {
+    fn from(fault: Fault<'a>) -> Self {
+        OwnedFault {
+            code: fault.code,
+            origin: fault.origin.clone().map(OwnedFunction::from),
+            message: String::from(fault.message),
+            cause: fault.cause().map(|cause| Box::new(OwnedFault::from(cause))),
+        }
+    }
+}
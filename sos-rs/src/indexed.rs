@@ -0,0 +1,120 @@
+use super::{DecodeIter, Value, SOS};
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::ops::Range;
+
+/// Random access to a list of encoded values. Where each value starts is found the first time a
+/// value at or after it is asked for, so every value is walked over at most once.
+#[derive(Debug, Clone)]
+pub struct IndexedValues<'a> {
+    values: DecodeIter<'a>,
+    /// Offsets into `values.buff` of the values found so far, the last one is where the walk
+    /// continues from.
+    starts: RefCell<Vec<usize>>,
+}
+
+impl<'a> IndexedValues<'a> {
+    pub fn new(values: DecodeIter<'a>) -> Self {
+        IndexedValues {
+            values,
+            starts: RefCell::new(vec![0]),
+        }
+    }
+
+    /// Number of values in the list. Values past the first malformed one can't be decoded, which
+    /// only happens if the buffer was not validated.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Decodes the value at `index`.
+    pub fn get(&self, index: usize) -> Option<Value<'a>> {
+        if index >= self.len() {
+            return None;
+        }
+        self.at(index)?.next()
+    }
+
+    /// The values in `range`, sharing this list's buffer. The result encodes to a valid argument
+    /// list on its own.
+    pub fn slice(&self, range: Range<usize>) -> Option<IndexedValues<'a>> {
+        if range.start > range.end || range.end > self.len() {
+            return None;
+        }
+        let first = self.start(range.start)?;
+        let end = self.start(range.end)?;
+        let mut values = self.walk(range.start, first);
+        values.buff = &values.buff[..end - first];
+        values.count = range.end - range.start;
+        let starts = self.starts.borrow()[range.start..range.end + 1]
+            .iter()
+            .map(|s| s - first)
+            .collect();
+        Some(IndexedValues {
+            values,
+            starts: RefCell::new(starts),
+        })
+    }
+
+    /// Iterates over the values from the start of the list.
+    pub fn iter(&self) -> DecodeIter<'a> {
+        self.values.clone()
+    }
+
+    pub fn into_inner(self) -> DecodeIter<'a> {
+        self.values
+    }
+
+    /// Offset of the value at `index`, or of the end of the list if `index` is its length.
+    fn start(&self, index: usize) -> Option<usize> {
+        let mut starts = self.starts.borrow_mut();
+        while starts.len() <= index {
+            let found = starts.len() - 1;
+            let (_, _, rest) = self.walk(found, starts[found]).peek_raw()?;
+            let next = self.values.buff.len() - rest.len();
+            starts.push(next);
+        }
+        Some(starts[index])
+    }
+
+    /// Iterator over the values from `index` on.
+    fn at(&self, index: usize) -> Option<DecodeIter<'a>> {
+        let start = self.start(index)?;
+        Some(self.walk(index, start))
+    }
+
+    fn walk(&self, index: usize, start: usize) -> DecodeIter<'a> {
+        let mut iter = self.values.clone();
+        iter.buff = &iter.buff[start..];
+        iter.offset += start;
+        iter.count -= index;
+        iter.position += index;
+        iter.budget = iter.budget.saturating_sub(index);
+        iter
+    }
+}
+
+impl<'a> From<DecodeIter<'a>> for IndexedValues<'a> {
+    fn from(values: DecodeIter<'a>) -> Self {
+        IndexedValues::new(values)
+    }
+}
+
+impl<'a> PartialEq for IndexedValues<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.values == other.values
+    }
+}
+
+impl<'a> SOS for IndexedValues<'a> {
+    fn encoded_len(&self) -> usize {
+        self.values.encoded_len()
+    }
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        self.values.encode(buf)
+    }
+}
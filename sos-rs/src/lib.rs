@@ -1,11 +1,60 @@
 #![no_std]
 #![feature(try_from)]
-#![feature(alloc)]
+#![cfg_attr(feature = "alloc", feature(alloc))]
 #![allow(dead_code)]
+#[cfg(feature = "alloc")]
+#[macro_use]
 extern crate alloc;
 extern crate byteorder;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+
+macro_rules! do_list {
+    ($do:ident[$($arg:tt),*]) => {
+        $($do!$arg;)*
+    };
+}
+
+mod args;
+mod array;
+#[cfg(all(feature = "serde", feature = "alloc"))]
+pub mod de;
+mod encoder;
+mod extension;
+mod fault;
+#[cfg(feature = "alloc")]
+mod indexed;
+#[cfg(all(feature = "serde", feature = "alloc"))]
+pub mod ser;
+mod text;
+mod validate;
+
+pub use args::{ArgError, ArgResult, FromValue, FromValues};
+#[cfg(feature = "alloc")]
+pub use array::OwnedArray;
+pub use array::{Array, ArrayElement, ArrayIter, ArrayType, ArrayValues, ARRAY_TYPES};
+#[cfg(all(feature = "serde", feature = "alloc"))]
+pub use de::{from_iter, from_slice};
+pub use encoder::{EncodeError, Encoder, ENCODER_MAX_DEPTH};
+#[cfg(feature = "alloc")]
+pub use extension::OwnedExtension;
+pub use extension::{Extension, ExtensionType, EXTENSION_MAX_ID, SOS_EXTENSION_TAG};
+#[cfg(feature = "alloc")]
+pub use fault::OwnedFault;
+pub use fault::{ErrorCode, Fault};
+#[cfg(feature = "alloc")]
+pub use indexed::IndexedValues;
+#[cfg(all(feature = "serde", feature = "alloc"))]
+pub use ser::{to_owned_values, to_vec, Error as SerdeError};
+#[cfg(feature = "alloc")]
+pub use text::{parse_sos, parse_values};
+pub use text::{ParseError, ParseErrorKind};
+pub use validate::{
+    validate_sos, validate_sos_limited, DecodeError, DecodeErrorKind, DecodeLimits, ValidatedValues,
+};
 
-use self::byteorder::{ByteOrder, NativeEndian};
+use self::byteorder::{ByteOrder, LittleEndian};
 #[cfg(feature = "alloc")]
 use alloc::borrow::Cow;
 #[cfg(feature = "alloc")]
@@ -14,13 +63,25 @@ use alloc::string::String;
 use alloc::vec::Vec;
 use core::convert::TryInto;
 use core::fmt::Debug;
+use core::mem;
+#[cfg(feature = "alloc")]
 use core::ops::Deref;
+#[cfg(feature = "alloc")]
 use core::slice;
 use core::str::from_utf8;
 
 const NULL: [u8; 1] = [0];
 const WRONG_TYPE: &str = "Received value is of incorrect type";
 
+/// Every encoded buffer starts with these two bytes.
+pub const SOS_MAGIC: [u8; 2] = *b"SO";
+/// Version of the wire format produced by this crate, decoders reject any other version.
+pub const SOS_VERSION: u8 = 1;
+/// magic (2), version (1), flags (1), count (4), size (4), all integers are little endian.
+pub const SOS_HEADER_SIZE: usize = 12;
+/// Header flag for `Layout::Aligned`.
+pub const SOS_FLAG_ALIGNED: u8 = 1;
+
 pub type EncodedValuesPtr = *const u8;
 
 type SyntacticFunc<'a> = (&'a str, &'a str);
@@ -76,6 +137,71 @@ pub trait SOS {
     fn encoded_len(&self) -> usize;
 }
 
+/// How the values in a buffer are placed, recorded in the header flags. Embedded values, maps and
+/// faults have headers of their own, so they may differ from the buffer they are in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// Values follow each other without padding.
+    Packed,
+    /// The data of every value is padded to its natural alignment, counted from the start of the
+    /// header. Zero bytes where a type is expected are padding. A buffer that is 8 byte aligned
+    /// in memory can hand out references to its numbers and arrays.
+    ///
+    /// A fixed size value is padded to its size, a length to 4 bytes and the data after a length
+    /// to 8 bytes. The element type of an array is followed by padding to the element size.
+    Aligned,
+}
+
+impl Layout {
+    /// Header flags that record the layout.
+    pub fn flags(self) -> u8 {
+        match self {
+            Layout::Packed => 0,
+            Layout::Aligned => SOS_FLAG_ALIGNED,
+        }
+    }
+
+    /// The layout recorded in header flags, `None` if there are flags this crate does not know.
+    pub fn from_flags(flags: u8) -> Option<Self> {
+        match flags {
+            0 => Some(Layout::Packed),
+            SOS_FLAG_ALIGNED => Some(Layout::Aligned),
+            _ => None,
+        }
+    }
+
+    /// Offsets of the length, if the type has one, and of the data, for a value whose type is at
+    /// `offset`.
+    fn place(self, val_type: CType, offset: usize) -> (Option<usize>, usize) {
+        match (self, val_type.fixed_size()) {
+            (Layout::Packed, Some(_)) => (None, offset + 1),
+            (Layout::Packed, None) => (Some(offset + 1), offset + 5),
+            (Layout::Aligned, Some(size)) => (None, align_up(offset + 1, size)),
+            (Layout::Aligned, None) => {
+                let length = align_up(offset + 1, 4);
+                (Some(length), align_up(length + 4, 8))
+            }
+        }
+    }
+
+    /// Padding between the element type of an array and its elements.
+    fn array_padding(self, elem: ArrayType) -> usize {
+        match self {
+            Layout::Packed => 0,
+            Layout::Aligned => elem.size() - 1,
+        }
+    }
+}
+
+/// Rounds `offset` up to a multiple of `align`, which is a power of two or 0.
+fn align_up(offset: usize, align: usize) -> usize {
+    if align <= 1 {
+        offset
+    } else {
+        (offset + align - 1) & !(align - 1)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value<'a> {
     Int32(i32),
@@ -84,6 +210,12 @@ pub enum Value<'a> {
     UInt64(u64),
     Float(f32),
     Double(f64),
+    Bool(bool),
+    Unit,
+    Int8(i8),
+    UInt8(u8),
+    Int16(i16),
+    UInt16(u16),
     Error(&'a str),
     String(&'a str),
     Opaque(&'a [u8]),
@@ -92,6 +224,13 @@ pub enum Value<'a> {
     EmbeddedIn(ReferencedValues<'a>),
     #[cfg(feature = "alloc")]
     EmbeddedVec(Vec<Value<'a>>),
+    Array(Array<'a>),
+    MapOut(MapIter<'a>),
+    MapIn(&'a [(Value<'a>, Value<'a>)]),
+    #[cfg(feature = "alloc")]
+    MapVec(Vec<(Value<'a>, Value<'a>)>),
+    Fault(Fault<'a>),
+    Extension(Extension<'a>),
 }
 
 #[cfg(feature = "alloc")]
@@ -103,11 +242,21 @@ pub enum OwnedValue {
     UInt64(u64),
     Float(f32),
     Double(f64),
+    Bool(bool),
+    Unit,
+    Int8(i8),
+    UInt8(u8),
+    Int16(i16),
+    UInt16(u16),
     Error(String),
     String(String),
     Opaque(Vec<u8>),
     Function(OwnedFunction),
     Embedded(Vec<OwnedValue>),
+    Array(OwnedArray),
+    Map(Vec<(OwnedValue, OwnedValue)>),
+    Fault(OwnedFault),
+    Extension(OwnedExtension),
 }
 
 #[cfg(feature = "alloc")]
@@ -120,6 +269,12 @@ impl OwnedValue {
             OwnedValue::UInt64(i) => Value::UInt64(*i),
             OwnedValue::Float(i) => Value::Float(*i),
             OwnedValue::Double(i) => Value::Double(*i),
+            OwnedValue::Bool(i) => Value::Bool(*i),
+            OwnedValue::Unit => Value::Unit,
+            OwnedValue::Int8(i) => Value::Int8(*i),
+            OwnedValue::UInt8(i) => Value::UInt8(*i),
+            OwnedValue::Int16(i) => Value::Int16(*i),
+            OwnedValue::UInt16(i) => Value::UInt16(*i),
             OwnedValue::Error(i) => Value::Error(i),
             OwnedValue::String(i) => Value::String(i),
             OwnedValue::Opaque(i) => Value::Opaque(i),
@@ -128,34 +283,79 @@ impl OwnedValue {
                 name: &i.name,
             }),
             OwnedValue::Embedded(i) => Value::EmbeddedVec(i.iter().map(|v| v.borrow()).collect()),
+            OwnedValue::Array(i) => Value::Array(i.borrow()),
+            OwnedValue::Map(i) => {
+                Value::MapVec(i.iter().map(|(k, v)| (k.borrow(), v.borrow())).collect())
+            }
+            OwnedValue::Fault(i) => Value::Fault(i.borrow()),
+            OwnedValue::Extension(i) => Value::Extension(i.borrow()),
         }
     }
 }
 
 #[derive(Debug)]
-pub struct JustError<'a>([Value<'a>; 1]);
+pub struct JustError<'a>(ErrorFault<'a>);
 
-impl<'a> Deref for JustError<'a> {
-    type Target = [Value<'a>];
-
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
+#[derive(Debug)]
+enum ErrorFault<'a> {
+    Borrowed(Fault<'a>),
+    /// For messages put together at runtime.
+    #[cfg(feature = "alloc")]
+    Owned(OwnedFault),
 }
 
 impl<'a> JustError<'a> {
     pub fn new(error: &'a str) -> Self {
-        JustError([Value::Error(error); 1])
+        JustError::with_code(ErrorCode::UNKNOWN, error)
+    }
+
+    pub fn with_code(code: ErrorCode, message: &'a str) -> Self {
+        JustError(ErrorFault::Borrowed(Fault::new(code, message)))
+    }
+
+    pub fn fault(&self) -> Fault {
+        match self.0 {
+            ErrorFault::Borrowed(ref fault) => fault.clone(),
+            #[cfg(feature = "alloc")]
+            ErrorFault::Owned(ref fault) => fault.borrow(),
+        }
+    }
+
+    /// The values the error is encoded as.
+    pub fn values(&self) -> [Value; 1] {
+        [Value::Fault(self.fault())]
+    }
+
+    /// Records the function the error came from, unless an inner call already did.
+    pub fn with_origin(mut self, origin: Function<'a>) -> Self {
+        match self.0 {
+            ErrorFault::Borrowed(ref mut fault) if fault.origin.is_none() => {
+                fault.origin = Some(origin)
+            }
+            #[cfg(feature = "alloc")]
+            ErrorFault::Owned(ref mut fault) if fault.origin.is_none() => {
+                fault.origin = Some(OwnedFunction::from(origin))
+            }
+            _ => (),
+        }
+        self
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'a> From<OwnedFault> for JustError<'a> {
+    fn from(fault: OwnedFault) -> Self {
+        JustError(ErrorFault::Owned(fault))
     }
 }
 
 impl<'a> SOS for JustError<'a> {
     fn encoded_len(&self) -> usize {
-        ReferencedValues(&self.0).encoded_len()
+        ReferencedValues(&self.values()).encoded_len()
     }
 
     fn encode(&self, buf: &mut [u8]) -> usize {
-        ReferencedValues(&self.0).encode(buf)
+        ReferencedValues(&self.values()).encode(buf)
     }
 }
 
@@ -164,7 +364,7 @@ pub struct ReferencedValues<'a>(pub &'a [Value<'a>]);
 
 impl<'a> SOS for ReferencedValues<'a> {
     fn encode(&self, buf: &mut [u8]) -> usize {
-        encode_sos(buf, self.0)
+        encode_sos(buf, self.0, Layout::Packed)
     }
 
     fn encoded_len(&self) -> usize {
@@ -172,6 +372,20 @@ impl<'a> SOS for ReferencedValues<'a> {
     }
 }
 
+/// Like `ReferencedValues`, but encoded with `Layout::Aligned`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlignedValues<'a>(pub &'a [Value<'a>]);
+
+impl<'a> SOS for AlignedValues<'a> {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        encode_sos(buf, self.0, Layout::Aligned)
+    }
+
+    fn encoded_len(&self) -> usize {
+        values_len(self.0, Layout::Aligned)
+    }
+}
+
 #[cfg(feature = "alloc")]
 pub type OwnedEncodedValues = Vec<u8>;
 
@@ -193,12 +407,19 @@ impl<'a> EncodedValues<'a> {
     pub fn decode(&self) -> Option<DecodeIter> {
         decode_sos(&self, true)
     }
+    /// Checks the whole buffer before handing out any values, use this for untrusted input.
+    pub fn validate(&self) -> Result<ValidatedValues, DecodeError> {
+        validate_sos(&self)
+    }
+    pub fn validate_limited(&self, limits: DecodeLimits) -> Result<ValidatedValues, DecodeError> {
+        validate_sos_limited(&self, limits)
+    }
     pub fn into_owned(self) -> OwnedEncodedValues {
         self.0.into_owned()
     }
-    // NO, length is not currently sent, I need to send it.
     pub unsafe fn from_ptr(ptr: EncodedValuesPtr) -> Self {
-        let length = *(ptr as *const u32).offset(1);
+        let header = slice::from_raw_parts(ptr, SOS_HEADER_SIZE);
+        let length = LittleEndian::read_u32(&header[8..12]);
         EncodedValues(Cow::Borrowed(slice::from_raw_parts(ptr, length as usize)))
     }
 }
@@ -249,6 +470,12 @@ impl<'a> Value<'a> {
             Value::UInt64(_) => stringify!(Value::UInt64),
             Value::Float(_) => stringify!(Value::Float),
             Value::Double(_) => stringify!(Value::Double),
+            Value::Bool(_) => stringify!(Value::Bool),
+            Value::Unit => stringify!(Value::Unit),
+            Value::Int8(_) => stringify!(Value::Int8),
+            Value::UInt8(_) => stringify!(Value::UInt8),
+            Value::Int16(_) => stringify!(Value::Int16),
+            Value::UInt16(_) => stringify!(Value::UInt16),
             Value::String(_) => stringify!(Value::String),
             Value::Error(_) => stringify!(Value::Error),
             Value::Opaque(_) => stringify!(Value::Opaque),
@@ -257,8 +484,19 @@ impl<'a> Value<'a> {
             Value::EmbeddedIn(_) => stringify!(Value::EmbeddedIn),
             #[cfg(feature = "alloc")]
             Value::EmbeddedVec(_) => stringify!(Value::EmbeddedVec),
+            Value::Array(_) => stringify!(Value::Array),
+            Value::MapOut(_) => stringify!(Value::MapOut),
+            Value::MapIn(_) => stringify!(Value::MapIn),
+            #[cfg(feature = "alloc")]
+            Value::MapVec(_) => stringify!(Value::MapVec),
+            Value::Fault(_) => stringify!(Value::Fault),
+            Value::Extension(_) => stringify!(Value::Extension),
         }
     }
+    /// The type byte the value is encoded with.
+    pub fn tag(&self) -> u8 {
+        self.ctype().tag()
+    }
     #[inline(always)]
     fn ctype(&self) -> CType {
         match self {
@@ -268,6 +506,12 @@ impl<'a> Value<'a> {
             Value::UInt64(_) => CType::UInt64,
             Value::Float(_) => CType::Float,
             Value::Double(_) => CType::Double,
+            Value::Bool(_) => CType::Bool,
+            Value::Unit => CType::Unit,
+            Value::Int8(_) => CType::Int8,
+            Value::UInt8(_) => CType::UInt8,
+            Value::Int16(_) => CType::Int16,
+            Value::UInt16(_) => CType::UInt16,
             Value::String(_) => CType::String,
             Value::Error(_) => CType::Error,
             Value::Opaque(_) => CType::Opaque,
@@ -275,29 +519,45 @@ impl<'a> Value<'a> {
             Value::EmbeddedOut(_) | Value::EmbeddedIn(_) => CType::Embedded,
             #[cfg(feature = "alloc")]
             Value::EmbeddedVec(_) => CType::Embedded,
+            Value::Array(_) => CType::Array,
+            Value::MapOut(_) | Value::MapIn(_) => CType::Map,
+            #[cfg(feature = "alloc")]
+            Value::MapVec(_) => CType::Map,
+            Value::Fault(_) => CType::Fault,
+            Value::Extension(ref e) => CType::Extension(e.type_id()),
         }
     }
-    #[inline(always)]
-    fn encoded_size(&self) -> usize {
+    /// Size of the data, without the type and length.
+    fn data_len(&self, layout: Layout) -> usize {
         match self {
             &Value::Int32(_) | &Value::UInt32(_) | &Value::Float(_) => 4,
             &Value::Int64(_) | &Value::UInt64(_) | &Value::Double(_) => 8,
-            &Value::String(ref i) => i.len() + 1 + 4,
-            &Value::Error(ref i) => i.len() + 1 + 4,
-            &Value::Opaque(ref i) => i.len() + 4,
-            &Value::EmbeddedIn(ref i) => i.encoded_len() + 4,
-            &Value::EmbeddedOut(ref f) => f.buff.len() + 4,
+            &Value::Bool(_) | &Value::Int8(_) | &Value::UInt8(_) => 1,
+            &Value::Int16(_) | &Value::UInt16(_) => 2,
+            &Value::Unit => 0,
+            &Value::String(ref i) => i.len() + 1,
+            &Value::Error(ref i) => i.len() + 1,
+            &Value::Opaque(ref i) => i.len(),
+            &Value::EmbeddedIn(ref i) => values_len(i.0, layout),
+            &Value::EmbeddedOut(ref f) => f.encoded_len(),
             #[cfg(feature = "alloc")]
-            &Value::EmbeddedVec(ref i) => ReferencedValues(&i[..]).encoded_len() + 4,
-            &Value::Function(ref f) => f.module.len() + f.name.len() + 2 + 4,
+            &Value::EmbeddedVec(ref i) => values_len(&i[..], layout),
+            &Value::Function(ref f) => f.module.len() + f.name.len() + 2,
+            &Value::Array(ref a) => 1 + layout.array_padding(a.elem_type()) + a.as_bytes().len(),
+            &Value::MapOut(ref m) => m.encoded_len(),
+            &Value::MapIn(ref p) => pairs_len(p, layout),
+            #[cfg(feature = "alloc")]
+            &Value::MapVec(ref p) => pairs_len(&p[..], layout),
+            &Value::Fault(ref f) => values_len(&f.values(), layout),
+            &Value::Extension(ref e) => e.data().len(),
         }
     }
-}
 
-macro_rules! do_list {
-    ($do:ident[$($arg:tt),*]) => {
-        $($do!$arg;)*
-    };
+    /// Bytes taken up by the value when its type is at `offset`, including any padding.
+    fn encoded_size(&self, offset: usize, layout: Layout) -> usize {
+        let (_, data) = layout.place(self.ctype(), offset);
+        data + self.data_len(layout) - offset
+    }
 }
 
 macro_rules! impl_from {
@@ -331,13 +591,29 @@ do_list!(impl_from[
     (u64, Value::UInt64),
     (f32, Value::Float),
     (f64, Value::Double),
+    (bool, Value::Bool),
+    (i8, Value::Int8),
+    (u8, Value::UInt8),
+    (i16, Value::Int16),
+    (u16, Value::UInt16),
     (&'a str, Value::String),
     (&'a [u8], Value::Opaque),
     (Function<'a>, Value::Function),
     (DecodeIter<'a>, Value::EmbeddedOut),
-    (ReferencedValues<'a>, Value::EmbeddedIn)
+    (ReferencedValues<'a>, Value::EmbeddedIn),
+    (Array<'a>, Value::Array),
+    (MapIter<'a>, Value::MapOut),
+    (&'a [(Value<'a>, Value<'a>)], Value::MapIn),
+    (Fault<'a>, Value::Fault),
+    (Extension<'a>, Value::Extension)
 ]);
 
+impl<'a> From<()> for Value<'a> {
+    fn from(_: ()) -> Self {
+        Value::Unit
+    }
+}
+
 impl<'a> From<(&'a str, &'a str)> for Value<'a> {
     fn from(i: (&'a str, &'a str)) -> Self {
         Value::Function(Function {
@@ -354,10 +630,19 @@ do_list!(impl_try_into[
     (Value::UInt64, u64),
     (Value::Float, f32),
     (Value::Double, f64),
+    (Value::Bool, bool),
+    (Value::Int8, i8),
+    (Value::UInt8, u8),
+    (Value::Int16, i16),
+    (Value::UInt16, u16),
     (Value::Opaque, &'a [u8]),
     (Value::Function, Function<'a>),
     (Value::EmbeddedOut, DecodeIter<'a>),
-    (Value::EmbeddedIn, ReferencedValues<'a>)
+    (Value::EmbeddedIn, ReferencedValues<'a>),
+    (Value::Array, Array<'a>),
+    (Value::MapOut, MapIter<'a>),
+    (Value::Fault, Fault<'a>),
+    (Value::Extension, Extension<'a>)
 ]);
 
 impl<'a> TryInto<&'a str> for Value<'a> {
@@ -384,6 +669,17 @@ enum CType {
     Opaque,
     Function,
     Embedded,
+    Bool,
+    Unit,
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Array,
+    Map,
+    Fault,
+    /// Any tag from `SOS_EXTENSION_TAG` up, holding the type id.
+    Extension(u8),
 }
 
 impl CType {
@@ -401,17 +697,91 @@ impl CType {
             9 => Some(CType::Opaque),
             10 => Some(CType::Function),
             11 => Some(CType::Embedded),
+            12 => Some(CType::Bool),
+            13 => Some(CType::Unit),
+            14 => Some(CType::Int8),
+            15 => Some(CType::UInt8),
+            16 => Some(CType::Int16),
+            17 => Some(CType::UInt16),
+            18 => Some(CType::Array),
+            19 => Some(CType::Map),
+            20 => Some(CType::Fault),
+            SOS_EXTENSION_TAG...0xff => Some(CType::Extension(i - SOS_EXTENSION_TAG)),
+            _ => None,
+        }
+    }
+
+    fn tag(self) -> u8 {
+        match self {
+            CType::Invalid => 0,
+            CType::Int32 => 1,
+            CType::UInt32 => 2,
+            CType::Int64 => 3,
+            CType::UInt64 => 4,
+            CType::Float => 5,
+            CType::Double => 6,
+            CType::Error => 7,
+            CType::String => 8,
+            CType::Opaque => 9,
+            CType::Function => 10,
+            CType::Embedded => 11,
+            CType::Bool => 12,
+            CType::Unit => 13,
+            CType::Int8 => 14,
+            CType::UInt8 => 15,
+            CType::Int16 => 16,
+            CType::UInt16 => 17,
+            CType::Array => 18,
+            CType::Map => 19,
+            CType::Fault => 20,
+            CType::Extension(id) => SOS_EXTENSION_TAG | id,
+        }
+    }
+
+    /// Size of the data for types that have no length field.
+    fn fixed_size(&self) -> Option<usize> {
+        match self {
+            CType::Unit => Some(0),
+            CType::Bool | CType::Int8 | CType::UInt8 => Some(1),
+            CType::Int16 | CType::UInt16 => Some(2),
+            CType::Int32 | CType::UInt32 | CType::Float => Some(4),
+            CType::Int64 | CType::UInt64 | CType::Double => Some(8),
             _ => None,
         }
     }
 }
 
 pub fn encoded_len(values: &[Value]) -> usize {
-    let mut len = values.len() + 8;
+    values_len(values, Layout::Packed)
+}
+
+fn values_len(values: &[Value], layout: Layout) -> usize {
+    let mut offset = SOS_HEADER_SIZE;
     for value in values {
-        len += value.encoded_size();
+        offset += value.encoded_size(offset, layout);
+    }
+    offset
+}
+
+/// Maps are encoded like embedded values, with the keys and values alternating.
+fn pairs_len(pairs: &[(Value, Value)], layout: Layout) -> usize {
+    let mut offset = SOS_HEADER_SIZE;
+    for (key, value) in pairs {
+        offset += key.encoded_size(offset, layout);
+        offset += value.encoded_size(offset, layout);
     }
-    len
+    offset
+}
+
+fn encode_pairs(buf: &mut [u8], pairs: &[(Value, Value)], layout: Layout) -> usize {
+    let len = pairs_len(pairs, layout);
+    write_header(buf, pairs.len() * 2, len, layout);
+    let mut offset = SOS_HEADER_SIZE;
+    for (key, value) in pairs {
+        offset = encode_value(buf, offset, key, layout);
+        offset = encode_value(buf, offset, value, layout);
+    }
+    offset
 }
 
 #[macro_export]
@@ -428,70 +798,120 @@ macro_rules! sos {
     };
 }
 
-#[allow(unused_must_use)]
-fn encode_sos(buf: &mut [u8], values: &[Value]) -> usize {
-    let len = ReferencedValues(values).encoded_len();
+fn write_header(buf: &mut [u8], count: usize, size: usize, layout: Layout) {
+    buf[..2].copy_from_slice(&SOS_MAGIC);
+    buf[2] = SOS_VERSION;
+    buf[3] = layout.flags();
+    LittleEndian::write_u32(&mut buf[4..8], count as u32);
+    LittleEndian::write_u32(&mut buf[8..12], size as u32);
+}
+
+/// Returns the value count, total size and layout from the header, if it is one we understand.
+fn read_header(buf: &[u8]) -> Option<(usize, usize, Layout)> {
+    if buf.len() < SOS_HEADER_SIZE || buf[..2] != SOS_MAGIC || buf[2] != SOS_VERSION {
+        return None;
+    }
+    let layout = Layout::from_flags(buf[3])?;
+    let count = LittleEndian::read_u32(&buf[4..8]) as usize;
+    let size = LittleEndian::read_u32(&buf[8..12]) as usize;
+    Some((count, size, layout))
+}
+
+fn encode_sos(buf: &mut [u8], values: &[Value], layout: Layout) -> usize {
+    let len = values_len(values, layout);
     assert!(buf.len() >= len);
     let buf = &mut buf[..len];
-    NativeEndian::write_u32(&mut buf[..4], values.len() as u32);
-    NativeEndian::write_u32(&mut buf[4..8], len as u32);
-    let mut coffset = 8;
+    write_header(buf, values.len(), len, layout);
+    let mut offset = SOS_HEADER_SIZE;
     for value in values {
-        let val_type = value.ctype();
-        let mut length = value.encoded_size();
-        buf[coffset] = val_type as u8;
-        coffset += match val_type {
-            CType::Int32
-            | CType::UInt32
-            | CType::Float
-            | CType::Int64
-            | CType::UInt64
-            | CType::Double => 1,
-            _ => {
-                length -= 4;
-                NativeEndian::write_u32(&mut buf[coffset + 1..coffset + 4 + 1], length as u32);
-                5
-            }
-        };
-        let wbuf = &mut buf[coffset..coffset + length];
-        match value {
-            &Value::Int32(i) => NativeEndian::write_i32(wbuf, i),
-            &Value::UInt32(i) => NativeEndian::write_u32(wbuf, i),
-            &Value::Int64(i) => NativeEndian::write_i64(wbuf, i),
-            &Value::UInt64(i) => NativeEndian::write_u64(wbuf, i),
-            &Value::Float(i) => NativeEndian::write_f32(wbuf, i),
-            &Value::Double(i) => NativeEndian::write_f64(wbuf, i),
-            &Value::String(i) => {
-                wbuf[..length - 1].copy_from_slice(i.as_bytes());
-                wbuf[length - 1] = 0;
-            }
-            &Value::Error(i) => {
-                wbuf[..length - 1].copy_from_slice(i.as_bytes());
-                wbuf[length - 1] = 0;
-            }
-            &Value::Opaque(i) => {
-                wbuf.copy_from_slice(&i);
-            }
-            &Value::EmbeddedIn(ref i) => {
-                i.encode(wbuf);
-            }
-            &Value::EmbeddedOut(ref f) => {
-                f.encode(wbuf);
-            }
-            &Value::EmbeddedVec(ref i) => {
-                ReferencedValues(&i[..]).encode(wbuf);
-            }
-            &Value::Function(ref f) => {
-                let modlen = f.module.len();
-                wbuf[..modlen].copy_from_slice(f.module.as_bytes());
-                wbuf[modlen] = 0;
-                wbuf[1 + modlen..length as usize - 1].copy_from_slice(f.name.as_bytes());
-                wbuf[length as usize - 1] = 0;
+        offset = encode_value(buf, offset, value, layout);
+    }
+    return offset;
+}
+
+/// Writes the type, length and data of a single value at `offset` in `buf`, a buffer that starts
+/// with its header, which must have room for `value.encoded_size(offset, layout)` bytes. Returns
+/// the offset after the value.
+#[allow(unused_must_use)]
+fn encode_value(buf: &mut [u8], offset: usize, value: &Value, layout: Layout) -> usize {
+    let val_type = value.ctype();
+    let length = value.data_len(layout);
+    let (length_at, data) = layout.place(val_type, offset);
+    buf[offset] = val_type.tag();
+    for b in &mut buf[offset + 1..data] {
+        *b = 0;
+    }
+    if let Some(at) = length_at {
+        LittleEndian::write_u32(&mut buf[at..at + 4], length as u32);
+    }
+    let wbuf = &mut buf[data..data + length];
+    match value {
+        &Value::Int32(i) => LittleEndian::write_i32(wbuf, i),
+        &Value::UInt32(i) => LittleEndian::write_u32(wbuf, i),
+        &Value::Int64(i) => LittleEndian::write_i64(wbuf, i),
+        &Value::UInt64(i) => LittleEndian::write_u64(wbuf, i),
+        &Value::Float(i) => LittleEndian::write_f32(wbuf, i),
+        &Value::Double(i) => LittleEndian::write_f64(wbuf, i),
+        &Value::Bool(i) => wbuf[0] = i as u8,
+        &Value::Unit => (),
+        &Value::Int8(i) => wbuf[0] = i as u8,
+        &Value::UInt8(i) => wbuf[0] = i,
+        &Value::Int16(i) => LittleEndian::write_i16(wbuf, i),
+        &Value::UInt16(i) => LittleEndian::write_u16(wbuf, i),
+        &Value::String(i) => {
+            wbuf[..length - 1].copy_from_slice(i.as_bytes());
+            wbuf[length - 1] = 0;
+        }
+        &Value::Error(i) => {
+            wbuf[..length - 1].copy_from_slice(i.as_bytes());
+            wbuf[length - 1] = 0;
+        }
+        &Value::Opaque(i) => {
+            wbuf.copy_from_slice(&i);
+        }
+        &Value::EmbeddedIn(ref i) => {
+            encode_sos(wbuf, i.0, layout);
+        }
+        &Value::EmbeddedOut(ref f) => {
+            f.encode(wbuf);
+        }
+        #[cfg(feature = "alloc")]
+        &Value::EmbeddedVec(ref i) => {
+            encode_sos(wbuf, &i[..], layout);
+        }
+        &Value::Function(ref f) => {
+            let modlen = f.module.len();
+            wbuf[..modlen].copy_from_slice(f.module.as_bytes());
+            wbuf[modlen] = 0;
+            wbuf[1 + modlen..length as usize - 1].copy_from_slice(f.name.as_bytes());
+            wbuf[length as usize - 1] = 0;
+        }
+        &Value::Array(ref a) => {
+            let start = 1 + layout.array_padding(a.elem_type());
+            wbuf[0] = a.elem_type().ctype().tag();
+            for b in &mut wbuf[1..start] {
+                *b = 0;
             }
+            wbuf[start..].copy_from_slice(a.as_bytes());
+        }
+        &Value::MapOut(ref m) => {
+            m.encode(wbuf);
+        }
+        &Value::MapIn(p) => {
+            encode_pairs(wbuf, p, layout);
+        }
+        #[cfg(feature = "alloc")]
+        &Value::MapVec(ref p) => {
+            encode_pairs(wbuf, &p[..], layout);
+        }
+        &Value::Fault(ref f) => {
+            encode_sos(wbuf, &f.values(), layout);
+        }
+        &Value::Extension(ref e) => {
+            wbuf.copy_from_slice(e.data());
         }
-        coffset += length as usize;
     }
-    return coffset;
+    data + length
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -499,71 +919,279 @@ pub struct DecodeIter<'a> {
     count: usize,
     buff: &'a [u8],
     lazy: bool,
+    position: usize,
+    layout: Layout,
+    /// Where `buff` starts, counted from the start of the header.
+    offset: usize,
+    limits: DecodeLimits,
+    /// How many buffers this one is nested in.
+    depth: usize,
+    /// Values that can still be decoded before `limits.max_values` is reached.
+    budget: usize,
 }
 
 impl<'a> SOS for DecodeIter<'a> {
     fn encoded_len(&self) -> usize {
-        self.buff.len() + 8
+        SOS_HEADER_SIZE + self.lead() + self.buff.len()
     }
     fn encode(&self, buf: &mut [u8]) -> usize {
-        NativeEndian::write_u32(&mut buf[..4], self.count as u32);
-        NativeEndian::write_u32(&mut buf[4..8], 0);
-        buf[8..].copy_from_slice(&self.buff);
-        self.encoded_len()
+        let len = self.encoded_len();
+        let start = SOS_HEADER_SIZE + self.lead();
+        write_header(buf, self.count, len, self.layout);
+        for b in &mut buf[SOS_HEADER_SIZE..start] {
+            *b = 0;
+        }
+        buf[start..len].copy_from_slice(&self.buff);
+        len
     }
 }
 
 impl<'a> DecodeIter<'a> {
-    fn count(&self) -> usize {
+    /// Number of values left according to the header.
+    pub fn len(&self) -> usize {
         self.count
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Random access to the values left.
+    #[cfg(feature = "alloc")]
+    pub fn indexed(self) -> IndexedValues<'a> {
+        IndexedValues::new(self)
+    }
+
+    /// Index of the next value to be decoded.
+    pub fn index(&self) -> usize {
+        self.position
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Padding needed in front of the remaining values to keep them aligned when they are copied
+    /// to a new buffer.
+    fn lead(&self) -> usize {
+        match self.layout {
+            Layout::Packed => 0,
+            Layout::Aligned => (self.offset - SOS_HEADER_SIZE) % 8,
+        }
+    }
+}
+
+/// Iterates over the key, value pairs of a map.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapIter<'a>(DecodeIter<'a>);
+
+impl<'a> MapIter<'a> {
+    /// Number of pairs left.
+    pub fn len(&self) -> usize {
+        self.0.count / 2
+    }
+
+    /// The keys and values left, each key followed by its value.
+    pub fn into_inner(self) -> DecodeIter<'a> {
+        self.0
+    }
+}
+
+impl<'a> Iterator for MapIter<'a> {
+    type Item = (Value<'a>, Value<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let key = self.0.next()?;
+        let value = self.0.next()?;
+        Some((key, value))
+    }
 }
 
+impl<'a> SOS for MapIter<'a> {
+    fn encoded_len(&self) -> usize {
+        self.0.encoded_len()
+    }
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        self.0.encode(buf)
+    }
+}
+
+/// Decodes `buff` within the default `DecodeLimits`. A lazy iterator returns Embedded and Map
+/// values as iterators over their buffers, otherwise they are collected.
 pub fn decode_sos(buff: &[u8], lazy: bool) -> Option<DecodeIter> {
-    if buff.len() <= 8 {
+    decode_sos_limited(buff, lazy, DecodeLimits::default())
+}
+
+/// Like `decode_sos`, but the iterator stops at the first value that exceeds `limits`.
+pub fn decode_sos_limited(buff: &[u8], lazy: bool, limits: DecodeLimits) -> Option<DecodeIter> {
+    decode_nested(buff, lazy, limits, 0, limits.max_values)
+}
+
+fn decode_nested(
+    buff: &[u8],
+    lazy: bool,
+    limits: DecodeLimits,
+    depth: usize,
+    budget: usize,
+) -> Option<DecodeIter> {
+    let (count, size, layout) = read_header(buff)?;
+    if buff.len() < size || size < SOS_HEADER_SIZE || size > limits.max_bytes {
         return None;
     }
-    let count = NativeEndian::read_u32(&buff[..4]) as usize;
-    let size = NativeEndian::read_u32(&buff[4..8]) as usize;
-    if buff.len() < size || size < 8 {
+    if depth > limits.max_depth || count > budget {
         return None;
     }
     Some(DecodeIter {
         count: count,
-        buff: &buff[8..size],
+        buff: &buff[SOS_HEADER_SIZE..size],
         lazy: lazy,
+        position: 0,
+        layout,
+        offset: SOS_HEADER_SIZE,
+        limits,
+        depth,
+        budget,
     })
 }
 
-impl<'a> Iterator for DecodeIter<'a> {
-    type Item = Value<'a>;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.buff.len() < 5 || self.count == 0 {
+impl<'a> DecodeIter<'a> {
+    /// Type and data of the next value, and the buffer after it.
+    fn peek_raw(&self) -> Option<(CType, &'a [u8], &'a [u8])> {
+        if self.count == 0 || self.budget == 0 {
             return None;
         }
-        let mut offset = 1;
-        let val_type = CType::from_u8(self.buff[0])?;
-        let val_length = match val_type {
-            CType::Int32 | CType::UInt32 | CType::Float => 4,
-            CType::Int64 | CType::UInt64 | CType::Double => 8,
-            _ => {
-                offset += 4;
-                NativeEndian::read_u32(&self.buff[1..5]) as usize
+        let skip = match self.layout {
+            Layout::Packed => 0,
+            Layout::Aligned => self.buff.iter().take_while(|&&b| b == 0).count(),
+        };
+        let buff = &self.buff[skip..];
+        let offset = self.offset + skip;
+        let val_type = CType::from_u8(*buff.first()?)?;
+        let (length_at, data) = self.layout.place(val_type, offset);
+        let (data, val_length) = match length_at {
+            None => (data - offset, val_type.fixed_size()?),
+            Some(at) => {
+                let at = at - offset;
+                if buff.len() < at + 4 {
+                    return None;
+                }
+                let val_length = LittleEndian::read_u32(&buff[at..at + 4]) as usize;
+                (data - offset, val_length)
             }
         };
-        if self.buff.len() < val_length + offset {
+        if buff.len() < data || buff.len() - data < val_length {
+            return None;
+        }
+        Some((
+            val_type,
+            &buff[data..data + val_length],
+            &buff[data + val_length..],
+        ))
+    }
+
+    fn advance(&mut self, rest: &'a [u8]) {
+        self.offset += self.buff.len() - rest.len();
+        self.buff = rest;
+        self.count -= 1;
+        self.budget -= 1;
+        self.position += 1;
+    }
+
+    /// Iterator over a buffer embedded in the next value, its values are counted against what is
+    /// left of this iterator's budget.
+    fn nested(&self, buff: &'a [u8]) -> Option<DecodeIter<'a>> {
+        decode_nested(
+            buff,
+            self.lazy,
+            self.limits,
+            self.depth + 1,
+            self.budget - 1,
+        )
+    }
+
+    /// Takes the values `nested` collected out of this iterator's budget, failing if it stopped
+    /// before its last value.
+    fn spend(&mut self, nested: &DecodeIter<'a>) -> Option<()> {
+        if nested.count != 0 {
             return None;
         }
-        let val_data = &self.buff[offset..offset + val_length];
+        // The value holding them is taken by `advance`
+        self.budget = nested.budget + 1;
+        Some(())
+    }
+
+    #[cfg(feature = "alloc")]
+    fn embedded(&mut self, mut iter: DecodeIter<'a>) -> Option<Value<'a>> {
+        if self.lazy {
+            return Some(Value::EmbeddedOut(iter));
+        }
+        let values = iter.by_ref().collect::<Vec<_>>();
+        self.spend(&iter)?;
+        Some(Value::EmbeddedVec(values))
+    }
+
+    /// Without an allocator embedded values are always decoded lazily.
+    #[cfg(not(feature = "alloc"))]
+    fn embedded(&mut self, iter: DecodeIter<'a>) -> Option<Value<'a>> {
+        Some(Value::EmbeddedOut(iter))
+    }
+
+    #[cfg(feature = "alloc")]
+    fn map(&mut self, mut pairs: MapIter<'a>) -> Option<Value<'a>> {
+        if self.lazy {
+            return Some(Value::MapOut(pairs));
+        }
+        let values = pairs.by_ref().collect::<Vec<_>>();
+        self.spend(&pairs.0)?;
+        Some(Value::MapVec(values))
+    }
+
+    /// Like `embedded`, maps are lazy without an allocator.
+    #[cfg(not(feature = "alloc"))]
+    fn map(&mut self, pairs: MapIter<'a>) -> Option<Value<'a>> {
+        Some(Value::MapOut(pairs))
+    }
+
+    /// Borrows the next value from the buffer if it is a `T` that is suitably aligned in memory,
+    /// which `Layout::Aligned` guarantees for a buffer that is 8 byte aligned. Nothing is
+    /// consumed otherwise. Packed arrays can be borrowed with `Array::as_slice`.
+    pub fn next_ref<T: ArrayElement>(&mut self) -> Option<&'a T> {
+        let (val_type, val_data, rest) = self.peek_raw()?;
+        if val_type != T::TYPE.ctype()
+            || cfg!(target_endian = "big")
+            || val_data.as_ptr() as usize % mem::align_of::<T>() != 0
+        {
+            return None;
+        }
+        self.advance(rest);
+        Some(unsafe { &*(val_data.as_ptr() as *const T) })
+    }
+}
+
+impl<'a> Iterator for DecodeIter<'a> {
+    type Item = Value<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (val_type, val_data, rest) = self.peek_raw()?;
+        let val_length = val_data.len();
         let val = match val_type {
             CType::Invalid => return None,
-            CType::Int32 => Value::Int32(NativeEndian::read_i32(&val_data)),
-            CType::UInt32 => Value::UInt32(NativeEndian::read_u32(&val_data)),
-            CType::Int64 => Value::Int64(NativeEndian::read_i64(&val_data)),
-            CType::UInt64 => Value::UInt64(NativeEndian::read_u64(&val_data)),
-            CType::Float => Value::Float(NativeEndian::read_f32(&val_data)),
-            CType::Double => Value::Double(NativeEndian::read_f64(&val_data)),
+            CType::Int32 => Value::Int32(LittleEndian::read_i32(&val_data)),
+            CType::UInt32 => Value::UInt32(LittleEndian::read_u32(&val_data)),
+            CType::Int64 => Value::Int64(LittleEndian::read_i64(&val_data)),
+            CType::UInt64 => Value::UInt64(LittleEndian::read_u64(&val_data)),
+            CType::Float => Value::Float(LittleEndian::read_f32(&val_data)),
+            CType::Double => Value::Double(LittleEndian::read_f64(&val_data)),
+            CType::Bool => match val_data[0] {
+                0 => Value::Bool(false),
+                1 => Value::Bool(true),
+                _ => return None,
+            },
+            CType::Unit => Value::Unit,
+            CType::Int8 => Value::Int8(val_data[0] as i8),
+            CType::UInt8 => Value::UInt8(val_data[0]),
+            CType::Int16 => Value::Int16(LittleEndian::read_i16(&val_data)),
+            CType::UInt16 => Value::UInt16(LittleEndian::read_u16(&val_data)),
             CType::String => Value::String(
                 from_utf8(&val_data[..if val_length == 0 { 0 } else { val_length - 1 }]).ok()?,
             ),
@@ -579,16 +1207,29 @@ impl<'a> Iterator for DecodeIter<'a> {
                 Value::Function(Function { module, name })
             }
             CType::Embedded => {
-                let iter = decode_sos(val_data, self.lazy)?;
-                if self.lazy {
-                    Value::EmbeddedOut(iter)
-                } else {
-                    Value::EmbeddedVec(iter.collect::<Vec<_>>())
+                let iter = self.nested(val_data)?;
+                self.embedded(iter)?
+            }
+            CType::Array => {
+                let (&elem, data) = val_data.split_first()?;
+                let elem = ArrayType::from_u8(elem)?;
+                let data = data.get(self.layout.array_padding(elem)..)?;
+                Value::Array(Array::from_bytes(elem, data)?)
+            }
+            CType::Map => {
+                let iter = self.nested(val_data)?;
+                if iter.count % 2 != 0 {
+                    return None;
                 }
+                self.map(MapIter(iter))?
+            }
+            CType::Fault => {
+                let iter = self.nested(val_data)?;
+                Value::Fault(Fault::decode(iter)?)
             }
+            CType::Extension(id) => Value::Extension(Extension::new(id, val_data)),
         };
-        self.buff = &self.buff[val_length + offset..];
-        self.count -= 1;
+        self.advance(rest);
         Some(val)
     }
 }
@@ -0,0 +1,376 @@
+use super::{
+    EncodedValues, Function, OwnedEncodedValues, OwnedFunction, OwnedValue, ReferencedValues,
+};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::{self, Display};
+use serde::ser::{self, Serialize};
+
+/// Newtype struct name used to smuggle a `Function` through serde.
+pub(crate) const FUNCTION_TOKEN: &str = "$sos::Function";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Message(String),
+    /// The buffer does not contain valid SOS.
+    Decode,
+    /// Ran out of values before the type was complete.
+    Eof,
+    /// Values were left over after the type was complete.
+    TrailingValues,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Message(msg) => f.write_str(msg),
+            Error::Decode => f.write_str("could not decode SOS"),
+            Error::Eof => f.write_str("not enough values"),
+            Error::TrailingValues => f.write_str("too many values"),
+        }
+    }
+}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
+
+impl<'a> Serialize for Function<'a> {
+    fn serialize<S: ser::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_newtype_struct(FUNCTION_TOKEN, &(self.module, self.name))
+    }
+}
+
+impl Serialize for OwnedFunction {
+    fn serialize<S: ser::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_newtype_struct(FUNCTION_TOKEN, &(&self.module, &self.name))
+    }
+}
+
+/// Serializes `value` into encoded SOS. Structs, tuples and sequences become the top level
+/// values, anything else is encoded as a single value.
+pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<OwnedEncodedValues, Error> {
+    let values = to_owned_values(value)?;
+    let borrowed = values.iter().map(|v| v.borrow()).collect::<Vec<_>>();
+    Ok(EncodedValues::from(ReferencedValues(&borrowed[..])).into_owned())
+}
+
+/// Same as `to_vec`, but stops short of encoding.
+pub fn to_owned_values<T: Serialize + ?Sized>(value: &T) -> Result<Vec<OwnedValue>, Error> {
+    match value.serialize(Serializer)? {
+        OwnedValue::Embedded(values) => Ok(values),
+        OwnedValue::Unit => Ok(Vec::new()),
+        value => Ok(vec![value]),
+    }
+}
+
+/// Serializes a type into an `OwnedValue`. Structs, tuples and sequences become `Embedded`
+/// values, `Some` is embedded as `[value]`, enum variants carrying data are embedded as
+/// `[variant, data]`.
+pub struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = OwnedValue;
+    type Error = Error;
+
+    type SerializeSeq = SerializeVec;
+    type SerializeTuple = SerializeVec;
+    type SerializeTupleStruct = SerializeVec;
+    type SerializeTupleVariant = SerializeVariant;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeVec;
+    type SerializeStructVariant = SerializeVariant;
+
+    fn serialize_bool(self, v: bool) -> Result<OwnedValue, Error> {
+        Ok(OwnedValue::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<OwnedValue, Error> {
+        Ok(OwnedValue::Int8(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<OwnedValue, Error> {
+        Ok(OwnedValue::Int16(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<OwnedValue, Error> {
+        Ok(OwnedValue::Int32(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<OwnedValue, Error> {
+        Ok(OwnedValue::Int64(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<OwnedValue, Error> {
+        Ok(OwnedValue::UInt8(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<OwnedValue, Error> {
+        Ok(OwnedValue::UInt16(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<OwnedValue, Error> {
+        Ok(OwnedValue::UInt32(v))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<OwnedValue, Error> {
+        Ok(OwnedValue::UInt64(v))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<OwnedValue, Error> {
+        Ok(OwnedValue::Float(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<OwnedValue, Error> {
+        Ok(OwnedValue::Double(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<OwnedValue, Error> {
+        Ok(OwnedValue::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<OwnedValue, Error> {
+        Ok(OwnedValue::String(String::from(v)))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<OwnedValue, Error> {
+        Ok(OwnedValue::Opaque(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<OwnedValue, Error> {
+        Ok(OwnedValue::Unit)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<OwnedValue, Error> {
+        Ok(OwnedValue::Embedded(vec![value.serialize(Serializer)?]))
+    }
+
+    fn serialize_unit(self) -> Result<OwnedValue, Error> {
+        Ok(OwnedValue::Unit)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<OwnedValue, Error> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<OwnedValue, Error> {
+        Ok(OwnedValue::String(String::from(variant)))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<OwnedValue, Error> {
+        let inner = value.serialize(Serializer)?;
+        if name != FUNCTION_TOKEN {
+            return Ok(inner);
+        }
+        match inner {
+            OwnedValue::Embedded(ref parts) if parts.len() == 2 => match (&parts[0], &parts[1]) {
+                (OwnedValue::String(module), OwnedValue::String(name)) => {
+                    Ok(OwnedValue::Function(OwnedFunction::new(module, name)))
+                }
+                _ => Err(Error::Message(String::from(
+                    "Function must be (module, name)",
+                ))),
+            },
+            _ => Err(Error::Message(String::from(
+                "Function must be (module, name)",
+            ))),
+        }
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<OwnedValue, Error> {
+        Ok(OwnedValue::Embedded(vec![
+            OwnedValue::String(String::from(variant)),
+            value.serialize(Serializer)?,
+        ]))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeVec, Error> {
+        Ok(SerializeVec(Vec::with_capacity(len.unwrap_or(0))))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeVec, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeVec, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant, Error> {
+        Ok(SerializeVariant(variant, Vec::with_capacity(len)))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<SerializeMap, Error> {
+        Ok(SerializeMap(Vec::with_capacity(len.unwrap_or(0)), None))
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeVec, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant, Error> {
+        Ok(SerializeVariant(variant, Vec::with_capacity(len)))
+    }
+}
+
+pub struct SerializeVec(Vec<OwnedValue>);
+
+impl ser::SerializeSeq for SerializeVec {
+    type Ok = OwnedValue;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.0.push(value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<OwnedValue, Error> {
+        Ok(OwnedValue::Embedded(self.0))
+    }
+}
+
+impl ser::SerializeTuple for SerializeVec {
+    type Ok = OwnedValue;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<OwnedValue, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeVec {
+    type Ok = OwnedValue;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<OwnedValue, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+// Field names are not encoded, fields are identified by their position.
+impl ser::SerializeStruct for SerializeVec {
+    type Ok = OwnedValue;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<OwnedValue, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+/// Pairs so far and the key waiting for its value.
+pub struct SerializeMap(Vec<(OwnedValue, OwnedValue)>, Option<OwnedValue>);
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = OwnedValue;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.1 = Some(key.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self
+            .1
+            .take()
+            .ok_or(Error::Message(String::from("Map value without a key")))?;
+        self.0.push((key, value.serialize(Serializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<OwnedValue, Error> {
+        Ok(OwnedValue::Map(self.0))
+    }
+}
+
+pub struct SerializeVariant(&'static str, Vec<OwnedValue>);
+
+impl SerializeVariant {
+    fn finish(self) -> OwnedValue {
+        OwnedValue::Embedded(vec![
+            OwnedValue::String(String::from(self.0)),
+            OwnedValue::Embedded(self.1),
+        ])
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeVariant {
+    type Ok = OwnedValue;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.1.push(value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<OwnedValue, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant {
+    type Ok = OwnedValue;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.1.push(value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<OwnedValue, Error> {
+        Ok(self.finish())
+    }
+}
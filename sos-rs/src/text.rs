@@ -0,0 +1,753 @@
+//! Text notation for SOS values, for logs, tests and typing values in by hand.
+//!
+//! A list of values is written comma separated, each one as:
+//!
+//! * integers and floats with a Rust style type suffix: `42u64`, `-1i8`, `1.5f32`, `NaNf64`.
+//!   Without a suffix an integer is an `i32` and anything else an `f64`
+//! * `true`, `false` and `()`
+//! * strings with Rust style escapes: `"hi\n"`, errors as `error("oops")`
+//! * opaque data as a byte string: `b"\x00\xffab"`
+//! * functions as a module and name pair: `("call", "print")`
+//! * embedded values in braces: `{1i32, "two"}`, maps as `{"key": 1i32}`, the empty map as `{:}`
+//! * packed arrays as `[1i32, 2i32]`, the empty array with its type after it: `[]i32`
+//! * faults as `fault(0x201, ("call", "print"), "Function not found", ())`, that is the code,
+//!   the origin or `()`, the message and the cause or `()`
+//! * extension values as their type id and data: `ext(5, b"\x01\x02")`
+
+#[cfg(feature = "alloc")]
+use super::{
+    ArrayElement, EncodedValues, ErrorCode, IndexedValues, OwnedArray, OwnedEncodedValues,
+    OwnedExtension, OwnedFault, OwnedFunction, OwnedValue, ARRAY_TYPES, EXTENSION_MAX_ID,
+};
+use super::{ArrayType, DecodeIter, Fault, Function, ReferencedValues, Value};
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::fmt::{self, Display, Write};
+#[cfg(feature = "alloc")]
+use core::str::FromStr;
+
+/// How deeply embedded values, maps and fault causes may nest in parsed text.
+const MAX_DEPTH: usize = 64;
+
+fn suffix(elem: ArrayType) -> &'static str {
+    match elem {
+        ArrayType::Int8 => "i8",
+        ArrayType::UInt8 => "u8",
+        ArrayType::Int16 => "i16",
+        ArrayType::UInt16 => "u16",
+        ArrayType::Int32 => "i32",
+        ArrayType::UInt32 => "u32",
+        ArrayType::Int64 => "i64",
+        ArrayType::UInt64 => "u64",
+        ArrayType::Float => "f32",
+        ArrayType::Double => "f64",
+    }
+}
+
+fn write_quoted(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            '\0' => f.write_str("\\0")?,
+            c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+fn write_bytes(f: &mut fmt::Formatter, bytes: &[u8]) -> fmt::Result {
+    f.write_str("b\"")?;
+    for &b in bytes {
+        match b {
+            b'"' => f.write_str("\\\"")?,
+            b'\\' => f.write_str("\\\\")?,
+            b'\n' => f.write_str("\\n")?,
+            b'\r' => f.write_str("\\r")?,
+            b'\t' => f.write_str("\\t")?,
+            b'\0' => f.write_str("\\0")?,
+            0x20...0x7e => f.write_char(b as char)?,
+            b => write!(f, "\\x{:02x}", b)?,
+        }
+    }
+    f.write_char('"')
+}
+
+fn write_function(f: &mut fmt::Formatter, function: &Function) -> fmt::Result {
+    f.write_char('(')?;
+    write_quoted(f, function.module)?;
+    f.write_str(", ")?;
+    write_quoted(f, function.name)?;
+    f.write_char(')')
+}
+
+fn write_fault(f: &mut fmt::Formatter, fault: &Fault) -> fmt::Result {
+    write!(f, "fault({:#x}, ", fault.code.0)?;
+    match fault.origin {
+        Some(ref origin) => write_function(f, origin)?,
+        None => f.write_str("()")?,
+    }
+    f.write_str(", ")?;
+    write_quoted(f, fault.message)?;
+    f.write_str(", ")?;
+    match fault.cause() {
+        Some(ref cause) => write_fault(f, cause)?,
+        None => f.write_str("()")?,
+    }
+    f.write_char(')')
+}
+
+fn write_list<I>(f: &mut fmt::Formatter, values: I) -> fmt::Result
+where
+    I: Iterator,
+    I::Item: Display,
+{
+    for (i, value) in values.enumerate() {
+        if i != 0 {
+            f.write_str(", ")?;
+        }
+        write!(f, "{}", value)?;
+    }
+    Ok(())
+}
+
+fn write_map<I, K, V>(f: &mut fmt::Formatter, pairs: I) -> fmt::Result
+where
+    I: Iterator<Item = (K, V)>,
+    K: Display,
+    V: Display,
+{
+    let mut empty = true;
+    f.write_char('{')?;
+    for (key, value) in pairs {
+        if !empty {
+            f.write_str(", ")?;
+        }
+        write!(f, "{}: {}", key, value)?;
+        empty = false;
+    }
+    f.write_str(if empty { ":}" } else { "}" })
+}
+
+impl<'a> Display for Value<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int32(i) => write!(f, "{}i32", i),
+            Value::UInt32(i) => write!(f, "{}u32", i),
+            Value::Int64(i) => write!(f, "{}i64", i),
+            Value::UInt64(i) => write!(f, "{}u64", i),
+            Value::Float(i) => write!(f, "{:?}f32", i),
+            Value::Double(i) => write!(f, "{:?}f64", i),
+            Value::Bool(i) => write!(f, "{}", i),
+            Value::Unit => f.write_str("()"),
+            Value::Int8(i) => write!(f, "{}i8", i),
+            Value::UInt8(i) => write!(f, "{}u8", i),
+            Value::Int16(i) => write!(f, "{}i16", i),
+            Value::UInt16(i) => write!(f, "{}u16", i),
+            Value::Error(i) => {
+                f.write_str("error(")?;
+                write_quoted(f, i)?;
+                f.write_char(')')
+            }
+            Value::String(i) => write_quoted(f, i),
+            Value::Opaque(i) => write_bytes(f, i),
+            Value::Function(i) => write_function(f, i),
+            Value::EmbeddedOut(i) => write!(f, "{{{}}}", i),
+            Value::EmbeddedIn(i) => write!(f, "{{{}}}", i),
+            #[cfg(feature = "alloc")]
+            Value::EmbeddedVec(i) => {
+                f.write_char('{')?;
+                write_list(f, i.iter())?;
+                f.write_char('}')
+            }
+            Value::Array(i) => {
+                f.write_char('[')?;
+                write_list(f, i.values())?;
+                f.write_char(']')?;
+                if i.is_empty() {
+                    f.write_str(suffix(i.elem_type()))?;
+                }
+                Ok(())
+            }
+            Value::MapOut(i) => write_map(f, i.clone()),
+            Value::MapIn(i) => write_map(f, i.iter().map(|&(ref k, ref v)| (k, v))),
+            #[cfg(feature = "alloc")]
+            Value::MapVec(i) => write_map(f, i.iter().map(|&(ref k, ref v)| (k, v))),
+            Value::Fault(i) => write_fault(f, i),
+            Value::Extension(i) => {
+                write!(f, "ext({}, ", i.type_id())?;
+                write_bytes(f, i.data())?;
+                f.write_char(')')
+            }
+        }
+    }
+}
+
+/// Comma separated, without braces.
+impl<'a> Display for DecodeIter<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_list(f, self.clone())
+    }
+}
+
+/// Comma separated, without braces.
+#[cfg(feature = "alloc")]
+impl<'a> Display for IndexedValues<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_list(f, self.iter())
+    }
+}
+
+/// Comma separated, without braces.
+impl<'a> Display for ReferencedValues<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_list(f, self.0.iter())
+    }
+}
+
+/// Comma separated, without braces.
+#[cfg(feature = "alloc")]
+impl<'a> Display for EncodedValues<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.decode() {
+            Some(values) => values.fmt(f),
+            None => f.write_str("<malformed SOS>"),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Display for OwnedValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.borrow().fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    UnexpectedEnd,
+    UnexpectedChar,
+    /// A number that does not fit its type, or has an unknown suffix.
+    BadNumber,
+    BadEscape,
+    /// An array element that is not a number, or not the same type as the first element.
+    BadArrayElement,
+    /// A fault origin that is not a function or `()`, or a cause that is not a fault or `()`.
+    BadFault,
+    /// Values are nested more than 64 deep.
+    TooDeep,
+}
+
+/// Why and where, as a byte offset into the text, parsing failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub offset: usize,
+}
+
+impl ParseError {
+    fn new(kind: ParseErrorKind, offset: usize) -> Self {
+        ParseError { kind, offset }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} at byte {}", self.kind, self.offset)
+    }
+}
+
+/// Parses a comma separated list of values.
+#[cfg(feature = "alloc")]
+pub fn parse_values(text: &str) -> Result<Vec<OwnedValue>, ParseError> {
+    let mut parser = Parser {
+        text,
+        pos: 0,
+        depth: 0,
+    };
+    let values = parser.list(None)?;
+    Ok(values)
+}
+
+/// Parses a comma separated list of values and encodes them.
+#[cfg(feature = "alloc")]
+pub fn parse_sos(text: &str) -> Result<OwnedEncodedValues, ParseError> {
+    let values = parse_values(text)?;
+    let values = values.iter().map(|v| v.borrow()).collect::<Vec<_>>();
+    Ok(EncodedValues::from(ReferencedValues(&values)).into_owned())
+}
+
+/// Parses a single value.
+#[cfg(feature = "alloc")]
+impl FromStr for OwnedValue {
+    type Err = ParseError;
+
+    fn from_str(text: &str) -> Result<Self, ParseError> {
+        let mut parser = Parser {
+            text,
+            pos: 0,
+            depth: 0,
+        };
+        let value = parser.value()?;
+        match parser.peek() {
+            None => Ok(value),
+            Some(_) => Err(ParseError::new(ParseErrorKind::UnexpectedChar, parser.pos)),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+struct Parser<'t> {
+    text: &'t str,
+    pos: usize,
+    depth: usize,
+}
+
+#[cfg(feature = "alloc")]
+impl<'t> Parser<'t> {
+    fn rest(&self) -> &'t str {
+        &self.text[self.pos..]
+    }
+
+    /// Skips whitespace and returns the next character without consuming it.
+    fn peek(&mut self) -> Option<char> {
+        let rest = self.rest();
+        let trimmed = rest.trim_left();
+        self.pos += rest.len() - trimmed.len();
+        trimmed.chars().next()
+    }
+
+    fn error(&self, kind: ParseErrorKind) -> ParseError {
+        ParseError::new(kind, self.pos)
+    }
+
+    fn unexpected(&self) -> ParseError {
+        if self.pos == self.text.len() {
+            self.error(ParseErrorKind::UnexpectedEnd)
+        } else {
+            self.error(ParseErrorKind::UnexpectedChar)
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    /// Takes a run of characters that can make up a number or keyword.
+    fn word(&mut self) -> &'t str {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.' || c == '-' || c == '+'))
+            .unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    fn nest(&mut self) -> Result<(), ParseError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error(ParseErrorKind::TooDeep));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Values separated by commas, up to `close` or the end of the text.
+    fn list(&mut self, close: Option<char>) -> Result<Vec<OwnedValue>, ParseError> {
+        let mut values = Vec::new();
+        loop {
+            if self.peek() == close {
+                break;
+            }
+            values.push(self.value()?);
+            if !self.eat(',') {
+                break;
+            }
+        }
+        if self.peek() != close {
+            return Err(self.unexpected());
+        }
+        if let Some(close) = close {
+            self.pos += close.len_utf8();
+        }
+        Ok(values)
+    }
+
+    fn value(&mut self) -> Result<OwnedValue, ParseError> {
+        match self.peek() {
+            Some('"') => Ok(OwnedValue::String(self.string()?)),
+            Some('(') => {
+                self.pos += 1;
+                if self.eat(')') {
+                    return Ok(OwnedValue::Unit);
+                }
+                Ok(OwnedValue::Function(self.function_rest()?))
+            }
+            Some('{') => {
+                self.nest()?;
+                self.pos += 1;
+                let value = self.braces()?;
+                self.depth -= 1;
+                Ok(value)
+            }
+            Some('[') => {
+                self.nest()?;
+                self.pos += 1;
+                let value = self.array();
+                self.depth -= 1;
+                value
+            }
+            Some(_) => self.keyword_or_number(),
+            None => Err(self.unexpected()),
+        }
+    }
+
+    /// Module and name of a function, after the opening parenthesis.
+    fn function_rest(&mut self) -> Result<OwnedFunction, ParseError> {
+        let module = self.string()?;
+        self.expect(',')?;
+        let name = self.string()?;
+        self.expect(')')?;
+        Ok(OwnedFunction { module, name })
+    }
+
+    /// An embedded value or a map, after the opening brace.
+    fn braces(&mut self) -> Result<OwnedValue, ParseError> {
+        if self.eat(':') {
+            self.expect('}')?;
+            return Ok(OwnedValue::Map(Vec::new()));
+        }
+        if self.eat('}') {
+            return Ok(OwnedValue::Embedded(Vec::new()));
+        }
+        let first = self.value()?;
+        if !self.eat(':') {
+            let mut values = vec![first];
+            if self.eat(',') {
+                values.extend(self.list(Some('}'))?);
+            } else {
+                self.expect('}')?;
+            }
+            return Ok(OwnedValue::Embedded(values));
+        }
+        let mut pairs = vec![(first, self.value()?)];
+        while self.eat(',') {
+            if self.peek() == Some('}') {
+                break;
+            }
+            let key = self.value()?;
+            self.expect(':')?;
+            pairs.push((key, self.value()?));
+        }
+        self.expect('}')?;
+        Ok(OwnedValue::Map(pairs))
+    }
+
+    /// A packed array, after the opening bracket.
+    fn array(&mut self) -> Result<OwnedValue, ParseError> {
+        let mut elem = None;
+        let mut data = Vec::new();
+        loop {
+            if self.peek() == Some(']') {
+                break;
+            }
+            let start = self.pos;
+            let value = self.value()?;
+            match array_element(&value, &mut data) {
+                Some(t) if elem.is_none() || elem == Some(t) => elem = Some(t),
+                _ => return Err(ParseError::new(ParseErrorKind::BadArrayElement, start)),
+            }
+            if !self.eat(',') {
+                break;
+            }
+        }
+        self.expect(']')?;
+        let elem = match elem {
+            Some(elem) => elem,
+            None => {
+                let start = self.pos;
+                let word = self.word();
+                ARRAY_TYPES
+                    .iter()
+                    .cloned()
+                    .find(|&t| suffix(t) == word)
+                    .ok_or(ParseError::new(ParseErrorKind::BadNumber, start))?
+            }
+        };
+        Ok(OwnedValue::Array(
+            OwnedArray::from_bytes(elem, data).expect("Array elements are whole"),
+        ))
+    }
+
+    fn keyword_or_number(&mut self) -> Result<OwnedValue, ParseError> {
+        let start = self.pos;
+        let word = self.word();
+        match word {
+            "" => Err(ParseError::new(ParseErrorKind::UnexpectedChar, start)),
+            "true" => Ok(OwnedValue::Bool(true)),
+            "false" => Ok(OwnedValue::Bool(false)),
+            "b" if self.rest().starts_with('"') => Ok(OwnedValue::Opaque(self.bytes()?)),
+            "error" => {
+                self.expect('(')?;
+                let message = self.string()?;
+                self.expect(')')?;
+                Ok(OwnedValue::Error(message))
+            }
+            "fault" => {
+                self.nest()?;
+                let fault = self.fault_rest()?;
+                self.depth -= 1;
+                Ok(OwnedValue::Fault(fault))
+            }
+            "ext" => {
+                self.expect('(')?;
+                self.peek();
+                let start = self.pos;
+                let type_id = self
+                    .word()
+                    .parse()
+                    .ok()
+                    .filter(|&id| id <= EXTENSION_MAX_ID);
+                let type_id = type_id.ok_or(ParseError::new(ParseErrorKind::BadNumber, start))?;
+                self.expect(',')?;
+                self.peek();
+                let start = self.pos;
+                if self.word() != "b" || !self.rest().starts_with('"') {
+                    return Err(ParseError::new(ParseErrorKind::UnexpectedChar, start));
+                }
+                let data = self.bytes()?;
+                self.expect(')')?;
+                Ok(OwnedValue::Extension(OwnedExtension::new(type_id, data)))
+            }
+            _ => number(word).ok_or(ParseError::new(ParseErrorKind::BadNumber, start)),
+        }
+    }
+
+    /// The fields of a fault, after the keyword.
+    fn fault_rest(&mut self) -> Result<OwnedFault, ParseError> {
+        self.expect('(')?;
+        self.peek();
+        let start = self.pos;
+        let word = self.word();
+        let code = if word.starts_with("0x") {
+            u32::from_str_radix(&word[2..], 16).ok()
+        } else {
+            word.parse().ok()
+        };
+        let code = code.ok_or(ParseError::new(ParseErrorKind::BadNumber, start))?;
+        self.expect(',')?;
+
+        self.expect('(')?;
+        let origin = if self.eat(')') {
+            None
+        } else {
+            Some(self.function_rest()?)
+        };
+        self.expect(',')?;
+        let message = self.string()?;
+        self.expect(',')?;
+
+        self.peek();
+        let start = self.pos;
+        let cause = match self.value()? {
+            OwnedValue::Unit => None,
+            OwnedValue::Fault(cause) => Some(Box::new(cause)),
+            _ => return Err(ParseError::new(ParseErrorKind::BadFault, start)),
+        };
+        self.expect(')')?;
+        Ok(OwnedFault {
+            code: ErrorCode(code),
+            origin,
+            message,
+            cause,
+        })
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            let c = self.rest().chars().next().ok_or(self.unexpected())?;
+            match c {
+                '"' => {
+                    self.pos += 1;
+                    return Ok(s);
+                }
+                '\\' => {
+                    self.pos += 1;
+                    s.push(self.escape(false)?);
+                }
+                c => {
+                    self.pos += c.len_utf8();
+                    s.push(c);
+                }
+            }
+        }
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, ParseError> {
+        self.expect('"')?;
+        let mut bytes = Vec::new();
+        loop {
+            let c = self.rest().chars().next().ok_or(self.unexpected())?;
+            match c {
+                '"' => {
+                    self.pos += 1;
+                    return Ok(bytes);
+                }
+                '\\' => {
+                    self.pos += 1;
+                    bytes.push(self.escape(true)? as u8);
+                }
+                c if c.is_ascii() => {
+                    self.pos += 1;
+                    bytes.push(c as u8);
+                }
+                _ => return Err(self.error(ParseErrorKind::UnexpectedChar)),
+            }
+        }
+    }
+
+    /// The character for an escape, after the backslash. Byte strings take `\x` escapes instead
+    /// of `\u`, and the character they return is the byte.
+    fn escape(&mut self, byte: bool) -> Result<char, ParseError> {
+        let start = self.pos - 1;
+        let bad = ParseError::new(ParseErrorKind::BadEscape, start);
+        let c = self.rest().chars().next().ok_or(self.unexpected())?;
+        self.pos += c.len_utf8();
+        Ok(match c {
+            '"' => '"',
+            '\'' => '\'',
+            '\\' => '\\',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            '0' => '\0',
+            'x' if byte => {
+                let hex = self.rest().get(..2).ok_or(bad)?;
+                self.pos += 2;
+                u8::from_str_radix(hex, 16).map_err(|_| bad)? as char
+            }
+            'u' if !byte => {
+                let rest = self.rest();
+                let end = rest.find('}').ok_or(bad)?;
+                if !rest.starts_with('{') {
+                    return Err(bad);
+                }
+                self.pos += end + 1;
+                u32::from_str_radix(&rest[1..end], 16)
+                    .ok()
+                    .and_then(::core::char::from_u32)
+                    .ok_or(bad)?
+            }
+            _ => return Err(bad),
+        })
+    }
+}
+
+/// Appends the little endian bytes of a number to `data`, returning its type.
+#[cfg(feature = "alloc")]
+fn array_element(value: &OwnedValue, data: &mut Vec<u8>) -> Option<ArrayType> {
+    let mut buf = [0; 8];
+    let elem = match *value {
+        OwnedValue::Int8(i) => {
+            i.write(&mut buf);
+            i8::TYPE
+        }
+        OwnedValue::UInt8(i) => {
+            i.write(&mut buf);
+            u8::TYPE
+        }
+        OwnedValue::Int16(i) => {
+            i.write(&mut buf);
+            i16::TYPE
+        }
+        OwnedValue::UInt16(i) => {
+            i.write(&mut buf);
+            u16::TYPE
+        }
+        OwnedValue::Int32(i) => {
+            i.write(&mut buf);
+            i32::TYPE
+        }
+        OwnedValue::UInt32(i) => {
+            i.write(&mut buf);
+            u32::TYPE
+        }
+        OwnedValue::Int64(i) => {
+            i.write(&mut buf);
+            i64::TYPE
+        }
+        OwnedValue::UInt64(i) => {
+            i.write(&mut buf);
+            u64::TYPE
+        }
+        OwnedValue::Float(i) => {
+            i.write(&mut buf);
+            f32::TYPE
+        }
+        OwnedValue::Double(i) => {
+            i.write(&mut buf);
+            f64::TYPE
+        }
+        _ => return None,
+    };
+    data.extend_from_slice(&buf[..elem.size()]);
+    Some(elem)
+}
+
+#[cfg(feature = "alloc")]
+fn number(word: &str) -> Option<OwnedValue> {
+    let typed = ARRAY_TYPES
+        .iter()
+        .cloned()
+        .find(|&t| word.len() > suffix(t).len() && word.ends_with(suffix(t)));
+    let elem = match typed {
+        Some(elem) => elem,
+        None if word
+            .trim_left_matches('-')
+            .bytes()
+            .all(|b| b.is_ascii_digit()) =>
+        {
+            ArrayType::Int32
+        }
+        None => ArrayType::Double,
+    };
+    let num = match typed {
+        Some(elem) => &word[..word.len() - suffix(elem).len()],
+        None => word,
+    };
+    Some(match elem {
+        ArrayType::Int8 => OwnedValue::Int8(num.parse().ok()?),
+        ArrayType::UInt8 => OwnedValue::UInt8(num.parse().ok()?),
+        ArrayType::Int16 => OwnedValue::Int16(num.parse().ok()?),
+        ArrayType::UInt16 => OwnedValue::UInt16(num.parse().ok()?),
+        ArrayType::Int32 => OwnedValue::Int32(num.parse().ok()?),
+        ArrayType::UInt32 => OwnedValue::UInt32(num.parse().ok()?),
+        ArrayType::Int64 => OwnedValue::Int64(num.parse().ok()?),
+        ArrayType::UInt64 => OwnedValue::UInt64(num.parse().ok()?),
+        ArrayType::Float => OwnedValue::Float(num.parse().ok()?),
+        ArrayType::Double => OwnedValue::Double(num.parse().ok()?),
+    })
+}
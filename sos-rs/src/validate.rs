@@ -0,0 +1,311 @@
+use super::byteorder::{ByteOrder, LittleEndian};
+use super::{
+    decode_sos_limited, ArrayType, CType, DecodeIter, ErrorCode, Fault, JustError, Layout, Value,
+    SOS, SOS_HEADER_SIZE, SOS_MAGIC, SOS_VERSION,
+};
+use core::fmt::{self, Display};
+use core::ops::{Deref, DerefMut};
+use core::str::from_utf8;
+use core::usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeErrorKind {
+    /// The buffer ends before the header or a value does.
+    Truncated,
+    BadMagic,
+    UnsupportedVersion,
+    UnknownFlags,
+    /// The size in the header is smaller than the header, or does not match the embedding value.
+    BadSize,
+    /// The count in the header does not match the number of values.
+    BadCount,
+    UnknownType,
+    /// A String, Error or Function is not NUL terminated.
+    MissingTerminator,
+    InvalidUtf8,
+    /// A Function does not separate its module and name.
+    MalformedFunction,
+    /// A Bool is neither 0 nor 1.
+    InvalidBool,
+    /// An Array has no element type, one that cannot be packed, or a partial element.
+    BadArray,
+    /// A Map has a key without a value.
+    OddMap,
+    /// A Fault is not a code, an origin, a message and a cause.
+    MalformedFault,
+    /// Padding in an aligned buffer is not zero.
+    BadPadding,
+    /// Buffers are nested deeper than `DecodeLimits::max_depth`.
+    TooDeep,
+    /// There are more values than `DecodeLimits::max_values`.
+    TooManyValues,
+    /// The buffer is larger than `DecodeLimits::max_bytes`.
+    TooLarge,
+}
+
+/// Bounds on the work that decoding a buffer can cause, for input that cannot be trusted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// How many buffers may be nested inside the outermost one, following Embedded, Map and
+    /// Fault values.
+    pub max_depth: usize,
+    /// How many values there may be in total, including the ones in nested buffers.
+    pub max_values: usize,
+    /// Largest size of the outermost buffer, header included.
+    pub max_bytes: usize,
+}
+
+impl DecodeLimits {
+    /// Only what the buffer itself describes bounds decoding.
+    pub const UNLIMITED: DecodeLimits = DecodeLimits {
+        max_depth: usize::MAX,
+        max_values: usize::MAX,
+        max_bytes: usize::MAX,
+    };
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_depth: 64,
+            max_values: 1 << 20,
+            max_bytes: 1 << 26,
+        }
+    }
+}
+
+/// Why and where, as a byte offset into the outermost buffer, validation failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub kind: DecodeErrorKind,
+    pub offset: usize,
+}
+
+impl DecodeError {
+    fn new(kind: DecodeErrorKind, offset: usize) -> Self {
+        DecodeError { kind, offset }
+    }
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} at byte {}", self.kind, self.offset)
+    }
+}
+
+impl<'a> From<DecodeError> for JustError<'a> {
+    fn from(e: DecodeError) -> Self {
+        use self::DecodeErrorKind::*;
+        match e.kind {
+            TooDeep | TooManyValues | TooLarge => {
+                JustError::with_code(ErrorCode::TOO_LARGE, "SOS exceeds decode limits")
+            }
+            _ => JustError::with_code(ErrorCode::MALFORMED, "Malformed SOS"),
+        }
+    }
+}
+
+/// Values that have been checked in full by `validate_sos`, decoding them can no longer fail.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidatedValues<'a>(DecodeIter<'a>);
+
+impl<'a> ValidatedValues<'a> {
+    pub fn into_inner(self) -> DecodeIter<'a> {
+        self.0
+    }
+}
+
+impl<'a> Iterator for ValidatedValues<'a> {
+    type Item = Value<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+impl<'a> Deref for ValidatedValues<'a> {
+    type Target = DecodeIter<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<'a> DerefMut for ValidatedValues<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<'a> SOS for ValidatedValues<'a> {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        self.0.encode(buf)
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.0.encoded_len()
+    }
+}
+
+/// Checks the header, every value and every embedded value of `buf` within the default
+/// `DecodeLimits`. Embedded values are decoded lazily by the returned iterator.
+pub fn validate_sos(buf: &[u8]) -> Result<ValidatedValues, DecodeError> {
+    validate_sos_limited(buf, DecodeLimits::default())
+}
+
+/// Like `validate_sos`, but rejects buffers that exceed `limits`.
+pub fn validate_sos_limited(
+    buf: &[u8],
+    limits: DecodeLimits,
+) -> Result<ValidatedValues, DecodeError> {
+    let mut values = 0;
+    let size = validate_at(buf, 0, 0, &limits, &mut values)?;
+    let iter =
+        decode_sos_limited(&buf[..size], true, limits).expect("Validated SOS failed to decode");
+    Ok(ValidatedValues(iter))
+}
+
+/// Validates the values in `buf`, which begins at `base` in the outermost buffer and is nested
+/// `depth` buffers deep, returning the size from the header. `values` counts the values seen so
+/// far in all buffers.
+fn validate_at(
+    buf: &[u8],
+    base: usize,
+    depth: usize,
+    limits: &DecodeLimits,
+    values: &mut usize,
+) -> Result<usize, DecodeError> {
+    use self::DecodeErrorKind::*;
+
+    if depth > limits.max_depth {
+        return Err(DecodeError::new(TooDeep, base));
+    }
+
+    if buf.len() < SOS_HEADER_SIZE {
+        return Err(DecodeError::new(Truncated, base + buf.len()));
+    }
+    if buf[..2] != SOS_MAGIC {
+        return Err(DecodeError::new(BadMagic, base));
+    }
+    if buf[2] != SOS_VERSION {
+        return Err(DecodeError::new(UnsupportedVersion, base + 2));
+    }
+    let layout = Layout::from_flags(buf[3]).ok_or(DecodeError::new(UnknownFlags, base + 3))?;
+    let count = LittleEndian::read_u32(&buf[4..8]) as usize;
+    let size = LittleEndian::read_u32(&buf[8..12]) as usize;
+    if size < SOS_HEADER_SIZE {
+        return Err(DecodeError::new(BadSize, base + 8));
+    }
+    if size > buf.len() {
+        return Err(DecodeError::new(Truncated, base + buf.len()));
+    }
+    if size > limits.max_bytes {
+        return Err(DecodeError::new(TooLarge, base + 8));
+    }
+
+    let buf = &buf[..size];
+    let mut offset = SOS_HEADER_SIZE;
+    let mut found = 0;
+    while offset < size {
+        if layout == Layout::Aligned && buf[offset] == 0 {
+            offset += 1;
+            continue;
+        }
+        let start = offset;
+        *values += 1;
+        if *values > limits.max_values {
+            return Err(DecodeError::new(TooManyValues, base + start));
+        }
+        let val_type = match CType::from_u8(buf[offset]) {
+            Some(CType::Invalid) | None => return Err(DecodeError::new(UnknownType, base + start)),
+            Some(t) => t,
+        };
+        let (length_at, data_at) = layout.place(val_type, offset);
+        if size < data_at {
+            return Err(DecodeError::new(Truncated, base + size));
+        }
+        for at in offset + 1..data_at {
+            let in_length = length_at.map_or(false, |l| at >= l && at < l + 4);
+            if !in_length && buf[at] != 0 {
+                return Err(DecodeError::new(BadPadding, base + at));
+            }
+        }
+        offset = data_at;
+        let length = match length_at {
+            None => val_type.fixed_size().unwrap(),
+            Some(at) => LittleEndian::read_u32(&buf[at..at + 4]) as usize,
+        };
+        if size - offset < length {
+            return Err(DecodeError::new(Truncated, base + size));
+        }
+        let data = &buf[offset..offset + length];
+        match val_type {
+            CType::String | CType::Error | CType::Function => {
+                if data.last() != Some(&0) {
+                    return Err(DecodeError::new(MissingTerminator, base + start));
+                }
+                let text = &data[..length - 1];
+                // A Function is the module and the name, each NUL terminated
+                let split = if val_type == CType::Function {
+                    text.iter()
+                        .position(|&x| x == 0)
+                        .ok_or(DecodeError::new(MalformedFunction, base + start))?
+                } else {
+                    text.len()
+                };
+                if let Err(e) = from_utf8(&text[..split]) {
+                    return Err(DecodeError::new(
+                        InvalidUtf8,
+                        base + offset + e.valid_up_to(),
+                    ));
+                }
+                if split < text.len() {
+                    if let Err(e) = from_utf8(&text[split + 1..]) {
+                        let at = offset + split + 1 + e.valid_up_to();
+                        return Err(DecodeError::new(InvalidUtf8, base + at));
+                    }
+                }
+            }
+            CType::Embedded | CType::Map | CType::Fault => {
+                if validate_at(data, base + offset, depth + 1, limits, values)? != length {
+                    return Err(DecodeError::new(BadSize, base + offset + 8));
+                }
+                if val_type == CType::Map && LittleEndian::read_u32(&data[4..8]) % 2 != 0 {
+                    return Err(DecodeError::new(OddMap, base + offset + 4));
+                }
+                // The cause was validated along with the rest of data, within the limits
+                if val_type == CType::Fault
+                    && decode_sos_limited(data, true, DecodeLimits::UNLIMITED)
+                        .and_then(Fault::decode)
+                        .is_none()
+                {
+                    return Err(DecodeError::new(MalformedFault, base + start));
+                }
+            }
+            CType::Bool => {
+                if data[0] > 1 {
+                    return Err(DecodeError::new(InvalidBool, base + offset));
+                }
+            }
+            CType::Array => {
+                let elem = data.first().and_then(|&elem| ArrayType::from_u8(elem));
+                let elem = elem.ok_or(DecodeError::new(BadArray, base + start))?;
+                let padding = layout.array_padding(elem);
+                if data.len() < 1 + padding || (data.len() - 1 - padding) % elem.size() != 0 {
+                    return Err(DecodeError::new(BadArray, base + start));
+                }
+                if let Some(at) = data[1..1 + padding].iter().position(|&b| b != 0) {
+                    return Err(DecodeError::new(BadPadding, base + offset + 1 + at));
+                }
+            }
+            _ => (),
+        }
+        offset += length;
+        found += 1;
+    }
+
+    if found != count {
+        return Err(DecodeError::new(BadCount, base + 4));
+    }
+    Ok(size)
+}
@@ -0,0 +1,88 @@
+extern crate sos;
+#[macro_use]
+extern crate sos_derive;
+
+use sos::*;
+
+#[derive(SOS, Debug, PartialEq)]
+struct Print<'a> {
+    function: Function<'a>,
+    message: &'a str,
+    times: u64,
+}
+
+#[derive(SOS, Debug, PartialEq)]
+struct Pair(i32, f64);
+
+#[derive(SOS, Debug, PartialEq)]
+struct Nothing;
+
+fn encode<T: SOS>(values: &T) -> Vec<u8> {
+    let mut buf = vec![0; values.encoded_len()];
+    let len = values.encode(&mut buf);
+    assert_eq!(len, buf.len());
+    buf
+}
+
+#[test]
+fn derive_roundtrip() {
+    let print = Print {
+        function: Function {
+            module: "call",
+            name: "print",
+        },
+        message: "hi",
+        times: 3,
+    };
+    let buf = encode(&print);
+    assert_eq!(buf, encode(&sos!(("call", "print"), "hi", 3u64)));
+    let decoded: Print = decode_sos(&buf, true).unwrap().extract().unwrap();
+    assert_eq!(print, decoded);
+
+    let buf = encode(&Pair(-1, 2.5));
+    assert_eq!(
+        decode_sos(&buf, true).unwrap().extract::<Pair>(),
+        Ok(Pair(-1, 2.5))
+    );
+
+    let buf = encode(&Nothing);
+    assert_eq!(
+        decode_sos(&buf, true).unwrap().extract::<Nothing>(),
+        Ok(Nothing)
+    );
+}
+
+#[test]
+fn extract_tuple() {
+    let buf = encode(&sos!(("call", "print"), "hi", 3u64, "rest"));
+    let mut iter = decode_sos(&buf, true).unwrap();
+    let (function, message, times): (Function, &str, u64) = iter.extract().unwrap();
+    assert_eq!(function.name, "print");
+    assert_eq!(message, "hi");
+    assert_eq!(times, 3);
+    assert_eq!(iter.next_as::<&str>(), Ok("rest"));
+}
+
+#[test]
+fn extract_errors() {
+    let buf = encode(&sos!(("call", "print"), "hi"));
+    let mut iter = decode_sos(&buf, true).unwrap();
+    let err = iter.extract::<(Function, u64)>().unwrap_err();
+    assert_eq!(
+        err,
+        ArgError {
+            index: 1,
+            expected: "u64",
+            found: Some("&str"),
+        }
+    );
+    assert_eq!(format!("{}", err), "argument 1: expected u64, found &str");
+    let err = JustError::from(err);
+    assert_eq!(err.fault().code, ErrorCode::BAD_ARGUMENTS);
+    assert_eq!(err.fault().message, "argument 1: expected u64, found &str");
+
+    let mut iter = decode_sos(&buf, true).unwrap();
+    let err = iter.extract::<Print>().unwrap_err();
+    assert_eq!(err.index, 2);
+    assert_eq!(err.found, None);
+}
@@ -0,0 +1,119 @@
+extern crate sos;
+use sos::*;
+
+fn encode_with(buf: &mut [u8]) -> Result<usize, EncodeError> {
+    encode_with_layout(buf, Layout::Packed)
+}
+
+fn encode_with_layout(buf: &mut [u8], layout: Layout) -> Result<usize, EncodeError> {
+    let mut enc = Encoder::with_layout(buf, layout)?;
+    enc.push(("call", "print"))?;
+    enc.push(7u64)?;
+    enc.open()?;
+    enc.push("inner")?;
+    enc.open()?;
+    enc.close()?;
+    enc.close()?;
+    enc.push_embedded(&sos!["hello", -1i32])?;
+    enc.finish()
+}
+
+fn expected() -> Vec<u8> {
+    expected_layout(Layout::Packed)
+}
+
+fn expected_layout(layout: Layout) -> Vec<u8> {
+    // Values added with push_embedded keep the layout they were encoded with
+    let tail = sos!["hello", -1i32];
+    let mut tail_buf = vec![0; tail.encoded_len()];
+    tail.encode(&mut tail_buf);
+
+    let inner = [
+        Value::String("inner"),
+        Value::EmbeddedIn(ReferencedValues(&[])),
+    ];
+    let values = [
+        ("call", "print").into(),
+        7u64.into(),
+        Value::EmbeddedIn(ReferencedValues(&inner)),
+        Value::EmbeddedOut(decode_sos(&tail_buf, true).unwrap()),
+    ];
+    let mut buf;
+    match layout {
+        Layout::Packed => {
+            buf = vec![0; ReferencedValues(&values).encoded_len()];
+            ReferencedValues(&values).encode(&mut buf);
+        }
+        Layout::Aligned => {
+            buf = vec![0; AlignedValues(&values).encoded_len()];
+            AlignedValues(&values).encode(&mut buf);
+        }
+    }
+    buf
+}
+
+#[test]
+fn matches_encode() {
+    let expected = expected();
+    let mut buf = vec![0; 4096];
+    let len = encode_with(&mut buf).unwrap();
+    assert_eq!(&buf[..len], &expected[..]);
+    assert_eq!(validate_sos(&buf[..len]).unwrap().count(), 4);
+}
+
+#[test]
+fn aligned_matches_encode() {
+    let expected = expected_layout(Layout::Aligned);
+    let mut buf = vec![0; 4096];
+    let len = encode_with_layout(&mut buf, Layout::Aligned).unwrap();
+    assert_eq!(&buf[..len], &expected[..]);
+    assert_eq!(buf[3], SOS_FLAG_ALIGNED);
+    assert_eq!(validate_sos(&buf[..len]).unwrap().count(), 4);
+    assert_ne!(expected, expected_layout(Layout::Packed));
+}
+
+#[test]
+fn no_space() {
+    let len = expected().len();
+    for size in 0..len {
+        let mut buf = vec![0; size];
+        assert_eq!(encode_with(&mut buf), Err(EncodeError::NoSpace));
+    }
+    let mut buf = vec![0; len];
+    assert_eq!(encode_with(&mut buf), Ok(len));
+}
+
+#[test]
+fn failed_push_leaves_encoder_usable() {
+    let mut buf = [0; 32];
+    let mut enc = Encoder::new(&mut buf).unwrap();
+    enc.push(1u32).unwrap();
+    assert_eq!(
+        enc.push("this string is far too long"),
+        Err(EncodeError::NoSpace)
+    );
+    enc.push("short").unwrap();
+    let len = enc.finish().unwrap();
+    let values = decode_sos(&buf[..len], false).unwrap().collect::<Vec<_>>();
+    assert_eq!(values, vec![Value::UInt32(1), Value::String("short")]);
+}
+
+#[test]
+fn nesting_errors() {
+    let mut buf = [0; 4096];
+    let mut enc = Encoder::new(&mut buf).unwrap();
+    assert_eq!(enc.close(), Err(EncodeError::NotOpen));
+    for _ in 0..ENCODER_MAX_DEPTH {
+        enc.open().unwrap();
+    }
+    assert_eq!(enc.open(), Err(EncodeError::TooDeep));
+    enc.close().unwrap();
+    assert_eq!(enc.finish(), Err(EncodeError::Unclosed));
+
+    // Misusing the encoder is not the values being too large
+    let code = |e: EncodeError| JustError::from(e).fault().code;
+    assert_eq!(code(EncodeError::NoSpace), ErrorCode::TOO_LARGE);
+    assert_eq!(code(EncodeError::TooDeep), ErrorCode::MALFORMED);
+    assert_eq!(code(EncodeError::NotOpen), ErrorCode::MALFORMED);
+    assert_eq!(code(EncodeError::Unclosed), ErrorCode::MALFORMED);
+}
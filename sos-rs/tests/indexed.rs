@@ -0,0 +1,83 @@
+extern crate sos;
+use sos::*;
+
+fn encode_vec<S: SOS>(values: &S) -> Vec<u8> {
+    let mut buf = vec![0; values.encoded_len()];
+    values.encode(&mut buf[..]);
+    buf
+}
+
+#[test]
+fn random_access() {
+    let buf = encode_vec(&sos![("call", "print"), "hello", 7u64, -1i8, 2.5f64]);
+    let values = validate_sos(&buf).unwrap().into_inner().indexed();
+    assert_eq!(values.len(), 5);
+
+    // Later values can be read first, and earlier ones again after them
+    assert_eq!(values.get(3), Some(Value::Int8(-1)));
+    assert_eq!(values.get(1), Some(Value::String("hello")));
+    assert_eq!(values.get(4), Some(Value::Double(2.5)));
+    assert_eq!(values.get(5), None);
+    assert_eq!(values.get_as::<u64>(2), Ok(7));
+    assert_eq!(
+        values.get_as::<u64>(1),
+        Err(ArgError {
+            index: 1,
+            expected: "u64",
+            found: Some("&str"),
+        })
+    );
+    assert_eq!(values.get_as::<u64>(9).unwrap_err().found, None);
+    assert_eq!(values.iter().count(), 5);
+}
+
+#[test]
+fn slice() {
+    for &aligned in &[false, true] {
+        let values = [
+            ("call", "print").into(),
+            Value::Int8(1),
+            7u64.into(),
+            "text".into(),
+            Value::Bool(true),
+        ];
+        let buf = if aligned {
+            encode_vec(&AlignedValues(&values))
+        } else {
+            encode_vec(&ReferencedValues(&values))
+        };
+        let indexed = decode_sos(&buf, true).unwrap().indexed();
+
+        // Everything after the function, passed on without re-encoding
+        let args = indexed.slice(1..indexed.len()).unwrap();
+        assert_eq!(args.len(), 4);
+        assert_eq!(args.get(1), Some(Value::UInt64(7)));
+        assert_eq!(args.get_as::<bool>(3), Ok(true));
+        assert_eq!(args.get_as::<bool>(0).unwrap_err().index, 1);
+        assert_eq!(args.to_string(), r#"1i8, 7u64, "text", true"#);
+
+        let copy = encode_vec(&args);
+        let reread = validate_sos(&copy).unwrap();
+        assert_eq!(reread.layout(), indexed.iter().layout());
+        assert_eq!(reread.collect::<Vec<_>>(), values[1..].to_vec());
+
+        let middle = args.slice(1..3).unwrap();
+        assert_eq!(middle.to_string(), r#"7u64, "text""#);
+        assert!(validate_sos(&encode_vec(&middle)).is_ok());
+        assert!(args.slice(2..2).unwrap().is_empty());
+        assert_eq!(args.slice(3..5), None);
+    }
+}
+
+#[test]
+fn malformed() {
+    let mut buf = encode_vec(&sos![1i32, 2i32, 3i32]);
+    // Corrupt the tag of the second value
+    buf[SOS_HEADER_SIZE + 5] = 0x7f;
+    let values = decode_sos(&buf, true).unwrap().indexed();
+    assert_eq!(values.len(), 3);
+    assert_eq!(values.get(0), Some(Value::Int32(1)));
+    assert_eq!(values.get(2), None);
+    assert_eq!(values.slice(0..2), None);
+    assert!(values.slice(0..1).is_some());
+}
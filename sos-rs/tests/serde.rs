@@ -0,0 +1,153 @@
+#![cfg(all(feature = "serde", feature = "alloc"))]
+#[macro_use]
+extern crate serde_derive;
+extern crate sos;
+
+use sos::*;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Point {
+    x: f64,
+    y: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+enum Shape {
+    Empty,
+    Circle(Point, f64),
+    Polygon { points: Vec<Point> },
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Call<'a> {
+    #[serde(borrow)]
+    function: Function<'a>,
+    name: &'a str,
+    data: Vec<u8>,
+    flag: bool,
+    small: u8,
+    optional: Option<Point>,
+    shapes: Vec<Shape>,
+}
+
+#[test]
+fn struct_roundtrip() {
+    let call = Call {
+        function: Function {
+            module: "call",
+            name: "print",
+        },
+        name: "hello",
+        data: vec![1, 2, 3],
+        flag: true,
+        small: 200,
+        optional: Some(Point { x: 1.0, y: 2.0 }),
+        shapes: vec![
+            Shape::Empty,
+            Shape::Circle(Point { x: 0.0, y: 0.0 }, 5.0),
+            Shape::Polygon {
+                points: vec![Point { x: 1.0, y: 1.0 }],
+            },
+        ],
+    };
+    let buf = to_vec(&call).unwrap();
+    let decoded: Call = from_slice(&buf).unwrap();
+    assert_eq!(call, decoded);
+}
+
+#[test]
+fn matches_sos_macro() {
+    let buf = to_vec(&(
+        Function {
+            module: "host",
+            name: "hello",
+        },
+        "Hello",
+        3u64,
+    ))
+    .unwrap();
+    let args = sos!(("host", "hello"), "Hello", 3u64);
+    let mut expected = vec![0; args.encoded_len()];
+    args.encode(&mut expected);
+    assert_eq!(buf, expected);
+}
+
+#[test]
+fn top_level_values() {
+    let buf = EncodedValues::from(sos!(("host", "hello"), "Hello", 3u64)).into_owned();
+    let (function, string, num): (Function, &str, u64) = from_slice(&buf).unwrap();
+    assert_eq!(function.module, "host");
+    assert_eq!(string, "Hello");
+    assert_eq!(num, 3);
+
+    let opaque = EncodedValues::from(sos!(&[1u8, 2, 3][..])).into_owned();
+    assert_eq!(from_slice::<&[u8]>(&opaque), Ok(&[1u8, 2, 3][..]));
+
+    assert_eq!(
+        from_slice::<(Function, &str)>(&buf),
+        Err(SerdeError::TrailingValues)
+    );
+    assert!(from_slice::<(Function, &str, u64, u64)>(&buf).is_err());
+    assert!(from_slice::<(Function, u64, u64)>(&buf).is_err());
+}
+
+#[test]
+fn single_value() {
+    let buf = to_vec(&42u64).unwrap();
+    assert_eq!(from_slice::<u64>(&buf), Ok(42));
+    assert_eq!(from_slice::<Option<u64>>(&buf), Ok(Some(42)));
+    let buf = to_vec(&Option::None::<u64>).unwrap();
+    assert_eq!(from_slice::<Option<u64>>(&buf), Ok(None));
+}
+
+#[test]
+fn nested_options() {
+    let buf = to_vec(&Some(())).unwrap();
+    assert_eq!(from_slice::<Option<()>>(&buf), Ok(Some(())));
+    let buf = to_vec(&Option::None::<()>).unwrap();
+    assert_eq!(from_slice::<Option<()>>(&buf), Ok(None));
+
+    for value in &[None, Some(None), Some(Some(7u64))] {
+        let buf = to_vec(value).unwrap();
+        assert_eq!(from_slice::<Option<Option<u64>>>(&buf), Ok(*value));
+        let buf = to_vec(&(1u8, value)).unwrap();
+        assert_eq!(
+            from_slice::<(u8, Option<Option<u64>>)>(&buf),
+            Ok((1, *value))
+        );
+    }
+}
+
+#[test]
+fn new_types() {
+    use std::collections::BTreeMap;
+
+    let buf = to_vec(&(true, 1i8, 2u8, 3i16, 4u16, ())).unwrap();
+    let values = decode_sos(&buf, false).unwrap().collect::<Vec<_>>();
+    assert_eq!(
+        values,
+        vec![
+            Value::Bool(true),
+            Value::Int8(1),
+            Value::UInt8(2),
+            Value::Int16(3),
+            Value::UInt16(4),
+            Value::Unit,
+        ]
+    );
+
+    let mut map = BTreeMap::new();
+    map.insert("one", 1u64);
+    map.insert("two", 2u64);
+    let buf = to_vec(&map).unwrap();
+    match decode_sos(&buf, true).unwrap().next() {
+        Some(Value::MapOut(pairs)) => assert_eq!(pairs.len(), 2),
+        other => panic!("expected a map, got {:?}", other),
+    }
+    assert_eq!(from_slice::<BTreeMap<&str, u64>>(&buf), Ok(map));
+
+    let doubles = [1.0f64, 2.0, 3.0];
+    let buf = EncodedValues::from(sos!(Array::new(&doubles[..]))).into_owned();
+    let (decoded,): (Vec<f64>,) = from_slice(&buf).unwrap();
+    assert_eq!(decoded, doubles.to_vec());
+}
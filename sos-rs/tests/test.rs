@@ -2,10 +2,12 @@
 #![plugin(quickcheck_macros)]
 #![feature(custom_attribute)]
 //#[macro_use]
+extern crate byteorder;
 extern crate quickcheck;
 extern crate rand;
 extern crate rand_xorshift;
 extern crate sos;
+use byteorder::{ByteOrder, LittleEndian};
 use quickcheck::{Arbitrary, Gen};
 use rand::distributions::{Alphanumeric, Standard};
 use rand::prelude::*;
@@ -29,8 +31,18 @@ enum RngType {
     Opaque,
     Function,
     Embedded,
+    Bool,
+    Unit,
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Array,
+    Map,
+    Fault,
+    Extension,
 }
-static TYPES: [RngType; 11] = [
+static TYPES: [RngType; 21] = [
     RngType::Int32,
     RngType::UInt32,
     RngType::Int64,
@@ -42,6 +54,16 @@ static TYPES: [RngType; 11] = [
     RngType::Opaque,
     RngType::Function,
     RngType::Embedded,
+    RngType::Bool,
+    RngType::Unit,
+    RngType::Int8,
+    RngType::UInt8,
+    RngType::Int16,
+    RngType::UInt16,
+    RngType::Array,
+    RngType::Map,
+    RngType::Fault,
+    RngType::Extension,
 ];
 
 #[derive(Clone)]
@@ -83,7 +105,44 @@ fn gen_rand_type<R: Rng>(t: RngType, rng: &mut R) -> OwnedValue {
             name: gen_rand_string(rng),
         }),
         RngType::Embedded => OwnedValue::Embedded(gen_rand_sos(MAX_EMBEDDED_SIZE, rng)),
+        RngType::Bool => OwnedValue::Bool(rng.gen()),
+        RngType::Unit => OwnedValue::Unit,
+        RngType::Int8 => OwnedValue::Int8(rng.gen()),
+        RngType::UInt8 => OwnedValue::UInt8(rng.gen()),
+        RngType::Int16 => OwnedValue::Int16(rng.gen()),
+        RngType::UInt16 => OwnedValue::UInt16(rng.gen()),
+        RngType::Array => {
+            let elem = *rng.choose(&ARRAY_TYPES[..]).unwrap();
+            let length = (rng.gen::<usize>() % MAX_LENGTH) * elem.size();
+            let data = rng.sample_iter(&Standard).take(length).collect();
+            OwnedValue::Array(OwnedArray::from_bytes(elem, data).unwrap())
+        }
+        RngType::Map => {
+            let keys = gen_rand_sos(MAX_EMBEDDED_SIZE, rng);
+            let values = gen_rand_sos(MAX_EMBEDDED_SIZE, rng);
+            OwnedValue::Map(keys.into_iter().zip(values).collect())
+        }
+        RngType::Fault => OwnedValue::Fault(gen_rand_fault(rng)),
+        RngType::Extension => {
+            let length = rng.gen::<usize>() % MAX_LENGTH;
+            let data = rng.sample_iter(&Standard).take(length).collect();
+            OwnedValue::Extension(OwnedExtension::new(rng.gen_range(0, 0x80), data))
+        }
+    }
+}
+
+fn gen_rand_fault<R: Rng>(rng: &mut R) -> OwnedFault {
+    let mut fault = OwnedFault::new(ErrorCode(rng.gen()), &gen_rand_string(rng));
+    if rng.gen() {
+        fault.origin = Some(OwnedFunction::new(
+            &gen_rand_string(rng),
+            &gen_rand_string(rng),
+        ));
     }
+    if rng.gen_range(0, 3) == 0 {
+        fault.cause = Some(Box::new(gen_rand_fault(rng)));
+    }
+    fault
 }
 
 fn gen_rand_sos<R: Rng>(num_values: usize, rng: &mut R) -> Vec<OwnedValue> {
@@ -107,7 +166,7 @@ fn encode_decode() {
     let rvals = ReferencedValues(&vals);
     let len = rvals.encode(&mut buf[..]);
     println!("Encoded {:?}", &buf[..len]);
-    let decoded = decode_sos(&buf[..len], false).collect::<Vec<_>>();
+    let decoded = decode_sos(&buf[..len], false).unwrap().collect::<Vec<_>>();
     println!("{:?}", decoded);
     assert_eq!(rvals, ReferencedValues(&decoded[..]))
 }
@@ -125,7 +184,7 @@ fn rand_encode_decode() {
         buf.set_len(length);
     }
     refvals.encode(&mut buf[..]);
-    let decoded = decode_sos(&buf, false).collect::<Vec<_>>();
+    let decoded = decode_sos(&buf, false).unwrap().collect::<Vec<_>>();
     println!("Decoded {:?}", decoded);
     assert_eq!(&rvals[..], &decoded[..]);
 }
@@ -141,9 +200,540 @@ fn encode_decode_identity(vals: Vec<RngValue>) -> bool {
         buf.set_len(length);
     }
     refvals.encode(&mut buf[..]);
-    let decoded = decode_sos(&buf, false).collect::<Vec<_>>();
+    let decoded = decode_sos(&buf, false).unwrap().collect::<Vec<_>>();
     if &rvals[..] != &decoded[..] {
         println!("Decoded {:?}\nArgs {:?}", decoded, rvals);
     }
     &rvals[..] == &decoded[..]
 }
+
+#[test]
+fn little_endian_header() {
+    let mut buf = [0; 100];
+    let len = ReferencedValues(&[Value::UInt32(0x01020304)]).encode(&mut buf[..]);
+    assert_eq!(&buf[..4], &[b'S', b'O', SOS_VERSION, 0]);
+    assert_eq!(&buf[4..8], &[1, 0, 0, 0]);
+    assert_eq!(&buf[8..12], &[len as u8, 0, 0, 0]);
+    assert_eq!(&buf[SOS_HEADER_SIZE + 1..len], &[4, 3, 2, 1]);
+}
+
+#[test]
+fn reject_unknown_version() {
+    let mut buf = [0; 100];
+    let len = ReferencedValues(&[Value::String("hello")]).encode(&mut buf[..]);
+    assert!(decode_sos(&buf[..len], false).is_some());
+    buf[2] = SOS_VERSION + 1;
+    assert!(decode_sos(&buf[..len], false).is_none());
+    buf[2] = SOS_VERSION;
+    buf[0] = 0;
+    assert!(decode_sos(&buf[..len], false).is_none());
+}
+
+fn encode_vec(vals: &[Value]) -> Vec<u8> {
+    let refvals = ReferencedValues(vals);
+    let mut buf = vec![0; refvals.encoded_len()];
+    refvals.encode(&mut buf[..]);
+    buf
+}
+
+#[test]
+fn validate_errors() {
+    let inner = [Value::String("inner")];
+    let buf = encode_vec(&[
+        Value::UInt64(1),
+        Value::String("hello"),
+        Value::EmbeddedIn(ReferencedValues(&inner)),
+    ]);
+    assert!(validate_sos(&buf).is_ok());
+
+    // Truncated anywhere
+    for len in 0..buf.len() {
+        let err = validate_sos(&buf[..len]).unwrap_err();
+        assert_eq!(err.kind, DecodeErrorKind::Truncated);
+    }
+
+    // Count claims more values than there are
+    let mut bad = buf.clone();
+    bad[4] = 4;
+    assert_eq!(
+        validate_sos(&bad),
+        Err(DecodeError {
+            kind: DecodeErrorKind::BadCount,
+            offset: 4,
+        })
+    );
+
+    // Invalid UTF-8 in "hello"
+    let hello = SOS_HEADER_SIZE + 9 + 5;
+    let mut bad = buf.clone();
+    bad[hello + 1] = 0xFF;
+    assert_eq!(
+        validate_sos(&bad),
+        Err(DecodeError {
+            kind: DecodeErrorKind::InvalidUtf8,
+            offset: hello + 1,
+        })
+    );
+
+    // Missing NUL terminator on "hello"
+    let mut bad = buf.clone();
+    bad[hello + 5] = b'!';
+    assert_eq!(
+        validate_sos(&bad).unwrap_err().kind,
+        DecodeErrorKind::MissingTerminator
+    );
+
+    // Corrupt version of the embedded value is reported at its offset
+    let embedded = hello + 6 + 5;
+    let mut bad = buf.clone();
+    bad[embedded + 2] = 0xFF;
+    assert_eq!(
+        validate_sos(&bad),
+        Err(DecodeError {
+            kind: DecodeErrorKind::UnsupportedVersion,
+            offset: embedded + 2,
+        })
+    );
+
+    // Unknown type tag, below the extension range
+    let mut bad = buf.clone();
+    bad[SOS_HEADER_SIZE] = SOS_EXTENSION_TAG - 1;
+    assert_eq!(
+        validate_sos(&bad).unwrap_err().kind,
+        DecodeErrorKind::UnknownType
+    );
+}
+
+#[quickcheck]
+fn validate_identity(vals: Vec<RngValue>) -> bool {
+    let rvals = vals.iter().map(|v| v.0.borrow()).collect::<Vec<Value>>();
+    let buf = encode_vec(&rvals[..]);
+    let validated = validate_sos(&buf).map(|v| v.count());
+    validated == Ok(rvals.len())
+}
+
+#[quickcheck]
+fn text_identity(vals: Vec<RngValue>) -> bool {
+    let rvals = vals.iter().map(|v| v.0.borrow()).collect::<Vec<Value>>();
+    let text = ReferencedValues(&rvals[..]).to_string();
+    let parsed = parse_values(&text).unwrap();
+    let reparsed = parsed.iter().map(|v| v.borrow()).collect::<Vec<Value>>();
+    // Compared as text, as NaNs are never equal
+    ReferencedValues(&reparsed[..]).to_string() == text
+}
+
+#[quickcheck]
+fn aligned_identity(vals: Vec<RngValue>) -> bool {
+    let rvals = vals.iter().map(|v| v.0.borrow()).collect::<Vec<Value>>();
+    let aligned = AlignedValues(&rvals[..]);
+    let mut buf = vec![0; aligned.encoded_len()];
+    aligned.encode(&mut buf[..]);
+    if validate_sos(&buf).map(|v| v.count()) != Ok(rvals.len()) {
+        return false;
+    }
+    let decoded = decode_sos(&buf, false).unwrap().collect::<Vec<_>>();
+
+    // What is left of a partly read buffer stays aligned when it is copied
+    let mut iter = decode_sos(&buf, true).unwrap();
+    iter.next();
+    let mut rest = vec![0; iter.encoded_len()];
+    iter.encode(&mut rest[..]);
+    let rest_valid = validate_sos(&rest).map(|v| v.count()) == Ok(rvals.len().saturating_sub(1));
+
+    &rvals[..] == &decoded[..] && rest_valid
+}
+
+/// A buffer that is 8 byte aligned in memory.
+fn aligned_buf(len: usize) -> Vec<u64> {
+    vec![0; len / 8 + 1]
+}
+
+#[test]
+fn aligned_zero_copy() {
+    let doubles = [0.5f64, 1.5, 2.5];
+    let values = [
+        Value::UInt8(7),
+        Value::UInt64(0x0102030405060708),
+        Value::String("odd"),
+        Value::Double(-1.0),
+        Array::new(&doubles[..]).into(),
+    ];
+    let aligned = AlignedValues(&values);
+    let mut words = aligned_buf(aligned.encoded_len());
+    let buf = unsafe {
+        std::slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, aligned.encoded_len())
+    };
+    aligned.encode(buf);
+
+    let mut iter = validate_sos(buf).unwrap();
+    assert_eq!(iter.layout(), Layout::Aligned);
+    assert_eq!(iter.next_ref::<u8>(), Some(&7));
+    // A type mismatch leaves the value in place
+    assert_eq!(iter.next_ref::<i64>(), None);
+    assert_eq!(iter.next_ref::<u64>(), Some(&0x0102030405060708));
+    assert_eq!(iter.next(), Some(Value::String("odd")));
+    assert_eq!(iter.next_ref::<f64>(), Some(&-1.0));
+    let array: Array = iter.next_as().unwrap();
+    assert_eq!(array.as_slice::<f64>(), Some(&doubles[..]));
+
+    // The same values packed put the u64 at an odd offset
+    let mut packed = aligned_buf(encoded_len(&values));
+    let buf = unsafe {
+        std::slice::from_raw_parts_mut(packed.as_mut_ptr() as *mut u8, encoded_len(&values))
+    };
+    ReferencedValues(&values).encode(buf);
+    let mut iter = validate_sos(buf).unwrap();
+    iter.next();
+    assert_eq!(iter.next_ref::<u64>(), None);
+    assert_eq!(iter.next(), Some(Value::UInt64(0x0102030405060708)));
+}
+
+#[test]
+fn aligned_padding() {
+    let halves = [1u16, 2];
+    let values = [
+        Value::UInt8(1),
+        Value::UInt32(2),
+        Array::new(&halves[..]).into(),
+    ];
+    let aligned = AlignedValues(&values);
+    let mut buf = vec![0; aligned.encoded_len()];
+    aligned.encode(&mut buf[..]);
+    assert!(validate_sos(&buf).is_ok());
+
+    // Type of the u32 at 14, padding to 16, the u32, then the array type at 20, padding to 24,
+    // length, padding to 32, then the element type and padding to 34
+    assert_eq!(buf[14], 2);
+    assert_eq!(&buf[16..20], &[2, 0, 0, 0]);
+    assert_eq!(&buf[24..28], &[6, 0, 0, 0]);
+    assert_eq!(buf.len(), 38);
+
+    for &at in &[15, 21, 29, 33] {
+        let mut bad = buf.clone();
+        bad[at] = 1;
+        assert_eq!(
+            validate_sos(&bad),
+            Err(DecodeError {
+                kind: DecodeErrorKind::BadPadding,
+                offset: at,
+            })
+        );
+    }
+
+    let mut bad = buf.clone();
+    bad[3] = 2;
+    assert_eq!(
+        validate_sos(&bad).unwrap_err().kind,
+        DecodeErrorKind::UnknownFlags
+    );
+}
+
+#[test]
+fn packed_array() {
+    let doubles = [1.5f64, -2.0, 1e300];
+    let buf = encode_vec(&[Value::UInt8(1), Array::new(&doubles[..]).into()]);
+    let mut iter = validate_sos(&buf).unwrap();
+    assert_eq!(iter.next(), Some(Value::UInt8(1)));
+    let array: Array = iter.next_as().unwrap();
+    assert_eq!(array.elem_type(), ArrayType::Double);
+    assert_eq!(array.len(), 3);
+    assert_eq!(array.get::<f64>(2), Some(1e300));
+    assert_eq!(array.get::<u64>(0), None);
+    assert_eq!(
+        array.iter::<f64>().unwrap().collect::<Vec<_>>(),
+        doubles.to_vec()
+    );
+
+    // The elements start 18 bytes into the buffer, which is too unaligned to borrow as f64s
+    let start = array.as_bytes().as_ptr() as usize - buf.as_ptr() as usize;
+    assert_eq!(start, SOS_HEADER_SIZE + 2 + 6);
+    assert_eq!(array.as_slice::<f64>(), None);
+
+    let bytes = [1u8, 2, 3];
+    let buf = encode_vec(&[Array::new(&bytes[..]).into()]);
+    let array: Array = validate_sos(&buf).unwrap().next_as().unwrap();
+    assert_eq!(array.as_slice::<u8>(), Some(&bytes[..]));
+    assert_eq!(OwnedArray::from(array), OwnedArray::from_slice(&bytes[..]));
+}
+
+#[test]
+fn map_pairs() {
+    let pairs = [
+        (Value::String("one"), Value::UInt64(1)),
+        (Value::Bool(false), Value::Unit),
+    ];
+    let buf = encode_vec(&[Value::MapIn(&pairs)]);
+    let mut iter = validate_sos(&buf).unwrap();
+    let map: MapIter = iter.next_as().unwrap();
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.collect::<Vec<_>>(), pairs.to_vec());
+    let decoded = decode_sos(&buf, false).unwrap().collect::<Vec<_>>();
+    assert_eq!(decoded, vec![Value::MapVec(pairs.to_vec())]);
+}
+
+#[test]
+fn decode_limits() {
+    let innermost = [Value::Int32(1)];
+    let inner = [Value::EmbeddedIn(ReferencedValues(&innermost))];
+    let buf = encode_vec(&[Value::EmbeddedIn(ReferencedValues(&inner)), Value::UInt8(2)]);
+    let limits = DecodeLimits {
+        max_depth: 2,
+        max_values: 4,
+        max_bytes: buf.len(),
+    };
+    assert!(validate_sos_limited(&buf, limits).is_ok());
+    assert_eq!(
+        decode_sos_limited(&buf, false, limits).map(|i| i.count()),
+        Some(2)
+    );
+
+    let too_deep = DecodeLimits {
+        max_depth: 1,
+        ..limits
+    };
+    let err = validate_sos_limited(&buf, too_deep).unwrap_err();
+    assert_eq!(err.kind, DecodeErrorKind::TooDeep);
+    assert_eq!(JustError::from(err).fault().code, ErrorCode::TOO_LARGE);
+    assert_eq!(
+        decode_sos_limited(&buf, false, too_deep).unwrap().next(),
+        None
+    );
+
+    let too_many = DecodeLimits {
+        max_values: 3,
+        ..limits
+    };
+    assert_eq!(
+        validate_sos_limited(&buf, too_many).unwrap_err().kind,
+        DecodeErrorKind::TooManyValues
+    );
+    // Decoding stops at the value that exceeds the limit
+    assert_eq!(
+        decode_sos_limited(&buf, false, too_many).unwrap().count(),
+        1
+    );
+    let fewer = DecodeLimits {
+        max_values: 2,
+        ..limits
+    };
+    assert_eq!(decode_sos_limited(&buf, false, fewer).unwrap().next(), None);
+    // Lazily decoded values count against the limit as they are reached
+    let mut lazy = decode_sos_limited(&buf, true, fewer).unwrap();
+    match lazy.next() {
+        Some(Value::EmbeddedOut(mut inner)) => assert_eq!(inner.next(), None),
+        other => panic!("expected an embedded value, got {:?}", other),
+    }
+
+    let too_large = DecodeLimits {
+        max_bytes: buf.len() - 1,
+        ..limits
+    };
+    assert_eq!(
+        validate_sos_limited(&buf, too_large),
+        Err(DecodeError {
+            kind: DecodeErrorKind::TooLarge,
+            offset: 8,
+        })
+    );
+    assert!(decode_sos_limited(&buf, true, too_large).is_none());
+    assert!(validate_sos_limited(&buf, DecodeLimits::UNLIMITED).is_ok());
+}
+
+#[test]
+fn validate_new_types() {
+    let buf = encode_vec(&[Value::Bool(true)]);
+    let mut bad = buf.clone();
+    bad[SOS_HEADER_SIZE + 1] = 2;
+    assert_eq!(
+        validate_sos(&bad).unwrap_err().kind,
+        DecodeErrorKind::InvalidBool
+    );
+    assert_eq!(decode_sos(&bad, false).unwrap().next(), None);
+
+    let halves = [1u16, 2];
+    let buf = encode_vec(&[Array::new(&halves[..]).into()]);
+    let mut bad = buf.clone();
+    bad[SOS_HEADER_SIZE + 5] = 12;
+    assert_eq!(
+        validate_sos(&bad).unwrap_err().kind,
+        DecodeErrorKind::BadArray
+    );
+    let mut bad = buf.clone();
+    bad[SOS_HEADER_SIZE + 5] = 3;
+    assert_eq!(
+        validate_sos(&bad).unwrap_err().kind,
+        DecodeErrorKind::BadArray
+    );
+
+    // A map with three values
+    let inner = [Value::Unit, Value::Unit, Value::Unit];
+    let mut buf = encode_vec(&[Value::EmbeddedIn(ReferencedValues(&inner))]);
+    buf[SOS_HEADER_SIZE] = 19;
+    assert_eq!(
+        validate_sos(&buf).unwrap_err().kind,
+        DecodeErrorKind::OddMap
+    );
+}
+
+#[test]
+fn fault_cause_chain() {
+    let transport = Fault::new(ErrorCode::TRANSPORT, "host went away");
+    let fault = Fault::new(ErrorCode::user(3), "lookup failed")
+        .with_origin(Function {
+            module: "db",
+            name: "get",
+        })
+        .with_cause(&transport);
+    let buf = encode_vec(&[Value::Fault(fault.clone())]);
+
+    let decoded: Fault = validate_sos(&buf).unwrap().next_as().unwrap();
+    assert_eq!(decoded, fault);
+    assert!(decoded.code.is_user());
+    assert_eq!(decoded.origin.as_ref().unwrap().name, "get");
+    let cause = decoded.cause().unwrap();
+    assert_eq!(cause.code, ErrorCode::TRANSPORT);
+    assert_eq!(cause.cause(), None);
+    assert_eq!(OwnedFault::from(decoded.clone()).borrow(), decoded);
+
+    // Causes are nested buffers, decoding them stays within the limits of the outermost one
+    let outer = Fault::new(ErrorCode::UNKNOWN, "outer").with_cause(&fault);
+    let buf = encode_vec(&[Value::Fault(outer)]);
+    let decode = |max_depth| -> Fault {
+        let limits = DecodeLimits {
+            max_depth,
+            ..DecodeLimits::default()
+        };
+        decode_sos_limited(&buf, true, limits)
+            .unwrap()
+            .next_as()
+            .unwrap()
+    };
+    let transport = decode(3).cause().unwrap().cause().unwrap();
+    assert_eq!(transport.code, ErrorCode::TRANSPORT);
+    // The cause of "lookup failed" is past the limit, so "lookup failed" doesn't decode either
+    assert_eq!(decode(2).cause(), None);
+
+    // A fault that is missing its cause slot
+    let short = [Value::UInt32(0), Value::Unit, Value::String("oops")];
+    let mut buf = encode_vec(&[Value::EmbeddedIn(ReferencedValues(&short))]);
+    buf[SOS_HEADER_SIZE] = 20;
+    assert_eq!(
+        validate_sos(&buf).unwrap_err().kind,
+        DecodeErrorKind::MalformedFault
+    );
+    assert_eq!(decode_sos(&buf, true).unwrap().next(), None);
+}
+
+#[test]
+fn just_error_fault() {
+    let function = Function {
+        module: "call",
+        name: "print",
+    };
+    let err = JustError::with_code(ErrorCode::NO_SUCH_FUNCTION, "Function not found")
+        .with_origin(function.clone())
+        .with_origin(Function {
+            module: "outer",
+            name: "caller",
+        });
+    assert_eq!(err.fault().origin, Some(function));
+    let buf = encode_vec(&err.values());
+    match decode_sos(&buf, true).unwrap().next() {
+        Some(Value::Fault(fault)) => {
+            assert_eq!(fault.code, ErrorCode::NO_SUCH_FUNCTION);
+            assert_eq!(fault.message, "Function not found");
+        }
+        other => panic!("expected a fault, got {:?}", other),
+    }
+}
+
+/// A point in time as seconds and nanoseconds, the way a module would register one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Timestamp {
+    secs: u64,
+    nanos: u32,
+}
+
+impl<'a> ExtensionType<'a> for Timestamp {
+    const TYPE_ID: u8 = 3;
+    const NAME: &'static str = "Timestamp";
+
+    fn encoded_len(&self) -> usize {
+        12
+    }
+
+    fn encode(&self, buf: &mut [u8]) {
+        LittleEndian::write_u64(&mut buf[..8], self.secs);
+        LittleEndian::write_u32(&mut buf[8..], self.nanos);
+    }
+
+    fn decode(data: &'a [u8]) -> Option<Self> {
+        if data.len() != 12 {
+            return None;
+        }
+        Some(Timestamp {
+            secs: LittleEndian::read_u64(&data[..8]),
+            nanos: LittleEndian::read_u32(&data[8..]),
+        })
+    }
+}
+
+#[test]
+fn extension_types() {
+    let time = Timestamp {
+        secs: 1_500_000_000,
+        nanos: 7,
+    };
+    let mut data = [0; 12];
+    let ext = Extension::encode(&time, &mut data);
+    assert_eq!(ext.type_id(), 3);
+    assert_eq!(Value::Extension(ext).tag(), SOS_EXTENSION_TAG + 3);
+
+    for &layout in &[Layout::Packed, Layout::Aligned] {
+        let values = [
+            Value::Extension(ext),
+            Value::Extension(Extension::new(0x7f, b"?")),
+        ];
+        let buf = match layout {
+            Layout::Packed => encode_vec(&values),
+            Layout::Aligned => {
+                let aligned = AlignedValues(&values);
+                let mut buf = vec![0; aligned.encoded_len()];
+                aligned.encode(&mut buf);
+                buf
+            }
+        };
+        assert_eq!(buf[SOS_HEADER_SIZE], SOS_EXTENSION_TAG + 3);
+
+        // The same bytes come out of the streaming encoder
+        let mut streamed = vec![0; buf.len()];
+        let mut encoder = Encoder::with_layout(&mut streamed, layout).unwrap();
+        encoder.push_extension(&time).unwrap();
+        encoder.push(Extension::new(0x7f, b"?")).unwrap();
+        assert_eq!(encoder.finish(), Ok(buf.len()));
+        assert_eq!(streamed, buf);
+
+        let mut iter = validate_sos(&buf).unwrap();
+        assert_eq!(iter.next_as::<Timestamp>(), Ok(time));
+        // A type this program has no registration for keeps its id and data
+        let unknown: Extension = iter.clone().next_as().unwrap();
+        assert_eq!((unknown.type_id(), unknown.data()), (0x7f, &b"?"[..]));
+        assert_eq!(
+            iter.next_as::<Timestamp>(),
+            Err(ArgError {
+                index: 1,
+                expected: "Timestamp",
+                found: Some("Extension"),
+            })
+        );
+        assert_eq!(unknown.decode::<Timestamp>(), None);
+    }
+
+    let owned = OwnedValue::Extension(OwnedExtension::encode(&time));
+    let buf = encode_vec(&[owned.borrow()]);
+    assert_eq!(buf, encode_vec(&[Value::Extension(ext)]));
+    assert_eq!(
+        decode_sos(&buf, false).unwrap().next(),
+        Some(Value::Extension(ext))
+    );
+}
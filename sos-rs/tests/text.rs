@@ -0,0 +1,159 @@
+extern crate sos;
+use sos::*;
+
+fn encode_vec<S: SOS>(values: &S) -> Vec<u8> {
+    let mut buf = vec![0; values.encoded_len()];
+    values.encode(&mut buf[..]);
+    buf
+}
+
+#[test]
+fn print() {
+    let ints = [1i32, 2];
+    let inner = [Value::Bool(true), Value::Unit];
+    let pairs = [(Value::String("key"), Value::Int8(-1))];
+    let values = [
+        ("call", "print").into(),
+        "hi \"there\"\n".into(),
+        42u64.into(),
+        Array::new(&ints[..]).into(),
+        Array::new(&[0u16; 0][..]).into(),
+        Value::Opaque(b"a\0\xff"),
+        Value::EmbeddedIn(ReferencedValues(&inner)),
+        Value::MapIn(&pairs),
+        Value::MapIn(&[]),
+        1.5f32.into(),
+        Value::Error("oops"),
+    ];
+    assert_eq!(
+        ReferencedValues(&values).to_string(),
+        r#"("call", "print"), "hi \"there\"\n", 42u64, [1i32, 2i32], []u16, b"a\0\xff", {true, ()}, {"key": -1i8}, {:}, 1.5f32, error("oops")"#
+    );
+
+    // Decoded values print the same as the ones they were encoded from
+    let buf = encode_vec(&ReferencedValues(&values));
+    let decoded = decode_sos(&buf, true).unwrap();
+    assert_eq!(decoded.to_string(), ReferencedValues(&values).to_string());
+    assert_eq!(
+        EncodedValues::from(&buf[..]).to_string(),
+        decoded.to_string()
+    );
+    assert_eq!(
+        EncodedValues::from(&buf[1..]).to_string(),
+        "<malformed SOS>"
+    );
+
+    let fault =
+        Fault::new(ErrorCode::NO_SUCH_FUNCTION, "Function not found").with_origin(Function {
+            module: "call",
+            name: "print",
+        });
+    assert_eq!(
+        Value::Fault(fault).to_string(),
+        r#"fault(0x201, ("call", "print"), "Function not found", ())"#
+    );
+}
+
+#[test]
+fn parse() {
+    let values = parse_values(
+        r#" ("call","print"), "hi", 42u64, [1i32, 2i32], 7, -2.5, NaNf32, {}, {1u8: "one",},
+            b"\x00a", []f64, fault(0x301, (), "outer", fault(1024, ("a", "b"), "inner", ())) "#,
+    )
+    .unwrap();
+    assert_eq!(values.len(), 12);
+    assert_eq!(
+        values[0],
+        OwnedValue::Function(OwnedFunction::new("call", "print"))
+    );
+    assert_eq!(values[2], OwnedValue::UInt64(42));
+    assert_eq!(
+        values[3],
+        OwnedValue::Array(OwnedArray::from_slice(&[1i32, 2][..]))
+    );
+    assert_eq!(values[4], OwnedValue::Int32(7));
+    assert_eq!(values[5], OwnedValue::Double(-2.5));
+    match values[6] {
+        OwnedValue::Float(f) => assert!(f.is_nan()),
+        ref other => panic!("expected a float, got {:?}", other),
+    }
+    assert_eq!(values[7], OwnedValue::Embedded(vec![]));
+    assert_eq!(
+        values[8],
+        OwnedValue::Map(vec![(
+            OwnedValue::UInt8(1),
+            OwnedValue::String("one".into())
+        )])
+    );
+    assert_eq!(values[9], OwnedValue::Opaque(vec![0, b'a']));
+    assert_eq!(
+        values[10],
+        OwnedValue::Array(OwnedArray::from_slice::<f64>(&[]))
+    );
+    match values[11] {
+        OwnedValue::Fault(ref fault) => {
+            assert_eq!(fault.code, ErrorCode::TOO_LARGE);
+            assert_eq!(fault.origin, None);
+            let cause = fault.cause.as_ref().unwrap();
+            assert_eq!(cause.code, ErrorCode::TRANSPORT);
+            assert_eq!(cause.origin, Some(OwnedFunction::new("a", "b")));
+        }
+        ref other => panic!("expected a fault, got {:?}", other),
+    }
+
+    let buf = parse_sos(r#"("call", "print"), "hello""#).unwrap();
+    assert_eq!(buf, encode_vec(&sos![("call", "print"), "hello"]));
+    assert_eq!(parse_sos("").unwrap(), encode_vec(&sos![]));
+
+    let unit: OwnedValue = "()".parse().unwrap();
+    assert_eq!(unit, OwnedValue::Unit);
+
+    let ext = OwnedValue::Extension(OwnedExtension::new(5, vec![1, b'z']));
+    assert_eq!(ext.borrow().to_string(), r#"ext(5, b"\x01z")"#);
+    assert_eq!(ext.borrow().to_string().parse(), Ok(ext));
+}
+
+#[test]
+fn parse_errors() {
+    let error = |text: &str| parse_values(text).unwrap_err();
+    assert_eq!(
+        error("1i32, ["),
+        ParseError {
+            kind: ParseErrorKind::UnexpectedEnd,
+            offset: 7,
+        }
+    );
+    assert_eq!(error("1i32 2i32").kind, ParseErrorKind::UnexpectedChar);
+    assert_eq!(
+        error("1i32, 300u8"),
+        ParseError {
+            kind: ParseErrorKind::BadNumber,
+            offset: 6,
+        }
+    );
+    assert_eq!(error("[]").kind, ParseErrorKind::BadNumber);
+    assert_eq!(
+        error("[1i32, 2u32]"),
+        ParseError {
+            kind: ParseErrorKind::BadArrayElement,
+            offset: 7,
+        }
+    );
+    assert_eq!(error(r#""\q""#).kind, ParseErrorKind::BadEscape);
+    assert_eq!(error(r#""open"#).kind, ParseErrorKind::UnexpectedEnd);
+    assert_eq!(
+        error(r#"fault(0, (), "x", 1)"#).kind,
+        ParseErrorKind::BadFault
+    );
+    assert_eq!(error(&"{".repeat(100)).kind, ParseErrorKind::TooDeep);
+    assert_eq!(error(&"[".repeat(1_000_000)).kind, ParseErrorKind::TooDeep);
+    assert_eq!(
+        error(r#"ext(128, b"")"#),
+        ParseError {
+            kind: ParseErrorKind::BadNumber,
+            offset: 4,
+        }
+    );
+    assert_eq!(error(r#"ext(1, "x")"#).kind, ParseErrorKind::UnexpectedChar);
+    assert!("1i32, 2i32".parse::<OwnedValue>().is_err());
+}
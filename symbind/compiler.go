@@ -29,6 +29,7 @@ type SymbolTable []SymbolTableEntry
 
 type ABI int
 type Visibility int
+type Kind int
 
 const (
 	C ABI = iota
@@ -40,11 +41,19 @@ const (
 	Private
 )
 
+// A pure function always returns the same values for the same arguments, so the kernel may
+// answer calls to it from earlier results.
+const (
+	Procedural Kind = iota
+	Pure
+)
+
 type SymbolTableEntry struct {
 	Name       string
 	Offset     uint64
 	ABI        ABI
 	Visibility Visibility
+	Kind       Kind
 }
 
 func (this Manifest) Serialize() []byte {
@@ -93,6 +102,7 @@ var (
 	InjectManifest bool
 	StdPath        string
 	PassPath       string
+	PureFunctions  = map[string]bool{}
 )
 
 func replaceExtension(filename string, extension string) string {
@@ -149,8 +159,19 @@ func injectManifest(binary *os.File, manifest *Manifest) error {
 	return nil
 }
 
+func kindOf(name string) Kind {
+	if PureFunctions[name] {
+		return Pure
+	}
+	return Procedural
+}
+
 func parseAnnotationPass(data []byte) (SymbolTable, error) {
 	table := SymbolTable{}
+	// A function annotated both public and pure is reported once for each
+	index := map[string]int{}
+	// Pure only says how a function is called, it takes a visibility to be in the table at all
+	pure := map[string]bool{}
 
 	lines := strings.Split(string(data), "\n")
 
@@ -163,20 +184,35 @@ func parseAnnotationPass(data []byte) (SymbolTable, error) {
 		if len(parts) != 2 {
 			return table, fmt.Errorf("Annotation pass returned data in invalid format - lines")
 		}
-		var vis Visibility
 
+		var visibility Visibility
 		// remove the NULL charachter put there by C++
 		switch parts[1][:len(parts[1])-1] {
 		case "public":
-			vis = Public
+			visibility = Public
 		case "private":
-			vis = Private
+			visibility = Private
+		case "pure":
+			pure[parts[0]] = true
+			continue
 		default:
 			log.Println(parts, len(parts[1]))
 			return table, fmt.Errorf("Annotation pass returned data in invalid format - entries")
 		}
 
-		table = append(table, SymbolTableEntry{Name: parts[0], ABI: SOS, Visibility: vis})
+		i, ok := index[parts[0]]
+		if !ok {
+			i = len(table)
+			index[parts[0]] = i
+			table = append(table, SymbolTableEntry{Name: parts[0], ABI: SOS, Kind: kindOf(parts[0])})
+		}
+		table[i].Visibility = visibility
+	}
+
+	for i := range table {
+		if pure[table[i].Name] {
+			table[i].Kind = Pure
+		}
 	}
 	return table, nil
 }
@@ -389,7 +425,7 @@ func elfSymbols(binary *os.File) error {
 			}
 		*/
 		if elf.ST_BIND(sym.Info) == elf.STB_GLOBAL && elf.ST_TYPE(sym.Info) == elf.STT_FUNC {
-			symtab = append(symtab, SymbolTableEntry{Name: sym.Name, Offset: sym.Value, ABI: C, Visibility: Public})
+			symtab = append(symtab, SymbolTableEntry{Name: sym.Name, Offset: sym.Value, ABI: C, Visibility: Public, Kind: kindOf(sym.Name)})
 		}
 	}
 
@@ -404,6 +440,10 @@ func compileMain(c *cli.Context) error {
 	inputFiles := c.Args()
 	var err error
 
+	for _, name := range c.StringSlice("pure") {
+		PureFunctions[name] = true
+	}
+
 	LinkerFile, err = ioutil.TempFile("", "faastr.lds")
 	if err != nil {
 		return fmt.Errorf("Could not create temporary linker script")
@@ -477,6 +517,10 @@ func main() {
 			Value:       "stdlib",
 			Destination: &StdPath,
 		},
+		cli.StringSliceFlag{
+			Name:  "pure",
+			Usage: "mark the named function as pure, can be repeated",
+		},
 		cli.StringFlag{
 			Name:        "pass-path, p",
 			Value:       "stage2/passes",
@@ -0,0 +1,64 @@
+#include <sos.h>
+
+long sys_return(const uint8_t *ptr, long length) {
+  long r;
+  asm("mov $0x6, %%rax;"
+      "int $0x80"
+      : "=a"(r)
+      : "b"(ptr), "c"(length)
+      );
+  return r;
+}
+
+// Encodes a UInt8, a UInt64 and a Double into buf, the UInt8 puts the other
+// two off an 8 byte boundary unless the layout pads them.
+static uintptr_t encode(uint64_t *buf, uintptr_t size, uint8_t flags) {
+  SosEncoder enc;
+  uintptr_t len = 0;
+  sos_encoder_init(&enc, (uint8_t *)buf, size, flags);
+  sos_push_u8(&enc, 1);
+  sos_push_u64(&enc, 0x0123456789abcdefULL);
+  sos_push_f64(&enc, 2.5);
+  sos_finish(&enc, &len);
+  return len;
+}
+
+// Reads the values back with GetValue, true if they all come out right.
+static int decode(const uint64_t *buf, uintptr_t len) {
+  SosDecoder dec;
+  if (sos_decoder_init(&dec, (const uint8_t *)buf, len) != SOS_STATUS_OK)
+    return 0;
+  return GetValue(&dec, UInt8) == 1 &&
+         GetValue(&dec, UInt64) == 0x0123456789abcdefULL &&
+         GetValue(&dec, Double) == 2.5 &&
+         sos_peek_type(&dec) == SOS_TYPE_INVALID;
+}
+
+// True if the UInt64 and the Double of an aligned buffer are handed out in
+// place on an 8 byte boundary, so GetValue never loads them unaligned.
+static int in_place(const uint64_t *buf, uintptr_t len) {
+  SosDecoder dec;
+  const uint64_t *u = NULL;
+  const double *d = NULL;
+  return sos_decoder_init(&dec, (const uint8_t *)buf, len) == SOS_STATUS_OK &&
+         sos_skip(&dec) == SOS_STATUS_OK &&
+         sos_ref_u64(&dec, &u) == SOS_STATUS_OK &&
+         sos_ref_f64(&dec, &d) == SOS_STATUS_OK &&
+         (uintptr_t)u % 8 == 0 && (uintptr_t)d % 8 == 0;
+}
+
+void aligned() {
+  uint64_t buf[64] = {0};
+  uint64_t packed[64] = {0};
+  uint8_t out[64] = {0};
+  SosEncoder enc;
+  uintptr_t len = encode(buf, sizeof(buf), SOS_FLAG_ALIGNED);
+  uintptr_t packed_len = encode(packed, sizeof(packed), 0);
+  uintptr_t out_len = 0;
+
+  sos_encoder_init(&enc, out, sizeof(out), 0);
+  sos_push_bool(&enc, in_place(buf, len) && decode(buf, len) &&
+                          decode(packed, packed_len));
+  sos_finish(&enc, &out_len);
+  sys_return(out, out_len);
+}
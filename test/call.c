@@ -6,7 +6,7 @@ void sys_exit() {
       "int $0x80");
 }
 
-long sys_return(Values ptr, long length) {
+long sys_return(const uint8_t *ptr, long length) {
   long r;
   asm("mov $0x6, %%rax;"
       "int $0x80"
@@ -16,60 +16,69 @@ long sys_return(Values ptr, long length) {
   return r;
 }
 
-Values sys_write(Values ptr, long len) {
+const uint8_t *sys_write(const uint8_t *ptr, long len) {
   long r;
   asm("mov $0x2, %%rax;"
       "int $0x80"
       : "=a"(r)
       : "b"(ptr), "c"(len));
-  return (Values)r;
+  return (const uint8_t *)r;
 }
 
-Values sys_fuse(Values ptr, long length) {
+const uint8_t *sys_fuse(const uint8_t *ptr, long length) {
   long r;
   asm("mov $0x3, %%rax;"
       "int $0x80"
       : "=a"(r)
       : "b"(ptr), "c"(length)
       );
-  return (Values)r;
+  return (const uint8_t *)r;
 }
 
-long sys_cast(Values ptr, long length) {
+long sys_cast(const uint8_t *ptr, long length) {
   long r;
   asm("mov $0x4, %%rax;"
       "int $0x80"
-      : "=a"(r)
+      :"=a"(r)
       :"b"(ptr), "c"(length));
   return r;
 }
 
-void print(Values args) {
-  char buf[4096] = {0};
-  Values vals = (Values)buf;
-  const char* input = GetString(args);
-  SetString(vals, input);
-  sys_write(vals, 4096);
-  sys_return(vals, 4096);
+// Encodes the string args starts with into buf, returning its length.
+static uintptr_t echo(const uint8_t *args, uint8_t *buf, uintptr_t size) {
+  SosDecoder dec;
+  SosEncoder enc;
+  const char *input = "";
+  uintptr_t len = 0;
+  if (sos_decoder_init(&dec, args, sos_encoded_len(args)) == SOS_STATUS_OK)
+    sos_next_string(&dec, &input, NULL);
+  sos_encoder_init(&enc, buf, size, 0);
+  sos_push_string(&enc, input);
+  sos_finish(&enc, &len);
+  return len;
 }
 
-void passthrough(Values args) {
-  char buf[4096] = {0};
-  Values vals = (Values)buf;
-  const char* input = GetString(args);
-  SetString(vals, input);
-  sys_return(vals, 4096);
+void print(const uint8_t *args) {
+  uint8_t buf[4096] = {0};
+  uintptr_t len = echo(args, buf, sizeof(buf));
+  sys_write(buf, len);
+  sys_return(buf, len);
 }
 
-void call() {
-  const char *hello = "calling";
-  char buf[4096] = {0};
-  Values vals = (Values)buf;
-  SetFunction(vals, "call", "passthrough");
-  SetString(vals, hello);
+void passthrough(const uint8_t *args) {
+  uint8_t buf[4096] = {0};
+  sys_return(buf, echo(args, buf, sizeof(buf)));
+}
 
-  Values pass_out = sys_fuse(vals, 4096);
-  SetString(vals, GetString(pass_out)); 
+void call() {
+  uint8_t buf[4096] = {0};
+  SosEncoder enc;
+  uintptr_t len = 0;
+  sos_encoder_init(&enc, buf, sizeof(buf), 0);
+  sos_push_function(&enc, "call", "passthrough");
+  sos_push_string(&enc, "calling");
+  sos_finish(&enc, &len);
 
-  sys_return(pass_out, 4096);
+  const uint8_t *pass_out = sys_fuse(buf, len);
+  sys_return(pass_out, sos_encoded_len(pass_out));
 }